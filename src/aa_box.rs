@@ -0,0 +1,127 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::hittable::Interval;
+use crate::ray::Ray;
+use crate::three_d_tree::Axis;
+use crate::vec3::Vec3;
+
+// Box with its faces aligned to the axes
+pub struct AaBox {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+impl Hittable for AaBox {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        // Where the ray enters and leaves the box, the ray can start inside of it
+        let (t_enter, t_exit) =
+            self.get_bounds()
+                .intersection(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+        let t = if t_enter >= t_min {
+            t_enter
+        } else if t_exit >= t_min {
+            t_exit
+        } else {
+            return None;
+        };
+        if t > t_max {
+            return None;
+        }
+        Some(self.hit_at(ray, t))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        match self
+            .get_bounds()
+            .intersection(ray, f64::NEG_INFINITY, f64::INFINITY)
+        {
+            // Rays that only touch an edge or a corner don't go through the box
+            Some((t_enter, t_exit)) if t_enter < t_exit => vec![Interval {
+                enter: self.hit_at(ray, t_enter),
+                exit: self.hit_at(ray, t_exit),
+            }],
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        BoundingBox::new(
+            self.min.x, self.max.x, self.min.y, self.max.y, self.min.z, self.max.z,
+        )
+    }
+}
+impl AaBox {
+    #[allow(dead_code)]
+    pub fn new(min: Vec3, max: Vec3) -> AaBox {
+        AaBox { min, max }
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+        let p = ray.at(t);
+        // The face that was hit is the one along the axis where the point is furthest out relative to the size of the box
+        let center = (self.min + self.max) * 0.5;
+        let half_size = (self.max - self.min) * 0.5;
+        let local = p - center;
+        let relative = |axis: &Axis| {
+            // Flat boxes can only be hit on their flat sides
+            let half_size = half_size.get_axis(axis);
+            if half_size > 0.0 {
+                local.get_axis(axis) / half_size
+            } else {
+                f64::INFINITY.copysign(local.get_axis(axis))
+            }
+        };
+        let axis = [Axis::X, Axis::Y, Axis::Z]
+            .into_iter()
+            .max_by(|a, b| relative(a).abs().total_cmp(&relative(b).abs()))
+            .unwrap();
+        let sign = relative(&axis).signum();
+        // Every face is mapped onto the whole texture
+        let (outward_normal, u_dir, v_dir) = match axis {
+            Axis::X => (
+                Vec3::new(sign, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+            Axis::Y => (
+                Vec3::new(0.0, sign, 0.0),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, 1.0),
+            ),
+            Axis::Z => (
+                Vec3::new(0.0, 0.0, sign),
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(0.0, 1.0, 0.0),
+            ),
+        };
+        let u = (p - self.min).dot(&u_dir) / (self.max - self.min).dot(&u_dir);
+        let v = (p - self.min).dot(&v_dir) / (self.max - self.min).dot(&v_dir);
+        let handedness = if outward_normal.cross(&u_dir).dot(&v_dir) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        Hit::new(p, outward_normal, t, ray)
+            .with_uv(u, v)
+            .with_tangent(u_dir, handedness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_box() {
+        // No thickness along y, it's hit on the top from above
+        let flat = AaBox::new(Vec3::new(-1.0, 0.0, -1.0), Vec3::new(1.0, 0.0, 1.0));
+        let ray = Ray {
+            origin: Vec3::new(0.3, 2.0, 0.1),
+            direction: Vec3::new(0.0, -1.0, 0.0),
+        };
+        let hit = flat.hit(&ray, 0.0, 10.0).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-12);
+        assert_eq!((hit.normal.x, hit.normal.y, hit.normal.z), (0.0, 1.0, 0.0));
+        assert!(hit.front_face);
+    }
+}
@@ -0,0 +1,13 @@
+use crate::hittable::Hit;
+use crate::texture::Texture;
+
+// Makes parts of a surface invisible, e.g. for leaves that are drawn onto flat cards
+pub struct AlphaMask {
+    pub opacity: Box<dyn Texture>,
+    pub cutoff: f64, // Hits with a lower opacity are ignored
+}
+impl AlphaMask {
+    pub fn is_opaque(&self, hit: &Hit) -> bool {
+        self.opacity.scalar(hit) >= self.cutoff
+    }
+}
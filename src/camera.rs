@@ -2,6 +2,7 @@ use crate::ray::Ray;
 use crate::util::degrees_to_radians;
 use crate::vec3::Vec3;
 
+#[allow(dead_code)]
 pub struct Camera {
     pub origin: Vec3,
     pub aspect_ratio: f64,
@@ -54,6 +55,17 @@ impl Camera {
             1.0,
         )
     }
+    pub fn with_orientation(mut self, forward: Vec3, up: Vec3) -> Camera {
+        // The camera looks along -z with y up by default, this turns it to look along forward instead
+        let w = -forward.unit_vector();
+        let u = up.cross(&w).unit_vector();
+        let v = w.cross(&u);
+        self.horizontal = u * self.viewport_width;
+        self.vertical = v * self.viewport_height;
+        self.lower_left_viewport_corner =
+            -self.horizontal / 2.0 - self.vertical / 2.0 - w * self.focal_length;
+        self
+    }
     pub fn get_ray(&self, horizontal_frac: f64, vertical_frac: f64) -> Ray {
         Ray {
             origin: self.origin,
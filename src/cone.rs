@@ -0,0 +1,117 @@
+use crate::cylinder::axis_bounds;
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::hittable::Interval;
+use crate::microfacet::Frame;
+use crate::polynomial::solve_quadratic;
+use crate::ray::Ray;
+use crate::util::PI;
+use crate::vec3::Vec3;
+
+// Cone with a base of the given radius and its apex at base + axis, optionally closed off by a disk at the base
+pub struct Cone {
+    pub base: Vec3,
+    pub axis: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+}
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t, on_cap) = self
+            .crossings(ray)
+            .into_iter()
+            .find(|(t, _)| *t >= t_min && *t <= t_max)?;
+        Some(self.hit_at(ray, t, on_cap))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // Only the capped cone is closed, it's convex so the ray is inside between the first and last crossing
+        let crossings = self.crossings(ray);
+        match (crossings.first(), crossings.last()) {
+            (Some(&(enter, enter_cap)), Some(&(exit, exit_cap))) if self.capped && enter < exit => {
+                vec![Interval {
+                    enter: self.hit_at(ray, enter, enter_cap),
+                    exit: self.hit_at(ray, exit, exit_cap),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        axis_bounds(self.base, self.base + self.axis, self.radius)
+    }
+}
+
+impl Cone {
+    #[allow(dead_code)]
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, capped: bool) -> Cone {
+        Cone {
+            base,
+            axis,
+            radius,
+            capped,
+        }
+    }
+
+    fn crossings(&self, ray: &Ray) -> Vec<(f64, bool)> {
+        // Every t where the line through the ray crosses the surface, sorted, with whether it's on the base
+        // Intersect in the local frame where the axis is z, the frame is orthonormal so t stays the same
+        // The surface is x² + y² = k² (h - z)² where k is the slope of the side
+        let height = self.axis.length();
+        let frame = Frame::new(self.axis / height);
+        let o = frame.to_local(ray.origin - self.base);
+        let d = frame.to_local(ray.direction);
+        let k = self.radius / height;
+        let k2 = k * k;
+
+        let mut crossings: Vec<(f64, bool)> = solve_quadratic(
+            d.x * d.x + d.y * d.y - k2 * d.z * d.z,
+            2.0 * (o.x * d.x + o.y * d.y + k2 * (height - o.z) * d.z),
+            o.x * o.x + o.y * o.y - k2 * (height - o.z) * (height - o.z),
+        )
+        .into_iter()
+        .filter(|t| (0.0..=height).contains(&(o.z + t * d.z)))
+        .map(|t| (t, false))
+        .collect();
+        if self.capped && d.z.abs() > 1e-12 {
+            let t = -o.z / d.z;
+            let p = o + d * t;
+            if p.x * p.x + p.y * p.y <= self.radius * self.radius {
+                crossings.push((t, true));
+            }
+        }
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        crossings
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64, on_cap: bool) -> Hit {
+        let height = self.axis.length();
+        let frame = Frame::new(self.axis / height);
+        let p = frame.to_local(ray.at(t) - self.base);
+        if on_cap {
+            // The base is mapped onto the square around it
+            let u = 0.5 + p.x / (2.0 * self.radius);
+            let v = 0.5 + p.y / (2.0 * self.radius);
+            Hit::new(ray.at(t), -frame.n, t, ray)
+                .with_uv(u, v)
+                .with_tangent(frame.t, -1.0)
+        } else {
+            // u goes around the axis and v from the base to the apex
+            let phi = p.y.atan2(p.x);
+            let normal = frame
+                .to_world(Vec3::new(phi.cos(), phi.sin(), self.radius / height))
+                .unit_vector();
+            let u = if phi < 0.0 { phi + 2.0 * PI } else { phi } / (2.0 * PI);
+            let hit = Hit::new(ray.at(t), normal, t, ray).with_uv(u, p.z / height);
+            // The tangent is undefined at the apex
+            let tangent = frame.to_world(Vec3::new(-p.y, p.x, 0.0));
+            if tangent.near_zero() {
+                hit
+            } else {
+                hit.with_tangent(tangent.unit_vector(), 1.0)
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::hittable::Interval;
+use crate::ray::Ray;
+
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    Difference, // Removes the right shape from the left one
+}
+impl CsgOperation {
+    fn is_inside(&self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// Constructive solid geometry, combines two closed shapes into a new one
+// Works on the intervals where rays are inside of the shapes, so nodes can be nested
+// Spheres, boxes, capped cylinders and cones and tori compute their intervals exactly, other shapes fall back to
+// pairing up their hits, which can turn the result inside out along rays that graze them
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<dyn Hittable>,
+    pub right: Box<dyn Hittable>,
+}
+impl Csg {
+    #[allow(dead_code)]
+    pub fn union(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg {
+            operation: CsgOperation::Union,
+            left,
+            right,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn intersection(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg {
+            operation: CsgOperation::Intersection,
+            left,
+            right,
+        }
+    }
+    pub fn difference(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg {
+            operation: CsgOperation::Difference,
+            left,
+            right,
+        }
+    }
+}
+
+fn flip(mut hit: Hit) -> Hit {
+    // The surface of the removed shape faces the other way, the normal already points against the ray
+    hit.front_face = !hit.front_face;
+    hit.tangent = hit
+        .tangent
+        .map(|(tangent, handedness)| (tangent, -handedness));
+    hit
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        self.intervals(ray)
+            .into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|hit| hit.t >= t_min && hit.t <= t_max)
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        let left = self.left.get_bounds();
+        let right = self.right.get_bounds();
+        match self.operation {
+            CsgOperation::Union => BoundingBox::new(
+                left.x_low.min(right.x_low),
+                left.x_high.max(right.x_high),
+                left.y_low.min(right.y_low),
+                left.y_high.max(right.y_high),
+                left.z_low.min(right.z_low),
+                left.z_high.max(right.z_high),
+            ),
+            CsgOperation::Intersection => BoundingBox::new(
+                left.x_low.max(right.x_low),
+                left.x_high
+                    .min(right.x_high)
+                    .max(left.x_low.max(right.x_low)),
+                left.y_low.max(right.y_low),
+                left.y_high
+                    .min(right.y_high)
+                    .max(left.y_low.max(right.y_low)),
+                left.z_low.max(right.z_low),
+                left.z_high
+                    .min(right.z_high)
+                    .max(left.z_low.max(right.z_low)),
+            ),
+            CsgOperation::Difference => left,
+        }
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // Walk along the boundaries of both shapes in order and keep track of which shapes the ray is in
+        // Every time that changes whether the ray is in the combined shape, the boundary is a surface of it
+        let mut events: Vec<(Hit, bool)> = Vec::new(); // Hit and whether it's on the left shape
+        for (shape, is_left) in [(&self.left, true), (&self.right, false)] {
+            for interval in shape.intervals(ray) {
+                events.push((interval.enter, is_left));
+                events.push((interval.exit, is_left));
+            }
+        }
+        events.sort_by(|a, b| a.0.t.partial_cmp(&b.0.t).unwrap());
+        let mut intervals = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<Hit> = None;
+        for (hit, is_left) in events {
+            let was_inside = self.operation.is_inside(in_left, in_right);
+            if is_left {
+                in_left = !in_left;
+            } else {
+                in_right = !in_right;
+            }
+            if was_inside == self.operation.is_inside(in_left, in_right) {
+                continue;
+            }
+            let hit = match self.operation {
+                CsgOperation::Difference if !is_left => flip(hit),
+                _ => hit,
+            };
+            match enter.take() {
+                None => enter = Some(hit),
+                Some(enter) => intervals.push(Interval { enter, exit: hit }),
+            }
+        }
+        intervals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aa_box::AaBox;
+    use crate::cylinder::Cylinder;
+    use crate::sphere::Sphere;
+    use crate::torus::Torus;
+    use crate::vec3::Vec3;
+
+    fn sphere(x: f64) -> Box<dyn Hittable> {
+        Box::new(Sphere {
+            center: Vec3::new(x, 0.0, 0.0),
+            radius: 1.0,
+        })
+    }
+
+    fn along_x(y: f64) -> Ray {
+        Ray {
+            origin: Vec3::new(-10.0, y, 0.0),
+            direction: Vec3::new(1.0, 0.0, 0.0),
+        }
+    }
+
+    fn bounds(shape: &dyn Hittable, ray: &Ray) -> Vec<(f64, f64)> {
+        // Intervals as x coordinates, the ray starts at x = -10
+        shape
+            .intervals(ray)
+            .iter()
+            .map(|i| (i.enter.t - 10.0, i.exit.t - 10.0))
+            .collect()
+    }
+
+    fn assert_bounds(actual: Vec<(f64, f64)>, expected: &[(f64, f64)]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for ((a0, a1), (e0, e1)) in actual.iter().zip(expected) {
+            assert!(
+                (a0 - e0).abs() < 1e-9 && (a1 - e1).abs() < 1e-9,
+                "{:?}",
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn union() {
+        let csg = Csg::union(sphere(-0.5), sphere(0.5));
+        assert_bounds(bounds(&csg, &along_x(0.0)), &[(-1.5, 1.5)]);
+        // Disjoint shapes stay separate
+        let csg = Csg::union(sphere(-2.0), sphere(2.0));
+        assert_bounds(bounds(&csg, &along_x(0.0)), &[(-3.0, -1.0), (1.0, 3.0)]);
+    }
+
+    #[test]
+    fn intersection() {
+        let csg = Csg::intersection(sphere(-0.5), sphere(0.5));
+        assert_bounds(bounds(&csg, &along_x(0.0)), &[(-0.5, 0.5)]);
+        let csg = Csg::intersection(sphere(-2.0), sphere(2.0));
+        assert!(csg.intervals(&along_x(0.0)).is_empty());
+        assert!(csg.hit(&along_x(0.0), 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn difference() {
+        let csg = Csg::difference(sphere(-0.5), sphere(0.5));
+        assert_bounds(bounds(&csg, &along_x(0.0)), &[(-1.5, -0.5)]);
+        // The exit is on the removed sphere, its normal still faces against the ray and the hit is from inside
+        let exit = &csg.intervals(&along_x(0.0))[0].exit;
+        assert!(exit.normal.x < 0.0 && !exit.front_face);
+        // Hits inside of the removed part are skipped
+        let hit = csg.hit(&along_x(0.0), 9.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 9.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn nested_shapes() {
+        // A box with a hole drilled through it along z, with a sphere added in the middle of the hole
+        let drilled = Csg::difference(
+            Box::new(AaBox {
+                min: Vec3::new(-2.0, -1.0, -1.0),
+                max: Vec3::new(2.0, 1.0, 1.0),
+            }),
+            Box::new(Cylinder {
+                base: Vec3::new(0.0, 0.0, -2.0),
+                axis: Vec3::new(0.0, 0.0, 4.0),
+                radius: 0.5,
+                capped: true,
+            }),
+        );
+        assert_bounds(bounds(&drilled, &along_x(0.0)), &[(-2.0, -0.5), (0.5, 2.0)]);
+        let filled = Csg::union(
+            Box::new(drilled),
+            Box::new(Sphere {
+                center: Vec3::new(0.0, 0.0, 0.0),
+                radius: 0.25,
+            }),
+        );
+        assert_bounds(
+            bounds(&filled, &along_x(0.0)),
+            &[(-2.0, -0.5), (-0.25, 0.25), (0.5, 2.0)],
+        );
+    }
+
+    #[test]
+    fn torus_and_grazing_rays() {
+        // The ray goes through both sides of the tube, a second torus is only touched by the ray
+        let torus = |y: f64| -> Box<dyn Hittable> {
+            Box::new(Torus {
+                center: Vec3::new(0.0, y, 0.0),
+                axis: Vec3::new(0.0, 0.0, 1.0),
+                major_radius: 2.0,
+                minor_radius: 0.5,
+            })
+        };
+        let csg = Csg::union(torus(0.0), torus(2.5));
+        assert_bounds(bounds(&csg, &along_x(0.0)), &[(-2.5, -1.5), (1.5, 2.5)]);
+        // The ray touches the sphere at the top, which must not flip inside and outside for the box behind it
+        let cube = AaBox {
+            min: Vec3::new(2.0, 0.0, -1.0),
+            max: Vec3::new(4.0, 2.0, 1.0),
+        };
+        let csg = Csg::union(sphere(0.0), Box::new(cube));
+        assert_bounds(bounds(&csg, &along_x(1.0)), &[(2.0, 4.0)]);
+    }
+}
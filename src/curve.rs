@@ -0,0 +1,293 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use std::fs;
+use std::path::Path;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy)]
+pub enum CurveType {
+    Ribbon,   // Flat strip that always faces the ray, good for thin hair
+    Cylinder, // Also faces the ray, but its normal bends around like on a tube
+}
+
+// Cubic Bézier curve with a width that changes linearly from start to end, used for hair, fur and grass
+// u goes along the curve and v across it, the tangent of hits points along the curve
+pub struct Curve {
+    pub control_points: [Vec3; 4],
+    pub width: (f64, f64),
+    pub curve_type: CurveType,
+}
+
+fn lerp(t: f64, a: Vec3, b: Vec3) -> Vec3 {
+    a * (1.0 - t) + b * t
+}
+
+fn subdivide_bezier(cp: &[Vec3; 4]) -> [Vec3; 7] {
+    // Splits the curve in the middle, the two halves share the middle point
+    [
+        cp[0],
+        (cp[0] + cp[1]) * 0.5,
+        (cp[0] + cp[1] * 2.0 + cp[2]) * 0.25,
+        (cp[0] + cp[1] * 3.0 + cp[2] * 3.0 + cp[3]) * 0.125,
+        (cp[1] + cp[2] * 2.0 + cp[3]) * 0.25,
+        (cp[2] + cp[3]) * 0.5,
+        cp[3],
+    ]
+}
+
+fn eval_bezier(cp: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+    // Point and derivative at u with de Casteljau's algorithm
+    let a = [
+        lerp(u, cp[0], cp[1]),
+        lerp(u, cp[1], cp[2]),
+        lerp(u, cp[2], cp[3]),
+    ];
+    let b = [lerp(u, a[0], a[1]), lerp(u, a[1], a[2])];
+    let derivative = if (b[1] - b[0]).length_squared() > 0.0 {
+        (b[1] - b[0]) * 3.0
+    } else {
+        // Control points that coincide at the ends
+        cp[3] - cp[0]
+    };
+    (lerp(u, b[0], b[1]), derivative)
+}
+
+fn rotate(v: Vec3, axis: Vec3, angle: f64) -> Vec3 {
+    // Rodrigues' rotation formula
+    let k = axis.unit_vector();
+    let (sin, cos) = angle.sin_cos();
+    v * cos + k.cross(&v) * sin + k * (k.dot(&v) * (1.0 - cos))
+}
+
+// Coordinate system where the ray starts at the origin and goes along z
+struct RaySpace {
+    origin: Vec3,
+    x: Vec3,
+    y: Vec3,
+    z: Vec3,
+}
+impl RaySpace {
+    fn to_local(&self, p: Vec3) -> Vec3 {
+        let d = p - self.origin;
+        Vec3::new(d.dot(&self.x), d.dot(&self.y), d.dot(&self.z))
+    }
+    fn vector_to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.x), v.dot(&self.y), v.dot(&self.z))
+    }
+    fn vector_to_world(&self, v: Vec3) -> Vec3 {
+        self.x * v.x + self.y * v.y + self.z * v.z
+    }
+}
+
+impl Curve {
+    fn width_at(&self, u: f64) -> f64 {
+        self.width.0 * (1.0 - u) + self.width.1 * u
+    }
+
+    fn overlaps_ray(&self, cp: &[Vec3], max_width: f64, z_max: f64) -> bool {
+        // Whether the bounds of the control points, grown by the width, contain a part of the ray
+        let r = 0.5 * max_width;
+        let min = |f: fn(&Vec3) -> f64| cp.iter().map(f).fold(f64::INFINITY, f64::min);
+        let max = |f: fn(&Vec3) -> f64| cp.iter().map(f).fold(f64::NEG_INFINITY, f64::max);
+        max(|p| p.x) + r >= 0.0
+            && min(|p| p.x) - r <= 0.0
+            && max(|p| p.y) + r >= 0.0
+            && min(|p| p.y) - r <= 0.0
+            && max(|p| p.z) + r >= 0.0
+            && min(|p| p.z) - r <= z_max
+    }
+
+    fn recursive_hit(
+        &self,
+        ray: &Ray,
+        space: &RaySpace,
+        cp: &[Vec3; 4],
+        (u0, u1): (f64, f64),
+        depth: u32,
+        (t_min, t_max): (f64, f64),
+    ) -> Option<Hit> {
+        let length = ray.direction.length();
+        if depth > 0 {
+            // Split the curve until the pieces are almost straight, skipping the ones the ray misses
+            let split = subdivide_bezier(cp);
+            let us = [u0, (u0 + u1) / 2.0, u1];
+            let mut closest: Option<Hit> = None;
+            for segment in 0..2 {
+                let cps = [
+                    split[3 * segment],
+                    split[3 * segment + 1],
+                    split[3 * segment + 2],
+                    split[3 * segment + 3],
+                ];
+                let t_max = closest.as_ref().map_or(t_max, |hit| hit.t);
+                let max_width = self
+                    .width_at(us[segment])
+                    .max(self.width_at(us[segment + 1]));
+                if !self.overlaps_ray(&cps, max_width, length * t_max) {
+                    continue;
+                }
+                let range = (us[segment], us[segment + 1]);
+                if let Some(hit) =
+                    self.recursive_hit(ray, space, &cps, range, depth - 1, (t_min, t_max))
+                {
+                    closest = Some(hit);
+                }
+            }
+            return closest;
+        }
+
+        // The piece is treated as a line, it's only hit between the planes through its ends
+        if (cp[1].y - cp[0].y) * -cp[0].y + cp[0].x * (cp[0].x - cp[1].x) < 0.0
+            || (cp[2].y - cp[3].y) * -cp[3].y + cp[3].x * (cp[3].x - cp[2].x) < 0.0
+        {
+            return None;
+        }
+        // Closest point of the line to the ray, which goes through the origin of the xy-plane
+        let (dx, dy) = (cp[3].x - cp[0].x, cp[3].y - cp[0].y);
+        let denom = dx * dx + dy * dy;
+        if denom == 0.0 {
+            return None;
+        }
+        let w = (-cp[0].x * dx - cp[0].y * dy) / denom;
+        let u = (u0 + (u1 - u0) * w).clamp(u0, u1);
+        let hit_width = self.width_at(u);
+        let (pc, dpcdw) = eval_bezier(cp, w.clamp(0.0, 1.0));
+        let dist2 = pc.x * pc.x + pc.y * pc.y;
+        if dist2 > hit_width * hit_width * 0.25 {
+            return None;
+        }
+        let t = pc.z / length;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        // v is 0.5 at the middle of the curve and goes to 0 and 1 at the edges
+        let dist = dist2.sqrt();
+        let v = if dpcdw.x * -pc.y + pc.x * dpcdw.y > 0.0 {
+            0.5 + dist / hit_width
+        } else {
+            0.5 - dist / hit_width
+        };
+
+        let (_, dpdu) = eval_bezier(&self.control_points, u);
+        let dpdu_plane = space.vector_to_local(dpdu);
+        let mut dpdv_plane = Vec3::new(-dpdu_plane.y, dpdu_plane.x, 0.0).unit_vector() * hit_width;
+        if let CurveType::Cylinder = self.curve_type {
+            // Bend the normal towards the edges, like it would be on a cylinder
+            let theta = (v - 0.5) * std::f64::consts::PI;
+            dpdv_plane = rotate(dpdv_plane, dpdu_plane, -theta);
+        }
+        let dpdv = space.vector_to_world(dpdv_plane);
+        let normal = dpdu.cross(&dpdv).unit_vector();
+        Some(
+            Hit::new(ray.at(t), normal, t, ray)
+                .with_uv(u, v)
+                .with_tangent(dpdu.unit_vector(), 1.0),
+        )
+    }
+
+    #[allow(dead_code)]
+    pub fn strands_from_file(
+        path: &Path,
+        curve_type: CurveType,
+    ) -> Result<Vec<Curve>, Box<dyn std::error::Error>> {
+        // Each line is a strand with the width at the root and the tip, followed by the points it goes through
+        // "0.01 0.002 x0 y0 z0 x1 y1 z1 ...", lines starting with # are comments
+        let mut curves = Vec::new();
+        for (line_number, line) in fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values: Vec<f64> = line
+                .split_whitespace()
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<_, _>>()?;
+            if values.len() < 8 || !(values.len() - 2).is_multiple_of(3) {
+                return Err(format!("Invalid strand on line {}", line_number + 1).into());
+            }
+            let points: Vec<Vec3> = values[2..]
+                .chunks(3)
+                .map(|c| Vec3::new(c[0], c[1], c[2]))
+                .collect();
+            curves.extend(Curve::strand(&points, (values[0], values[1]), curve_type));
+        }
+        Ok(curves)
+    }
+
+    pub fn strand(points: &[Vec3], width: (f64, f64), curve_type: CurveType) -> Vec<Curve> {
+        // Catmull-Rom spline through the points, with one Bézier curve for every pair of neighboring points
+        let n = points.len();
+        if n < 2 {
+            return Vec::new();
+        }
+        let width_at = |i: usize| width.0 + (width.1 - width.0) * i as f64 / (n - 1) as f64;
+        (0..n - 1)
+            .map(|i| {
+                let p0 = points[i.saturating_sub(1)];
+                let (p1, p2) = (points[i], points[i + 1]);
+                let p3 = points[(i + 2).min(n - 1)];
+                Curve {
+                    control_points: [p1, p1 + (p2 - p0) / 6.0, p2 - (p3 - p1) / 6.0, p2],
+                    width: (width_at(i), width_at(i + 1)),
+                    curve_type,
+                }
+            })
+            .collect()
+    }
+}
+impl Hittable for Curve {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        // Recursive subdivision in the space of the ray, as in pbrt
+        let direction = ray.direction.unit_vector();
+        let mut up = direction.cross(&(self.control_points[3] - self.control_points[0]));
+        if up.near_zero() {
+            // The curve goes along the ray, any direction orthogonal to it works
+            up = direction.cross(&if direction.x.abs() > 0.9 {
+                Vec3::new(0.0, 1.0, 0.0)
+            } else {
+                Vec3::new(1.0, 0.0, 0.0)
+            });
+        }
+        let x = up.cross(&direction).unit_vector();
+        let space = RaySpace {
+            origin: ray.origin,
+            x,
+            y: direction.cross(&x),
+            z: direction,
+        };
+        let cp = self.control_points.map(|p| space.to_local(p));
+        let max_width = self.width.0.max(self.width.1);
+        if !self.overlaps_ray(&cp, max_width, ray.direction.length() * t_max) {
+            return None;
+        }
+        // Enough subdivisions so that the pieces deviate from straight lines by a fraction of the width
+        let l0 = (0..2)
+            .map(|i| {
+                let d = cp[i] - cp[i + 1] * 2.0 + cp[i + 2];
+                d.x.abs().max(d.y.abs()).max(d.z.abs())
+            })
+            .fold(0.0, f64::max);
+        let eps = max_width * 0.05;
+        let depth =
+            ((std::f64::consts::SQRT_2 * 6.0 * l0 / (8.0 * eps)).log2() / 2.0).clamp(0.0, 10.0);
+        self.recursive_hit(ray, &space, &cp, (0.0, 1.0), depth as u32, (t_min, t_max))
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        let r = 0.5 * self.width.0.max(self.width.1);
+        let cp = &self.control_points;
+        let min = |f: fn(&Vec3) -> f64| cp.iter().map(f).fold(f64::INFINITY, f64::min) - r;
+        let max = |f: fn(&Vec3) -> f64| cp.iter().map(f).fold(f64::NEG_INFINITY, f64::max) + r;
+        BoundingBox::new(
+            min(|p| p.x),
+            max(|p| p.x),
+            min(|p| p.y),
+            max(|p| p.y),
+            min(|p| p.z),
+            max(|p| p.z),
+        )
+    }
+}
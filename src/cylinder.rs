@@ -0,0 +1,131 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::hittable::Interval;
+use crate::microfacet::Frame;
+use crate::polynomial::solve_quadratic;
+use crate::ray::Ray;
+use crate::util::PI;
+use crate::vec3::Vec3;
+
+// Cylinder around the axis from base to base + axis, optionally closed off by disks at both ends
+pub struct Cylinder {
+    pub base: Vec3,
+    pub axis: Vec3,
+    pub radius: f64,
+    pub capped: bool,
+}
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t, on_cap) = self
+            .crossings(ray)
+            .into_iter()
+            .find(|(t, _)| *t >= t_min && *t <= t_max)?;
+        Some(self.hit_at(ray, t, on_cap))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // Only the capped cylinder is closed, it's convex so the ray is inside between the first and last crossing
+        let crossings = self.crossings(ray);
+        match (crossings.first(), crossings.last()) {
+            (Some(&(enter, enter_cap)), Some(&(exit, exit_cap))) if self.capped && enter < exit => {
+                vec![Interval {
+                    enter: self.hit_at(ray, enter, enter_cap),
+                    exit: self.hit_at(ray, exit, exit_cap),
+                }]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        axis_bounds(self.base, self.base + self.axis, self.radius)
+    }
+}
+
+impl Cylinder {
+    #[allow(dead_code)]
+    pub fn new(base: Vec3, axis: Vec3, radius: f64, capped: bool) -> Cylinder {
+        Cylinder {
+            base,
+            axis,
+            radius,
+            capped,
+        }
+    }
+
+    fn crossings(&self, ray: &Ray) -> Vec<(f64, bool)> {
+        // Every t where the line through the ray crosses the surface, sorted, with whether it's on a cap
+        // Intersect in the local frame where the axis is z, the frame is orthonormal so t stays the same
+        let height = self.axis.length();
+        let frame = Frame::new(self.axis / height);
+        let o = frame.to_local(ray.origin - self.base);
+        let d = frame.to_local(ray.direction);
+        let r2 = self.radius * self.radius;
+
+        let mut crossings: Vec<(f64, bool)> = solve_quadratic(
+            d.x * d.x + d.y * d.y,
+            2.0 * (o.x * d.x + o.y * d.y),
+            o.x * o.x + o.y * o.y - r2,
+        )
+        .into_iter()
+        .filter(|t| (0.0..=height).contains(&(o.z + t * d.z)))
+        .map(|t| (t, false))
+        .collect();
+        if self.capped && d.z.abs() > 1e-12 {
+            crossings.extend(
+                [0.0, height]
+                    .iter()
+                    .map(|z| (z - o.z) / d.z)
+                    .filter(|t| {
+                        let p = o + d * *t;
+                        p.x * p.x + p.y * p.y <= r2
+                    })
+                    .map(|t| (t, true)),
+            );
+        }
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        crossings
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64, on_cap: bool) -> Hit {
+        let height = self.axis.length();
+        let frame = Frame::new(self.axis / height);
+        let p = frame.to_local(ray.at(t) - self.base);
+        if on_cap {
+            // The caps are mapped onto the square around them
+            let top = p.z > height / 2.0;
+            let normal = if top { frame.n } else { -frame.n };
+            let u = 0.5 + p.x / (2.0 * self.radius);
+            let v = 0.5 + p.y / (2.0 * self.radius);
+            Hit::new(ray.at(t), normal, t, ray)
+                .with_uv(u, v)
+                .with_tangent(frame.t, if top { 1.0 } else { -1.0 })
+        } else {
+            // u goes around the axis and v from the base to the top
+            let normal = frame.to_world(Vec3::new(p.x, p.y, 0.0) / self.radius);
+            let phi = p.y.atan2(p.x);
+            let u = if phi < 0.0 { phi + 2.0 * PI } else { phi } / (2.0 * PI);
+            let tangent = frame.to_world(Vec3::new(-p.y, p.x, 0.0)).unit_vector();
+            Hit::new(ray.at(t), normal, t, ray)
+                .with_uv(u, p.z / height)
+                .with_tangent(tangent, 1.0)
+        }
+    }
+}
+
+pub fn axis_bounds(p1: Vec3, p2: Vec3, radius: f64) -> BoundingBox {
+    // Bounds of a round shape with the given radius around the line from p1 to p2
+    // Along each axis a disk around the line extends by the radius times the sine of the angle between the axis and the line
+    let a = (p2 - p1).unit_vector();
+    let extent = |a: f64| radius * (1.0 - a * a).max(0.0).sqrt();
+    let (ex, ey, ez) = (extent(a.x), extent(a.y), extent(a.z));
+    BoundingBox::new(
+        p1.x.min(p2.x) - ex,
+        p1.x.max(p2.x) + ex,
+        p1.y.min(p2.y) - ey,
+        p1.y.max(p2.y) + ey,
+        p1.z.min(p2.z) - ez,
+        p1.z.max(p2.z) + ez,
+    )
+}
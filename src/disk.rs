@@ -0,0 +1,56 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+#[allow(dead_code)]
+pub struct Disk {
+    pub center: Vec3,
+    pub normal: Vec3,
+    pub radius: f64,
+}
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let normal = self.normal.unit_vector();
+        let denom = normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            // Ray is parallel to the disk
+            return None;
+        }
+        let t = (self.center - ray.origin).dot(&normal) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.at(t);
+        let local = p - self.center;
+        if local.length_squared() > self.radius * self.radius {
+            return None;
+        }
+        // The texture is mapped onto the square around the disk
+        let frame = Frame::new(normal);
+        let u = 0.5 + local.dot(&frame.t) / (2.0 * self.radius);
+        let v = 0.5 + local.dot(&frame.b) / (2.0 * self.radius);
+        Some(
+            Hit::new(p, normal, t, ray)
+                .with_uv(u, v)
+                .with_tangent(frame.t, 1.0),
+        )
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        // Along each axis the disk extends by the radius times the sine of the angle between the axis and the normal
+        let normal = self.normal.unit_vector();
+        let extent = |n: f64| self.radius * (1.0 - n * n).max(0.0).sqrt();
+        let (ex, ey, ez) = (extent(normal.x), extent(normal.y), extent(normal.z));
+        BoundingBox::new(
+            self.center.x - ex,
+            self.center.x + ex,
+            self.center.y - ey,
+            self.center.y + ey,
+            self.center.z - ez,
+            self.center.z + ez,
+        )
+    }
+}
@@ -0,0 +1,853 @@
+use crate::alpha_mask::AlphaMask;
+use crate::camera::Camera;
+use crate::hittable::Hit;
+use crate::light::Light;
+use crate::lights::{DirectionalLight, PointLight, SpotLight};
+use crate::materials::Principled;
+use crate::mesh::Mesh;
+use crate::normal_map::NormalMap;
+use crate::object::Object;
+use crate::texture::Texture;
+use crate::textures::{ImageTexture, WrapMode};
+use crate::vec3::Vec3;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Column-major like in glTF files, m[column][row]
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.0; 4]; 4];
+    for (column, b_column) in b.iter().enumerate() {
+        for row in 0..4 {
+            m[column][row] = (0..4).map(|k| a[k][row] * b_column[k]).sum();
+        }
+    }
+    m
+}
+
+fn transform_vector(m: &Matrix, v: Vec3) -> Vec3 {
+    Vec3::new(
+        m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+        m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+        m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+    )
+}
+
+fn transform_point(m: &Matrix, p: Vec3) -> Vec3 {
+    transform_vector(m, p) + Vec3::new(m[3][0], m[3][1], m[3][2])
+}
+
+fn columns(m: &Matrix) -> [Vec3; 3] {
+    [0, 1, 2].map(|i| Vec3::new(m[i][0], m[i][1], m[i][2]))
+}
+
+fn determinant(m: &Matrix) -> f64 {
+    let [a, b, c] = columns(m);
+    a.dot(&b.cross(&c))
+}
+
+fn transform_normal(m: &Matrix, n: Vec3) -> Vec3 {
+    // With the inverse transpose, which is the cofactor matrix divided by the determinant
+    let [a, b, c] = columns(m);
+    let n = b.cross(&c) * n.x + c.cross(&a) * n.y + a.cross(&b) * n.z;
+    n.unit_vector() * determinant(m).signum()
+}
+
+fn node_matrix(node: &Value) -> Matrix {
+    // Either a whole matrix or translation, rotation and scale, which are applied in reverse order
+    if let Some(m) = numbers(&node["matrix"]).filter(|m| m.len() == 16) {
+        return [0, 1, 2, 3].map(|column| [0, 1, 2, 3].map(|row| m[column * 4 + row]));
+    }
+    let t = numbers(&node["translation"]).unwrap_or(vec![0.0; 3]);
+    let q = numbers(&node["rotation"]).unwrap_or(vec![0.0, 0.0, 0.0, 1.0]);
+    let s = numbers(&node["scale"]).unwrap_or(vec![1.0; 3]);
+    let (x, y, z, w) = (q[0], q[1], q[2], q[3]);
+    let rotation = [
+        [
+            1.0 - 2.0 * (y * y + z * z),
+            2.0 * (x * y + z * w),
+            2.0 * (x * z - y * w),
+        ],
+        [
+            2.0 * (x * y - z * w),
+            1.0 - 2.0 * (x * x + z * z),
+            2.0 * (y * z + x * w),
+        ],
+        [
+            2.0 * (x * z + y * w),
+            2.0 * (y * z - x * w),
+            1.0 - 2.0 * (x * x + y * y),
+        ],
+    ];
+    let mut m = IDENTITY;
+    for column in 0..3 {
+        for row in 0..3 {
+            m[column][row] = rotation[column][row] * s[column];
+        }
+        m[3][column] = t[column];
+    }
+    m
+}
+
+fn numbers(value: &Value) -> Option<Vec<f64>> {
+    value.as_array()?.iter().map(|v| v.as_f64()).collect()
+}
+
+fn index(value: &Value) -> Option<usize> {
+    value.as_u64().map(|i| i as usize)
+}
+
+fn percent_decode(uri: &str) -> String {
+    // Relative URIs escape spaces and other special characters as %XX, with the byte in hexadecimal
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut bytes = Vec::new();
+    let (mut bits, mut bit_count) = (0u32, 0);
+    for c in data
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return Err("Invalid base64 data".into()),
+        };
+        bits = ((bits << 6) | value as u32) & 0xFFFFFF;
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            bytes.push((bits >> bit_count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ImageKind {
+    Color, // Read with a gamma of 2
+    Data,  // Read with a gamma of 1, e.g. normals and roughness
+    Alpha, // The alpha channel
+}
+
+// Texture of a glTF material, the factor times the image and the colors of the vertices if there are any
+struct MaterialTexture {
+    factor: Vec3,
+    image: Option<Arc<ImageTexture>>,
+    channel: Option<usize>, // Only this channel of the image is used, e.g. blue for metalness
+    vertex_colors: bool,
+}
+impl Texture for MaterialTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let mut color = self.factor;
+        if let Some(image) = &self.image {
+            let c = image.value(hit);
+            color = color
+                * match self.channel {
+                    Some(0) => Vec3::new(c.x, c.x, c.x),
+                    Some(1) => Vec3::new(c.y, c.y, c.y),
+                    Some(_) => Vec3::new(c.z, c.z, c.z),
+                    None => c,
+                };
+        }
+        match hit.color {
+            Some(c) if self.vertex_colors => color * c,
+            _ => color,
+        }
+    }
+}
+
+// Scene from a glTF 2.0 file, either .gltf with separate or embedded buffers, or binary .glb
+// Meshes are transformed into world space while loading, cameras and lights keep their transforms as well
+pub struct GltfScene {
+    pub objects: Vec<Object>,
+    pub lights: Vec<Box<dyn Light>>, // From the KHR_lights_punctual extension
+    pub cameras: Vec<Camera>,        // Only perspective cameras, they use the given aspect ratio
+    pub warnings: Vec<String>, // Parts of the file that were left out, e.g. images that can't be read
+}
+
+// The JSON and the binary buffer of a .glb file
+type GlbChunks = (Value, Option<Vec<u8>>);
+
+// The parsed file with its buffers, while the scene is being built
+struct Document {
+    json: Value,
+    buffers: Vec<Vec<u8>>,
+    directory: PathBuf, // Relative paths of buffers and images start here
+    images: HashMap<(usize, ImageKind), Option<Arc<ImageTexture>>>, // Loaded images by texture index, None if they can't be read
+    warnings: Vec<String>,
+}
+
+impl GltfScene {
+    #[allow(dead_code)]
+    pub fn from_file(
+        path: &Path,
+        aspect_ratio: f64,
+    ) -> Result<GltfScene, Box<dyn std::error::Error>> {
+        let data = fs::read(path)?;
+        let (json, mut binary) = if data.starts_with(b"glTF") {
+            GltfScene::read_glb(&data)?
+        } else {
+            (serde_json::from_slice(&data)?, None)
+        };
+        let directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        let buffers = json["buffers"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|buffer| match buffer["uri"].as_str() {
+                Some(uri) => Document::read_uri(&directory, uri),
+                // Only the first buffer of a .glb file can be stored in the file itself
+                None => binary.take().ok_or_else(|| "Buffer without data".into()),
+            })
+            .collect::<Result<_, _>>()?;
+        let mut document = Document {
+            json,
+            buffers,
+            directory,
+            images: HashMap::new(),
+            warnings: Vec::new(),
+        };
+
+        let mut scene = GltfScene {
+            objects: Vec::new(),
+            lights: Vec::new(),
+            cameras: Vec::new(),
+            warnings: Vec::new(),
+        };
+        let root_nodes: Vec<usize> = match index(&document.json["scene"])
+            .or(document.json["scenes"].as_array().map(|_| 0))
+        {
+            Some(i) => document.json["scenes"][i]["nodes"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(index)
+                .collect(),
+            None => {
+                // Files without scenes just contain nodes, the ones that aren't children of others are used
+                let nodes = document.json["nodes"].as_array().map_or(0, |n| n.len());
+                let children: Vec<usize> = document.json["nodes"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|node| node["children"].as_array().into_iter().flatten())
+                    .filter_map(index)
+                    .collect();
+                (0..nodes).filter(|i| !children.contains(i)).collect()
+            }
+        };
+        for node in root_nodes {
+            document.add_node(node, &IDENTITY, &[], aspect_ratio, &mut scene)?;
+        }
+        scene.warnings = document.warnings;
+        Ok(scene)
+    }
+
+    fn read_glb(data: &[u8]) -> Result<GlbChunks, Box<dyn std::error::Error>> {
+        // 12 byte header, then chunks with their length and type, the first one holds the JSON
+        let u32_at = |i: usize| -> Result<usize, Box<dyn std::error::Error>> {
+            let bytes = data.get(i..i + 4).ok_or("Unexpected end of GLB file")?;
+            Ok(u32::from_le_bytes(bytes.try_into()?) as usize)
+        };
+        if u32_at(4)? != 2 {
+            return Err(format!("Unsupported glTF version {}", u32_at(4)?).into());
+        }
+        let (mut json, mut binary) = (None, None);
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let length = u32_at(pos)?;
+            let chunk = data
+                .get(pos + 8..pos + 8 + length)
+                .ok_or("Unexpected end of GLB file")?;
+            match u32_at(pos + 4)? {
+                0x4E4F534A => json = Some(serde_json::from_slice(chunk)?), // "JSON"
+                0x004E4942 => binary = Some(chunk.to_vec()),               // "BIN"
+                _ => (), // Chunks of unknown types have to be ignored
+            }
+            pos += 8 + length;
+        }
+        Ok((json.ok_or("GLB file without JSON")?, binary))
+    }
+}
+
+impl Document {
+    fn read_uri(directory: &Path, uri: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // Either a path relative to the file or the data itself in base64
+        if uri.starts_with("data:") {
+            let (_, data) = uri
+                .split_once(";base64,")
+                .ok_or("Only base64 data URIs are supported")?;
+            decode_base64(data)
+        } else {
+            Ok(fs::read(directory.join(percent_decode(uri)))?)
+        }
+    }
+
+    fn buffer_view(&self, view: usize) -> Result<&[u8], Box<dyn std::error::Error>> {
+        let view = &self.json["bufferViews"][view];
+        let buffer = index(&view["buffer"])
+            .and_then(|b| self.buffers.get(b))
+            .ok_or("Buffer view without buffer")?;
+        let start = index(&view["byteOffset"]).unwrap_or(0);
+        let length = index(&view["byteLength"]).ok_or("Buffer view without length")?;
+        Ok(buffer
+            .get(start..start + length)
+            .ok_or("Buffer view out of range")?)
+    }
+
+    fn read_accessor(&self, accessor: usize) -> Result<Vec<Vec<f64>>, Box<dyn std::error::Error>> {
+        // Every element as a list of its components, normalized integers are mapped to [0, 1] or [-1, 1]
+        let accessor = &self.json["accessors"][accessor];
+        let count = index(&accessor["count"]).ok_or("Accessor without count")?;
+        let components = match accessor["type"].as_str() {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            t => return Err(format!("Unsupported accessor type {:?}", t).into()),
+        };
+        let component_type = accessor["componentType"].as_u64().unwrap_or(0);
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            t => return Err(format!("Unsupported component type {}", t).into()),
+        };
+        let normalized = accessor["normalized"].as_bool().unwrap_or(false);
+        // Accessors without a buffer view are all zeros, sparse accessors aren't supported
+        let view = match index(&accessor["bufferView"]) {
+            Some(view) => view,
+            None => return Ok(vec![vec![0.0; components]; count]),
+        };
+        let data = self.buffer_view(view)?;
+        let start = index(&accessor["byteOffset"]).unwrap_or(0);
+        let stride =
+            index(&self.json["bufferViews"][view]["byteStride"]).unwrap_or(components * size);
+        (0..count)
+            .map(|i| {
+                (0..components)
+                    .map(|c| {
+                        let offset = start + i * stride + c * size;
+                        let b = data
+                            .get(offset..offset + size)
+                            .ok_or("Accessor out of range")?;
+                        Ok(match (component_type, normalized) {
+                            (5120, false) => b[0] as i8 as f64,
+                            (5120, true) => (b[0] as i8 as f64 / 127.0).max(-1.0),
+                            (5121, false) => b[0] as f64,
+                            (5121, true) => b[0] as f64 / 255.0,
+                            (5122, false) => i16::from_le_bytes([b[0], b[1]]) as f64,
+                            (5122, true) => {
+                                (i16::from_le_bytes([b[0], b[1]]) as f64 / 32767.0).max(-1.0)
+                            }
+                            (5123, false) => u16::from_le_bytes([b[0], b[1]]) as f64,
+                            (5123, true) => u16::from_le_bytes([b[0], b[1]]) as f64 / 65535.0,
+                            (5125, _) => u32::from_le_bytes(b.try_into()?) as f64,
+                            _ => f32::from_le_bytes(b.try_into()?) as f64,
+                        })
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn add_node(
+        &mut self,
+        node_index: usize,
+        parent: &Matrix,
+        ancestors: &[usize], // Nodes above this one, a node can't be its own descendant
+        aspect_ratio: f64,
+        scene: &mut GltfScene,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if ancestors.contains(&node_index) {
+            return Err(format!("Node {} is a descendant of itself", node_index).into());
+        }
+        let node = self.json["nodes"][node_index].clone();
+        let matrix = multiply(parent, &node_matrix(&node));
+        if let Some(mesh) = index(&node["mesh"]) {
+            self.add_mesh(mesh, &matrix, scene)?;
+        }
+        // Cameras look along -z and lights shine along -z, with y up
+        let origin = transform_point(&matrix, Vec3::z());
+        let forward = transform_vector(&matrix, Vec3::new(0.0, 0.0, -1.0));
+        if let Some(camera) = index(&node["camera"]) {
+            let perspective = &self.json["cameras"][camera]["perspective"];
+            if let Some(yfov) = perspective["yfov"].as_f64() {
+                let up = transform_vector(&matrix, Vec3::new(0.0, 1.0, 0.0));
+                scene.cameras.push(
+                    Camera::new_with_fov(origin, aspect_ratio, yfov.to_degrees())
+                        .with_orientation(forward, up),
+                );
+            }
+        }
+        if let Some(light) = index(&node["extensions"]["KHR_lights_punctual"]["light"]) {
+            let light = &self.json["extensions"]["KHR_lights_punctual"]["lights"][light];
+            let c = numbers(&light["color"]).unwrap_or(vec![1.0; 3]);
+            let color = Vec3::new(c[0], c[1], c[2]);
+            let intensity = light["intensity"].as_f64().unwrap_or(1.0);
+            match light["type"].as_str() {
+                Some("directional") => scene.lights.push(Box::new(DirectionalLight {
+                    direction: forward,
+                    color,
+                    intensity,
+                })),
+                Some("point") => scene.lights.push(Box::new(PointLight {
+                    position: origin,
+                    color,
+                    intensity,
+                })),
+                Some("spot") => scene.lights.push(Box::new(SpotLight {
+                    position: origin,
+                    direction: forward,
+                    color,
+                    intensity,
+                    inner_angle: light["spot"]["innerConeAngle"].as_f64().unwrap_or(0.0),
+                    outer_angle: light["spot"]["outerConeAngle"]
+                        .as_f64()
+                        .unwrap_or(std::f64::consts::FRAC_PI_4),
+                })),
+                t => return Err(format!("Unsupported light type {:?}", t).into()),
+            }
+        }
+        let ancestors = [ancestors, &[node_index]].concat();
+        for child in node["children"].as_array().into_iter().flatten() {
+            let child = index(child).ok_or("Invalid child node")?;
+            self.add_node(child, &matrix, &ancestors, aspect_ratio, scene)?;
+        }
+        Ok(())
+    }
+
+    fn add_mesh(
+        &mut self,
+        mesh: usize,
+        matrix: &Matrix,
+        scene: &mut GltfScene,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let primitives = self.json["meshes"][mesh]["primitives"].clone();
+        for primitive in primitives.as_array().into_iter().flatten() {
+            let attributes = &primitive["attributes"];
+            let attribute =
+                |name: &str| -> Result<Option<Vec<Vec<f64>>>, Box<dyn std::error::Error>> {
+                    index(&attributes[name])
+                        .map(|a| self.read_accessor(a))
+                        .transpose()
+                };
+            let vertices: Vec<Vec3> = attribute("POSITION")?
+                .ok_or("Primitive without positions")?
+                .iter()
+                .map(|p| transform_point(matrix, Vec3::new(p[0], p[1], p[2])))
+                .collect();
+            let normals: Option<Vec<Vec3>> = attribute("NORMAL")?.map(|normals| {
+                normals
+                    .iter()
+                    .map(|n| transform_normal(matrix, Vec3::new(n[0], n[1], n[2])))
+                    .collect()
+            });
+            // Only the first set of texture coordinates is used, v goes down in glTF
+            let tex_coords: Option<Vec<(f64, f64)>> = attribute("TEXCOORD_0")?
+                .map(|uvs| uvs.iter().map(|uv| (uv[0], 1.0 - uv[1])).collect());
+            let colors: Option<Vec<Vec3>> = attribute("COLOR_0")?
+                .map(|colors| colors.iter().map(|c| Vec3::new(c[0], c[1], c[2])).collect());
+            let indices: Vec<usize> = match index(&primitive["indices"]) {
+                Some(a) => self
+                    .read_accessor(a)?
+                    .iter()
+                    .map(|i| i[0] as usize)
+                    .collect(),
+                None => (0..vertices.len()).collect(),
+            };
+            let mut triangles: Vec<[usize; 3]> = match primitive["mode"].as_u64().unwrap_or(4) {
+                4 => indices
+                    .chunks_exact(3)
+                    .map(|t| [t[0], t[1], t[2]])
+                    .collect(),
+                // Every other triangle of a strip is wound the other way
+                5 => (2..indices.len())
+                    .map(|i| match i % 2 {
+                        0 => [indices[i - 2], indices[i - 1], indices[i]],
+                        _ => [indices[i - 1], indices[i - 2], indices[i]],
+                    })
+                    .collect(),
+                6 => (2..indices.len())
+                    .map(|i| [indices[0], indices[i - 1], indices[i]])
+                    .collect(),
+                _ => continue, // Points and lines can't be rendered
+            };
+            if determinant(matrix) < 0.0 {
+                // Mirroring turns the faces inside out
+                triangles.iter_mut().for_each(|t| t.swap(1, 2));
+            }
+            if vertices.is_empty() || triangles.is_empty() {
+                continue;
+            }
+            let mesh = Mesh::from_buffers(vertices, normals, tex_coords, colors, &triangles)?;
+            let object = self.object(index(&primitive["material"]), Box::new(mesh));
+            scene.objects.push(object);
+        }
+        Ok(())
+    }
+
+    fn object(&mut self, material: Option<usize>, shape: Box<Mesh>) -> Object {
+        // Metallic-roughness materials become Principled ones, emission isn't supported
+        let material = match material {
+            Some(i) => self.json["materials"][i].clone(),
+            None => Value::Null,
+        };
+        let pbr = &material["pbrMetallicRoughness"];
+        let base_color = numbers(&pbr["baseColorFactor"]).unwrap_or(vec![1.0; 4]);
+        let gray = |value: &Value, default: f64| {
+            let v = value.as_f64().unwrap_or(default);
+            Vec3::new(v, v, v)
+        };
+        let metallic_roughness = self.texture(&pbr["metallicRoughnessTexture"], ImageKind::Data);
+        let extensions = &material["extensions"];
+        let transmission = &extensions["KHR_materials_transmission"];
+        let principled = Principled {
+            base_color: Box::new(MaterialTexture {
+                factor: Vec3::new(base_color[0], base_color[1], base_color[2]),
+                image: self.texture(&pbr["baseColorTexture"], ImageKind::Color),
+                channel: None,
+                vertex_colors: true,
+            }),
+            // Roughness is in the green channel and metalness in the blue one
+            metallic: Box::new(MaterialTexture {
+                factor: gray(&pbr["metallicFactor"], 1.0),
+                image: metallic_roughness.clone(),
+                channel: Some(2),
+                vertex_colors: false,
+            }),
+            roughness: Box::new(MaterialTexture {
+                factor: gray(&pbr["roughnessFactor"], 1.0),
+                image: metallic_roughness,
+                channel: Some(1),
+                vertex_colors: false,
+            }),
+            transmission: Box::new(MaterialTexture {
+                factor: gray(&transmission["transmissionFactor"], 0.0),
+                image: self.texture(&transmission["transmissionTexture"], ImageKind::Data),
+                channel: Some(0),
+                vertex_colors: false,
+            }),
+            ir: extensions["KHR_materials_ior"]["ior"]
+                .as_f64()
+                .unwrap_or(1.5),
+            ..Principled::new(Vec3::new(1.0, 1.0, 1.0))
+        };
+        let mut object = Object::new(Box::new(principled), shape);
+
+        let normal_texture = &material["normalTexture"];
+        if let Some(image) = self.texture(normal_texture, ImageKind::Data) {
+            object = object.with_normal_map(NormalMap::TangentSpace {
+                texture: Box::new(MaterialTexture {
+                    factor: Vec3::new(1.0, 1.0, 1.0),
+                    image: Some(image),
+                    channel: None,
+                    vertex_colors: false,
+                }),
+                strength: normal_texture["scale"].as_f64().unwrap_or(1.0),
+            });
+        }
+        // Blended materials are rendered opaque
+        if material["alphaMode"].as_str() == Some("MASK") {
+            object = object.with_alpha_mask(AlphaMask {
+                opacity: Box::new(MaterialTexture {
+                    factor: Vec3::new(base_color[3], base_color[3], base_color[3]),
+                    image: self.texture(&pbr["baseColorTexture"], ImageKind::Alpha),
+                    channel: None,
+                    vertex_colors: false,
+                }),
+                cutoff: material["alphaCutoff"].as_f64().unwrap_or(0.5),
+            });
+        }
+        object
+    }
+
+    fn texture(&mut self, info: &Value, kind: ImageKind) -> Option<Arc<ImageTexture>> {
+        // The image of a texture reference in a material, images are only loaded once
+        // Images that can't be read, e.g. JPEGs, are left out so that the material uses its factors
+        let texture_index = index(&info["index"])?;
+        if let Some(image) = self.images.get(&(texture_index, kind)) {
+            return image.clone();
+        }
+        let image = match self.read_image(texture_index, kind) {
+            Ok(image) => Some(Arc::new(image)),
+            Err(e) => {
+                self.warnings.push(format!(
+                    "Ignoring the image of texture {}: {}",
+                    texture_index, e
+                ));
+                None
+            }
+        };
+        self.images.insert((texture_index, kind), image.clone());
+        image
+    }
+
+    fn read_image(
+        &self,
+        texture_index: usize,
+        kind: ImageKind,
+    ) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        let texture = &self.json["textures"][texture_index];
+        let image =
+            &self.json["images"][index(&texture["source"]).ok_or("Texture without image")?];
+        // Only PNG images can be decoded, images in files are checked by their extension
+        if let Some(mime_type) = image["mimeType"].as_str().filter(|m| *m != "image/png") {
+            return Err(format!("Unsupported image type {}", mime_type).into());
+        }
+        let data = match (image["uri"].as_str(), index(&image["bufferView"])) {
+            (Some(uri), _) if !uri.starts_with("data:") => None,
+            (Some(uri), _) => Some(Document::read_uri(&self.directory, uri)?),
+            (None, Some(view)) => Some(self.buffer_view(view)?.to_vec()),
+            (None, None) => return Err("Image without data".into()),
+        };
+        let path = |uri: &Value| self.directory.join(percent_decode(uri.as_str().unwrap()));
+        let mut image_texture = match (data, kind) {
+            (Some(data), ImageKind::Alpha) => ImageTexture::alpha_from_png_data(&data)?,
+            (Some(data), _) => ImageTexture::from_png_data(&data)?,
+            (None, ImageKind::Alpha) => ImageTexture::alpha_from_file(&path(&image["uri"]))?,
+            (None, _) => ImageTexture::from_file(&path(&image["uri"]))?,
+        };
+        if kind == ImageKind::Data {
+            image_texture.gamma = 1.0;
+        }
+        // The wrap mode along u is used for both directions
+        image_texture.wrap = match index(&texture["sampler"])
+            .and_then(|s| self.json["samplers"][s]["wrapS"].as_u64())
+        {
+            Some(33071) => WrapMode::Clamp,
+            Some(33648) => WrapMode::Mirror,
+            _ => WrapMode::Repeat,
+        };
+        Ok(image_texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use serde_json::json;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(
+            (a - b).length() < 1e-9,
+            "({}, {}, {}) != ({}, {}, {})",
+            a.x,
+            a.y,
+            a.z,
+            b.x,
+            b.y,
+            b.z
+        );
+    }
+
+    fn glb(version: u32, chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (chunk_type, data) in chunks {
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(*chunk_type);
+            body.extend(*data);
+        }
+        let mut file = b"glTF".to_vec();
+        file.extend(version.to_le_bytes());
+        file.extend((12 + body.len() as u32).to_le_bytes());
+        file.extend(body);
+        file
+    }
+
+    #[test]
+    fn glb_chunks() {
+        // Chunks of unknown types are skipped
+        let json = br#"{"asset": {"version": "2.0"}}  "#;
+        let file = glb(
+            2,
+            &[(b"JSON", json), (b"XTRA", b"abcd"), (b"BIN\0", b"\x01\x02")],
+        );
+        let (json, binary) = GltfScene::read_glb(&file).unwrap();
+        assert_eq!(json["asset"]["version"], "2.0");
+        assert_eq!(binary.unwrap(), [1, 2]);
+        let (_, binary) = GltfScene::read_glb(&glb(2, &[(b"JSON", b"{}")])).unwrap();
+        assert!(binary.is_none());
+        let error = |file: &[u8]| GltfScene::read_glb(file).err().unwrap().to_string();
+        assert!(error(&glb(1, &[(b"JSON", b"{}")])).contains("version 1"));
+        assert!(error(&glb(2, &[(b"BIN\0", b"")])).contains("without JSON"));
+        let file = glb(2, &[(b"JSON", b"{}  ")]);
+        assert!(error(&file[..file.len() - 1]).contains("Unexpected end"));
+        assert!(error(b"glTF").contains("Unexpected end"));
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!(
+            decode_base64("SGVsbG8s\nIHdvcmxkIQ==").unwrap(),
+            b"Hello, world!"
+        );
+        // Both the standard and the URL safe alphabet
+        assert_eq!(decode_base64("+/8=").unwrap(), [0xfb, 0xff]);
+        assert_eq!(decode_base64("-_8").unwrap(), [0xfb, 0xff]);
+        assert!(decode_base64("ab$d").is_err());
+        let data = Document::read_uri(Path::new(""), "data:application/octet-stream;base64,AAEC");
+        assert_eq!(data.unwrap(), [0, 1, 2]);
+        assert!(Document::read_uri(Path::new(""), "data:text/plain,abc").is_err());
+        // Escapes that aren't complete stay as they are
+        assert_eq!(percent_decode("a%20b%2Bc%C3%A9.bin"), "a b+cé.bin");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn node_transforms() {
+        // Scaled, then rotated by 90° around z, then moved
+        let half = std::f64::consts::FRAC_1_SQRT_2;
+        let node = json!({
+            "translation": [1.0, 2.0, 3.0],
+            "rotation": [0.0, 0.0, half, half],
+            "scale": [2.0, 2.0, 2.0],
+        });
+        let m = node_matrix(&node);
+        assert_close(
+            transform_point(&m, Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(1.0, 4.0, 3.0),
+        );
+        assert_close(
+            transform_vector(&m, Vec3::new(0.0, 1.0, 0.0)),
+            Vec3::new(-2.0, 0.0, 0.0),
+        );
+        // Matrices are column-major and take precedence
+        let values: Vec<f64> = m.iter().flatten().copied().collect();
+        let matrix = node_matrix(&json!({"matrix": values, "translation": [9.0, 9.0, 9.0]}));
+        assert_eq!(matrix, m);
+        // Children are transformed by their parents first
+        let parent = node_matrix(&json!({"translation": [0.0, 0.0, 5.0]}));
+        let combined = multiply(&parent, &m);
+        assert_close(
+            transform_point(&combined, Vec3::z()),
+            Vec3::new(1.0, 2.0, 8.0),
+        );
+        // Normals stay orthogonal to the surface under non-uniform scaling and are mirrored with it
+        let stretched = node_matrix(&json!({"scale": [1.0, 4.0, 1.0]}));
+        let n = transform_normal(&stretched, Vec3::new(1.0, 1.0, 0.0));
+        assert_close(n, Vec3::new(4.0, 1.0, 0.0).unit_vector());
+        let mirrored = node_matrix(&json!({"scale": [-1.0, 1.0, 1.0]}));
+        assert_close(
+            transform_normal(&mirrored, Vec3::new(1.0, 0.0, 0.0)),
+            Vec3::new(-1.0, 0.0, 0.0),
+        );
+    }
+
+    fn write_scene(name: &str, nodes: Value, material: Value) -> PathBuf {
+        // A triangle in a buffer file whose name has to be percent-decoded, followed by an image that isn't one
+        let directory = std::env::temp_dir();
+        let buffer: Vec<u8> = [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .chain(*b"not a png")
+            .collect();
+        fs::write(directory.join(format!("ray tracer {}.bin", name)), buffer).unwrap();
+        let json = json!({
+            "asset": {"version": "2.0"},
+            "buffers": [{"uri": format!("ray%20tracer%20{}.bin", name), "byteLength": 45}],
+            "bufferViews": [
+                {"buffer": 0, "byteLength": 36},
+                {"buffer": 0, "byteOffset": 36, "byteLength": 9},
+            ],
+            "accessors": [{"bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3"}],
+            "meshes": [{"primitives": [{"attributes": {"POSITION": 0}, "material": 0}]}],
+            "materials": [material],
+            "images": [
+                {"uri": "ray_tracer_missing.jpg"},
+                {"bufferView": 1, "mimeType": "image/jpeg"},
+                {"bufferView": 1, "mimeType": "image/png"},
+            ],
+            "textures": [{"source": 0}, {"source": 1}, {"source": 2}],
+            "cameras": [{"type": "perspective", "perspective": {"yfov": 0.8, "znear": 0.1}}],
+            "nodes": nodes,
+        });
+        let path = directory.join(format!("ray_tracer_{}.gltf", name));
+        fs::write(&path, json.to_string()).unwrap();
+        path
+    }
+
+    #[test]
+    fn scenes() {
+        // The camera is a child of the moved node, the mesh is mirrored
+        let nodes = json!([
+            {"translation": [0.0, 0.0, 5.0], "children": [1, 2]},
+            {"camera": 0, "rotation": [0.0, 1.0, 0.0, 0.0]},
+            {"mesh": 0, "scale": [-1.0, 1.0, 1.0]},
+        ]);
+        let path = write_scene("nodes", nodes, json!({}));
+        let scene = GltfScene::from_file(&path, 1.5).unwrap();
+        assert_eq!(scene.objects.len(), 1);
+        assert_eq!(scene.cameras.len(), 1);
+        let ray = scene.cameras[0].get_ray(0.5, 0.5);
+        assert_close(ray.origin, Vec3::new(0.0, 0.0, 5.0));
+        assert_close(ray.direction.unit_vector(), Vec3::new(0.0, 0.0, 1.0));
+        let bounds = scene.objects[0].get_bounds();
+        assert_close(bounds.lower(), Vec3::new(-1.0, 0.0, 5.0));
+        // Nodes that are their own descendants can't be loaded
+        let nodes = json!([{"children": [1]}, {"children": [2]}, {"children": [1]}]);
+        let path = write_scene("cycle", nodes, json!({}));
+        let error = GltfScene::from_file(&path, 1.5).err().unwrap().to_string();
+        assert!(error.contains("descendant of itself"));
+    }
+
+    #[test]
+    fn unreadable_images() {
+        // Images that can't be decoded are left out, the materials still load with their factors
+        for texture in 0..3 {
+            let material = json!({
+                "pbrMetallicRoughness": {"baseColorTexture": {"index": texture}},
+                "normalTexture": {"index": texture},
+            });
+            let path = write_scene(
+                &format!("image_{}", texture),
+                json!([{"mesh": 0}]),
+                material,
+            );
+            let scene = GltfScene::from_file(&path, 1.0).unwrap();
+            assert_eq!(scene.objects.len(), 1);
+            assert!(scene.objects[0].normal_map.is_none());
+            // Once for the colors and once for the normal map
+            assert_eq!(scene.warnings.len(), 2);
+            let expected = format!("Ignoring the image of texture {}", texture);
+            assert!(scene.warnings.iter().all(|w| w.starts_with(&expected)));
+        }
+    }
+}
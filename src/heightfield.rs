@@ -0,0 +1,335 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::textures::ImageTexture;
+use crate::vec3::Vec3;
+use std::path::Path;
+
+// Terrain from a grid of heights, each cell of the grid is split into two triangles
+// The grid lies in the xz-plane starting at corner, row by row along z, and the heights go up along y
+pub struct Heightfield {
+    pub corner: Vec3,
+    pub size: Vec3, // Extent along x and z, and the height of a sample with the value 1
+    width: usize,   // Number of samples along x
+    depth: usize,   // Number of samples along z
+    heights: Vec<f64>,
+    normals: Vec<Vec3>,
+    // Lowest and highest height in blocks of 2^level by 2^level cells, level 0 holds the single cells
+    // Lets rays skip large parts of the terrain that they pass over
+    min_max: Vec<Vec<(f64, f64)>>,
+}
+impl Heightfield {
+    pub fn new(
+        corner: Vec3,
+        size: Vec3,
+        width: usize,
+        depth: usize,
+        heights: Vec<f64>,
+    ) -> Heightfield {
+        assert!(width >= 2 && depth >= 2 && heights.len() == width * depth);
+        let mut heightfield = Heightfield {
+            corner,
+            size,
+            width,
+            depth,
+            heights,
+            normals: Vec::new(),
+            min_max: Vec::new(),
+        };
+        heightfield.normals = (0..width * depth)
+            .map(|i| heightfield.vertex_normal(i % width, i / width))
+            .collect();
+        heightfield.build_min_max();
+        heightfield
+    }
+
+    #[allow(dead_code)]
+    pub fn from_file(
+        path: &Path,
+        corner: Vec3,
+        size: Vec3,
+    ) -> Result<Heightfield, Box<dyn std::error::Error>> {
+        // The brightness of a grayscale image gives the heights, the top row of the image is at the lowest z
+        let image = ImageTexture::from_file(path)?;
+        if image.width < 2 || image.height < 2 {
+            return Err(format!(
+                "Heightfield image {} is smaller than 2 by 2 pixels",
+                path.display()
+            )
+            .into());
+        }
+        Ok(Heightfield::new(
+            corner,
+            size,
+            image.width,
+            image.height,
+            image.grayscale(),
+        ))
+    }
+
+    fn cell_size(&self) -> (f64, f64) {
+        (
+            self.size.x / (self.width - 1) as f64,
+            self.size.z / (self.depth - 1) as f64,
+        )
+    }
+
+    fn height(&self, x: usize, z: usize) -> f64 {
+        self.heights[z * self.width + x] * self.size.y
+    }
+
+    fn vertex(&self, x: usize, z: usize) -> Vec3 {
+        let (dx, dz) = self.cell_size();
+        self.corner + Vec3::new(x as f64 * dx, self.height(x, z), z as f64 * dz)
+    }
+
+    fn vertex_normal(&self, x: usize, z: usize) -> Vec3 {
+        // Central differences of the heights, one sided at the border
+        let (dx, dz) = self.cell_size();
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.width - 1));
+        let (z0, z1) = (z.saturating_sub(1), (z + 1).min(self.depth - 1));
+        let slope_x = (self.height(x1, z) - self.height(x0, z)) / ((x1 - x0) as f64 * dx);
+        let slope_z = (self.height(x, z1) - self.height(x, z0)) / ((z1 - z0) as f64 * dz);
+        Vec3::new(-slope_x, 1.0, -slope_z).unit_vector()
+    }
+
+    fn build_min_max(&mut self) {
+        let (mut w, mut d) = (self.width - 1, self.depth - 1);
+        let mut level: Vec<(f64, f64)> = (0..w * d)
+            .map(|i| {
+                let (x, z) = (i % w, i / w);
+                let h = [
+                    self.height(x, z),
+                    self.height(x + 1, z),
+                    self.height(x, z + 1),
+                    self.height(x + 1, z + 1),
+                ];
+                (
+                    h.iter().fold(f64::INFINITY, |a, b| a.min(*b)),
+                    h.iter().fold(f64::NEG_INFINITY, |a, b| a.max(*b)),
+                )
+            })
+            .collect();
+        loop {
+            let done = w == 1 && d == 1;
+            let (next_w, next_d) = (w.div_ceil(2), d.div_ceil(2));
+            let next = (0..next_w * next_d)
+                .map(|i| {
+                    let (x, z) = (2 * (i % next_w), 2 * (i / next_w));
+                    [(x, z), (x + 1, z), (x, z + 1), (x + 1, z + 1)]
+                        .iter()
+                        .filter(|(x, z)| *x < w && *z < d)
+                        .fold((f64::INFINITY, f64::NEG_INFINITY), |acc, (x, z)| {
+                            let (low, high) = level[z * w + x];
+                            (acc.0.min(low), acc.1.max(high))
+                        })
+                })
+                .collect();
+            self.min_max.push(level);
+            if done {
+                break;
+            }
+            level = next;
+            (w, d) = (next_w, next_d);
+        }
+    }
+
+    fn block_bounds(&self, level: usize, x: usize, z: usize) -> BoundingBox {
+        // Bounds of the cells from 2^level * (x, z) up to the next block, or the end of the grid
+        let (dx, dz) = self.cell_size();
+        let cells = 1 << level;
+        let x_end = ((x + 1) * cells).min(self.width - 1);
+        let z_end = ((z + 1) * cells).min(self.depth - 1);
+        let (low, high) = self.min_max[level][z * self.level_width(level) + x];
+        BoundingBox::new(
+            self.corner.x + (x * cells) as f64 * dx,
+            self.corner.x + x_end as f64 * dx,
+            self.corner.y + low,
+            self.corner.y + high,
+            self.corner.z + (z * cells) as f64 * dz,
+            self.corner.z + z_end as f64 * dz,
+        )
+    }
+
+    fn level_width(&self, level: usize) -> usize {
+        (self.width - 1).div_ceil(1 << level)
+    }
+
+    fn level_depth(&self, level: usize) -> usize {
+        (self.depth - 1).div_ceil(1 << level)
+    }
+
+    fn hit_block(
+        &self,
+        ray: &Ray,
+        level: usize,
+        x: usize,
+        z: usize,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<Hit> {
+        if level == 0 {
+            return self.hit_cell(ray, x, z, t_min, t_max);
+        }
+        // Visit the blocks of the next level in the order the ray passes through them
+        let mut children: Vec<(f64, usize, usize)> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+            .iter()
+            .map(|(i, j)| (2 * x + i, 2 * z + j))
+            .filter(|(x, z)| *x < self.level_width(level - 1) && *z < self.level_depth(level - 1))
+            .filter_map(|(x, z)| {
+                let (enter, _) = self
+                    .block_bounds(level - 1, x, z)
+                    .intersection(ray, t_min, t_max)?;
+                Some((enter, x, z))
+            })
+            .collect();
+        children.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let mut closest: Option<Hit> = None;
+        for (enter, x, z) in children {
+            let t_max = closest.as_ref().map_or(t_max, |hit| hit.t);
+            if enter > t_max {
+                break;
+            }
+            if let Some(hit) = self.hit_block(ray, level - 1, x, z, t_min, t_max) {
+                closest = Some(hit);
+            }
+        }
+        closest
+    }
+
+    fn hit_cell(&self, ray: &Ray, x: usize, z: usize, t_min: f64, t_max: f64) -> Option<Hit> {
+        let corners = [(x, z), (x + 1, z), (x + 1, z + 1), (x, z + 1)];
+        let near = self.hit_triangle(ray, [corners[0], corners[2], corners[1]], t_min, t_max);
+        let t_max = near.as_ref().map_or(t_max, |hit| hit.t);
+        self.hit_triangle(ray, [corners[0], corners[3], corners[2]], t_min, t_max)
+            .or(near)
+    }
+
+    fn hit_triangle(
+        &self,
+        ray: &Ray,
+        vertices: [(usize, usize); 3],
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<Hit> {
+        // Möller–Trumbore, the barycentric coordinates interpolate the normals and texture coordinates
+        let [a, b, c] = vertices;
+        let p0 = self.vertex(a.0, a.1);
+        let e1 = self.vertex(b.0, b.1) - p0;
+        let e2 = self.vertex(c.0, c.1) - p0;
+        let pvec = ray.direction.cross(&e2);
+        let det = e1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let tvec = ray.origin - p0;
+        let beta = tvec.dot(&pvec) / det;
+        if !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        let qvec = tvec.cross(&e1);
+        let gamma = ray.direction.dot(&qvec) / det;
+        if gamma < 0.0 || beta + gamma > 1.0 {
+            return None;
+        }
+        let t = e2.dot(&qvec) / det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let alpha = 1.0 - beta - gamma;
+        let normal = |(x, z): (usize, usize)| self.normals[z * self.width + x];
+        let normal = (normal(a) * alpha + normal(b) * beta + normal(c) * gamma).unit_vector();
+        let (x, z) = (
+            a.0 as f64 * alpha + b.0 as f64 * beta + c.0 as f64 * gamma,
+            a.1 as f64 * alpha + b.1 as f64 * beta + c.1 as f64 * gamma,
+        );
+        // u increases along x and v against z, so that the top of an image is at the lowest z like for the heights
+        let u = x / (self.width - 1) as f64;
+        let v = 1.0 - z / (self.depth - 1) as f64;
+        let tangent = Vec3::new(1.0, 0.0, 0.0);
+        let tangent = (tangent - normal * normal.dot(&tangent)).unit_vector();
+        Some(
+            Hit::new(ray.at(t), normal, t, ray)
+                .with_uv(u, v)
+                .with_tangent(tangent, 1.0),
+        )
+    }
+}
+impl Hittable for Heightfield {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let top = self.min_max.len() - 1;
+        self.get_bounds().intersection(ray, t_min, t_max)?;
+        self.hit_block(ray, top, 0, 0, t_min, t_max)
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        self.block_bounds(self.min_max.len() - 1, 0, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        // The closest hit found through the min/max mipmap is the closest hit of all cells
+        let mut rng = StdRng::seed_from_u64(7);
+        let (width, depth) = (19, 12);
+        let heights = (0..width * depth).map(|_| rng.gen::<f64>()).collect();
+        let heightfield = Heightfield::new(
+            Vec3::new(-3.0, -0.5, -2.0),
+            Vec3::new(6.0, 1.5, 4.0),
+            width,
+            depth,
+            heights,
+        );
+        let random_point = |rng: &mut StdRng, scale: f64| {
+            Vec3::new(
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+                rng.gen_range(-scale..scale),
+            )
+        };
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = random_point(&mut rng, 6.0);
+            let ray = Ray {
+                origin,
+                direction: random_point(&mut rng, 2.0) - origin,
+            };
+            let brute_force = (0..width - 1)
+                .flat_map(|x| (0..depth - 1).map(move |z| (x, z)))
+                .filter_map(|(x, z)| heightfield.hit_cell(&ray, x, z, 0.0, f64::INFINITY))
+                .min_by(|a, b| a.t.total_cmp(&b.t));
+            match (heightfield.hit(&ray, 0.0, f64::INFINITY), brute_force) {
+                (None, None) => {}
+                (Some(hit), Some(expected)) => {
+                    assert!((hit.t - expected.t).abs() < 1e-9);
+                    assert!((hit.normal - expected.normal).length() < 1e-9);
+                    hits += 1;
+                }
+                (hit, expected) => panic!(
+                    "Traversal found {:?}, brute force {:?}",
+                    hit.map(|h| h.t),
+                    expected.map(|h| h.t)
+                ),
+            }
+        }
+        // Enough of the rays hit the terrain for the comparison to mean something
+        assert!(hits > 500);
+    }
+
+    #[test]
+    fn small_image() {
+        let path = std::env::temp_dir().join("ray_tracer_heightfield.ppm");
+        std::fs::write(&path, "P3 1 1 255\n128 128 128\n").unwrap();
+        match Heightfield::from_file(&path, Vec3::z(), Vec3::new(1.0, 1.0, 1.0)) {
+            Ok(_) => panic!("Heightfield from a single pixel"),
+            Err(e) => assert!(e.to_string().contains("smaller than 2 by 2")),
+        }
+    }
+}
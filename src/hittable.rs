@@ -1,7 +1,7 @@
 use crate::object::Object;
 use crate::ray::Ray;
 use crate::three_d_tree::Axis;
-use crate::util::{self, EPSILON};
+use crate::util;
 use crate::vec3::Vec3;
 
 #[derive(Clone)]
@@ -10,6 +10,12 @@ pub struct Hit {
     pub normal: Vec3, // Always points opposite to hit ray
     pub t: f64,
     pub front_face: bool, // If the face that was hit was the front, i.e. outward face
+    pub barycentric: Option<Vec3>, // Weights of the vertices of the hit primitive, if it has any
+    pub uv: (f64, f64),   // Texture coordinates of the hit
+    // Direction in which u increases and whether v increases along (1.0) or against (-1.0) normal x tangent
+    // The normal used for this is the outward normal, not the one pointing against the ray
+    pub tangent: Option<(Vec3, f64)>,
+    pub color: Option<Vec3>, // Color interpolated from the vertices of the hit primitive, if it has any
 }
 
 impl Hit {
@@ -20,6 +26,33 @@ impl Hit {
             normal,
             t,
             front_face,
+            barycentric: None,
+            uv: (0.0, 0.0),
+            tangent: None,
+            color: None,
+        }
+    }
+    pub fn with_barycentric(mut self, barycentric: Vec3) -> Hit {
+        self.barycentric = Some(barycentric);
+        self
+    }
+    pub fn with_uv(mut self, u: f64, v: f64) -> Hit {
+        self.uv = (u, v);
+        self
+    }
+    pub fn with_tangent(mut self, tangent: Vec3, handedness: f64) -> Hit {
+        self.tangent = Some((tangent, handedness));
+        self
+    }
+    pub fn with_color(mut self, color: Vec3) -> Hit {
+        self.color = Some(color);
+        self
+    }
+    pub fn outward_normal(&self) -> Vec3 {
+        if self.front_face {
+            self.normal
+        } else {
+            -self.normal
         }
     }
     pub fn to_face_normal(ray: &Ray, outward_normal: Vec3) -> (bool, Vec3) {
@@ -67,6 +100,15 @@ impl BoundingBox {
     pub fn higher(&self) -> Vec3 {
         Vec3::new(self.x_high, self.y_high, self.z_high)
     }
+    pub fn is_finite(&self) -> bool {
+        // Infinite shapes like planes have infinite bounds
+        self.lower().x.is_finite()
+            && self.lower().y.is_finite()
+            && self.lower().z.is_finite()
+            && self.higher().x.is_finite()
+            && self.higher().y.is_finite()
+            && self.higher().z.is_finite()
+    }
     pub fn encloses_point(&self, p: &Vec3) -> bool {
         self.x_low <= p.x
             && p.x <= self.x_high
@@ -75,10 +117,15 @@ impl BoundingBox {
             && self.z_low <= p.z
             && p.z <= self.z_high
     }
+    #[allow(dead_code)]
     pub fn encloses_bounds(&self, bounds: BoundingBox) -> bool {
         self.encloses_point(&bounds.lower()) && self.encloses_point(&bounds.higher())
     }
     pub fn intersects(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersection(ray, t_min, t_max).is_some()
+    }
+    pub fn intersection(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        // Gives the part of the ray between t_min and t_max that is inside of the box
         // Branchless box intersection https://tavianator.com/2011/ray_box.html
         let tx1 = ray.intersect_axis_plane(&Axis::X, self.x_low);
         let tx2 = ray.intersect_axis_plane(&Axis::X, self.x_high);
@@ -98,16 +145,59 @@ impl BoundingBox {
         let tmin = tmin.max(tz1.min(tz2));
         let tmax = tmax.min(tz1.max(tz2));
 
-        return tmax >= t_min.max(tmin) && tmin < t_max;
+        if tmax >= t_min.max(tmin) && tmin < t_max {
+            Some((tmin.max(t_min), tmax.min(t_max)))
+        } else {
+            None
+        }
     }
 }
 
+// Part of a ray that is inside of a closed shape
+#[derive(Clone)]
+pub struct Interval {
+    pub enter: Hit,
+    pub exit: Hit,
+}
+
 pub trait Hittable {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit>;
     fn get_bounds(&self) -> BoundingBox;
+    // All the parts of the line through the ray that are inside of the shape, sorted along the ray
+    // Only makes sense for closed shapes, the default collects every hit and pairs them up as enter and exit
+    // That goes wrong when a ray grazes the surface or hits it twice within EPSILON, so shapes should override it
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let mut hits = Vec::new();
+        let mut t_min = -util::INFINITY;
+        while let Some(hit) = self.hit(ray, t_min, util::INFINITY) {
+            t_min = hit.t + util::EPSILON;
+            hits.push(hit);
+        }
+        hits.chunks_exact(2)
+            .map(|pair| Interval {
+                enter: pair[0].clone(),
+                exit: pair[1].clone(),
+            })
+            .collect()
+    }
+}
+
+// Counts the work done by an ObjectContainer for a single ray, used to visualize the cost of the acceleration structure
+#[derive(Default)]
+pub struct TraversalStats {
+    pub nodes_visited: usize,
+    pub intersection_tests: usize,
 }
+
 pub trait ObjectContainer: Sync {
-    fn get_object_hit(&self, ray: &Ray) -> Option<(&Object, Hit)>;
+    fn get_object_hit(&self, ray: &Ray) -> Option<(&Object, Hit)> {
+        self.get_object_hit_with_stats(ray, &mut TraversalStats::default())
+    }
+    fn get_object_hit_with_stats(
+        &self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<(&Object, Hit)>;
 }
 
 pub fn hit_list<'a, T: Hittable>(
@@ -131,6 +221,7 @@ pub fn hit_list<'a, T: Hittable>(
     closest
 }
 
+#[allow(dead_code)]
 pub fn hit_list_default<'a, T: Hittable>(
     hittables: &Vec<&'a T>,
     ray: &Ray,
@@ -138,12 +229,19 @@ pub fn hit_list_default<'a, T: Hittable>(
     hit_list(hittables, ray, util::EPSILON, util::INFINITY)
 }
 
+#[allow(dead_code)]
 pub struct HittableList<'a> {
     pub objects: &'a Vec<Object>,
 }
 impl<'a> ObjectContainer for HittableList<'a> {
-    fn get_object_hit(&self, ray: &Ray) -> Option<(&Object, Hit)> {
-        return hit_list_default(&self.objects.iter().collect(), ray);
+    fn get_object_hit_with_stats(
+        &self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<(&Object, Hit)> {
+        stats.nodes_visited += 1;
+        stats.intersection_tests += self.objects.len();
+        hit_list_default(&self.objects.iter().collect(), ray)
     }
 }
 unsafe impl<'a> Sync for HittableList<'a> {}
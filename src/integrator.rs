@@ -0,0 +1,13 @@
+use crate::hittable::Hit;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+pub trait Integrator: Sync {
+    // Gets the color of a specific ray in the scene and passes back the first hit
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>);
+    // Turns the mean of the colors returned by ray_color for a pixel into its RGB color
+    fn pixel_color(&self, mean: Vec3) -> Vec3 {
+        mean
+    }
+}
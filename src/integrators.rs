@@ -0,0 +1,518 @@
+use crate::hittable::{Hit, TraversalStats};
+use crate::integrator::Integrator;
+use crate::light::LightInfo;
+use crate::material::{Lobe, Scatter};
+use crate::materials::RGB_WAVELENGTHS;
+use crate::medium::Medium;
+use crate::object::Object;
+use crate::ray::Ray;
+use crate::scene::Scene;
+use crate::spectrum;
+use crate::spectrum::SampledWavelengths;
+use crate::util;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+pub struct PathIntegrator {
+    // Separate limits per kind of bounce, e.g. glass needs many transmission bounces while diffuse light fades quickly
+    pub max_diffuse_bounces: i32,
+    pub max_specular_bounces: i32,
+    pub max_transmission_bounces: i32,
+    pub max_volume_bounces: i32, // Random walks through dense media need many more bounces than surfaces
+    pub min_bounces: i32, // Number of bounces before paths can get terminated by russian roulette
+    pub max_light_val: f64,
+    pub spectral: bool, // Trace wavelength samples instead of RGB colors, needed for accurate dispersion
+}
+
+// Number of bounces a path has taken so far
+#[derive(Clone, Copy, Default)]
+struct PathDepth {
+    total: i32,
+    diffuse: i32,
+    specular: i32,
+    transmission: i32,
+    volume: i32,
+}
+impl PathDepth {
+    fn after(self, lobe: Lobe) -> PathDepth {
+        let mut next = self;
+        next.total += 1;
+        match lobe {
+            Lobe::Diffuse => next.diffuse += 1,
+            Lobe::Specular => next.specular += 1,
+            Lobe::Transmission => next.transmission += 1,
+            Lobe::Volume => next.volume += 1,
+        }
+        next
+    }
+}
+
+// State of a path that gets passed on to the next bounce
+#[derive(Clone, Copy)]
+struct PathState<'a> {
+    depth: PathDepth,
+    throughput: Vec3,
+    dist_so_far: f64,
+    medium: Option<&'a dyn Medium>, // Medium the ray travels through
+    channel: Option<usize>, // Color channel the path follows after a dispersive surface when tracing RGB
+}
+
+impl PathIntegrator {
+    fn exceeds_limits(&self, depth: &PathDepth) -> bool {
+        depth.diffuse > self.max_diffuse_bounces
+            || depth.specular > self.max_specular_bounces
+            || depth.transmission > self.max_transmission_bounces
+            || depth.volume > self.max_volume_bounces
+    }
+
+    fn survival_probability(&self, depth: &PathDepth, throughput: Vec3) -> f64 {
+        // Paths that can only contribute little to the final color are likely to be terminated
+        if depth.total <= self.min_bounces {
+            1.0
+        } else {
+            throughput.max_component().min(1.0)
+        }
+    }
+
+    fn compress(&self, color: Vec3) -> Vec3 {
+        // Fixes issues when objects become too bright
+        color.ln_1p().clamp(Vec3::new(
+            self.max_light_val,
+            self.max_light_val,
+            self.max_light_val,
+        ))
+    }
+
+    fn ray_color_per_light<'a>(
+        &self,
+        ray: &Ray,
+        world: &Scene<'a>,
+        state: PathState<'a>,
+        wavelengths: Option<&mut SampledWavelengths>,
+    ) -> (Vec<Vec3>, Option<Hit>) {
+        // Function that gets the color for a given ray in the scene for every light source and passes back the first hit
+        // Calculate hit once, then get info for all lights
+        // In spectral mode the colors are the values of the spectrum at the sampled wavelengths
+        let object_hit = world.objects.get_object_hit(ray);
+        let medium = match state.medium {
+            None => {
+                return self.surface_color_per_light(ray, object_hit, world, state, wavelengths)
+            }
+            Some(medium) => medium,
+        };
+        // Inside of a medium the ray can scatter before it reaches the next surface
+        let t_max = object_hit.as_ref().map_or(util::INFINITY, |(_, hit)| hit.t);
+        let sample = medium.sample(ray, t_max, state.throughput);
+        let weight = match &wavelengths {
+            None => sample.weight,
+            Some(wavelengths) => wavelengths.upsample_rgb(sample.weight),
+        };
+        let state = PathState {
+            throughput: state.throughput * weight,
+            ..state
+        };
+        let (colors, hit) = match sample.t {
+            Some(t) => (
+                self.medium_color_per_light(ray, ray.at(t), medium, world, state, wavelengths),
+                None,
+            ),
+            None => self.surface_color_per_light(ray, object_hit, world, state, wavelengths),
+        };
+        (colors.iter().map(|c| *c * weight).collect(), hit)
+    }
+
+    fn surface_color_per_light<'a>(
+        &self,
+        ray: &Ray,
+        object_hit: Option<(&'a Object, Hit)>,
+        world: &Scene<'a>,
+        state: PathState<'a>,
+        mut wavelengths: Option<&mut SampledWavelengths>,
+    ) -> (Vec<Vec3>, Option<Hit>) {
+        match object_hit {
+            None => (
+                world
+                    .lights
+                    .iter()
+                    .map(|light| {
+                        let color = light.no_hit(ray, state.dist_so_far);
+                        match &wavelengths {
+                            None => color,
+                            Some(wavelengths) => wavelengths.upsample_rgb(color),
+                        }
+                    })
+                    .collect(),
+                None,
+            ),
+            Some((obj, hit)) => {
+                let hit = obj.shading_hit(hit);
+                let mut channel = state.channel;
+                let scatter = match wavelengths.as_deref_mut() {
+                    None if obj.material.depends_on_wavelength() => {
+                        // The channel is picked once per path, later surfaces only see light of that channel
+                        let (c, weight) = match state.channel {
+                            Some(c) => (c, 1.0),
+                            None => (rand::thread_rng().gen_range(0..3), 3.0),
+                        };
+                        channel = Some(c);
+                        let mut mask = Vec3::z();
+                        match c {
+                            0 => mask.x = weight,
+                            1 => mask.y = weight,
+                            _ => mask.z = weight,
+                        }
+                        obj.material
+                            .scatter_wavelength(ray, &hit, RGB_WAVELENGTHS[c])
+                            .map(|scatter| Scatter {
+                                attenuation: scatter.attenuation * mask,
+                                ..scatter
+                            })
+                    }
+                    None => obj.material.scatter(ray, &hit),
+                    Some(wavelengths) => {
+                        if obj.material.depends_on_wavelength() {
+                            wavelengths.terminate_secondary();
+                        }
+                        obj.material
+                            .scatter_wavelength(ray, &hit, wavelengths.hero())
+                            .map(|scatter| Scatter {
+                                attenuation: wavelengths.upsample_rgb(scatter.attenuation),
+                                ..scatter
+                            })
+                    }
+                };
+                // Rays that go through the surface of an object with a medium inside enter or leave that medium
+                let interior = obj.interior.as_deref().or(obj.material.interior());
+                let other_medium = match interior {
+                    Some(interior) if hit.front_face => Some(interior),
+                    Some(_) => world.medium,
+                    None => state.medium,
+                };
+                let next_medium = match &scatter {
+                    Some(scatter) if scatter.lobe == Lobe::Transmission => other_medium,
+                    _ => state.medium,
+                };
+                let next_colors = self.scattered_color_per_light(
+                    scatter,
+                    world,
+                    PathState {
+                        dist_so_far: state.dist_so_far + (hit.p - ray.origin).length(),
+                        medium: next_medium,
+                        channel,
+                        ..state
+                    },
+                    wavelengths.as_deref_mut(),
+                );
+                (
+                    next_colors
+                        .iter()
+                        .zip(world.lights.iter())
+                        .map(|(next_color, light)| {
+                            let light_info = light.at(hit.p, world.objects, state.dist_so_far);
+                            // Light from behind the surface comes through the medium on the other side
+                            let light_medium = if light_info.direction.dot(&hit.normal) < 0.0 {
+                                other_medium
+                            } else {
+                                state.medium
+                            };
+                            let light_info =
+                                self.light_through_medium(light_info, hit.p, light_medium, world);
+                            match &wavelengths {
+                                None => obj.material.get_color(ray, light_info, &hit, *next_color),
+                                // The direct light is computed in RGB, the scattered light is already a spectrum
+                                Some(wavelengths) => {
+                                    wavelengths.upsample_rgb(obj.material.get_color(
+                                        ray,
+                                        light_info,
+                                        &hit,
+                                        Vec3::z(),
+                                    )) + *next_color
+                                }
+                            }
+                        })
+                        .collect(),
+                    Option::Some(hit),
+                )
+            }
+        }
+    }
+
+    fn medium_color_per_light<'a>(
+        &self,
+        ray: &Ray,
+        p: Vec3,
+        medium: &'a dyn Medium,
+        world: &Scene<'a>,
+        state: PathState<'a>,
+        mut wavelengths: Option<&mut SampledWavelengths>,
+    ) -> Vec<Vec3> {
+        // Gets the color of the light that scatters in the medium at point p
+        // The phase function is sampled exactly, so the scattered ray keeps its full weight
+        let scatter = Scatter {
+            ray: Ray {
+                origin: p,
+                direction: medium.phase().sample(ray.direction),
+            },
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            lobe: Lobe::Volume,
+        };
+        let next_colors = self.scattered_color_per_light(
+            Some(scatter),
+            world,
+            PathState {
+                dist_so_far: state.dist_so_far + (p - ray.origin).length(),
+                ..state
+            },
+            wavelengths.as_deref_mut(),
+        );
+        next_colors
+            .iter()
+            .zip(world.lights.iter())
+            .map(|(next_color, light)| {
+                let light_info = self.light_through_medium(
+                    light.at(p, world.objects, state.dist_so_far),
+                    p,
+                    Some(medium),
+                    world,
+                );
+                // Lights without a direction, like the ambient light, don't light the medium directly
+                let color = if light_info.color.near_zero() {
+                    Vec3::z()
+                } else {
+                    light_info.color * medium.phase().eval(ray.direction, light_info.direction)
+                };
+
+                match &wavelengths {
+                    None => color + *next_color,
+                    Some(wavelengths) => wavelengths.upsample_rgb(color) + *next_color,
+                }
+            })
+            .collect()
+    }
+
+    fn light_through_medium(
+        &self,
+        light_info: LightInfo,
+        p: Vec3,
+        medium: Option<&dyn Medium>,
+        world: &Scene,
+    ) -> LightInfo {
+        // Light gets attenuated by the medium up to the next surface or the light itself
+        let medium = match medium {
+            Some(medium) if !light_info.color.near_zero() => medium,
+            _ => return light_info,
+        };
+        let shadow_ray = Ray {
+            origin: p,
+            direction: light_info.direction,
+        };
+        let t_max = world
+            .objects
+            .get_object_hit(&shadow_ray)
+            .map_or(light_info.distance, |(_, hit)| {
+                hit.t.min(light_info.distance)
+            });
+        LightInfo {
+            color: light_info.color * medium.transmittance(&shadow_ray, t_max),
+            ..light_info
+        }
+    }
+
+    fn scattered_color_per_light<'a>(
+        &self,
+        scatter: Option<Scatter>,
+        world: &Scene<'a>,
+        state: PathState<'a>,
+        wavelengths: Option<&mut SampledWavelengths>,
+    ) -> Vec<Vec3> {
+        // Gets the color of the scattered ray, weighted by the attenuation of the scatter
+        let black = || world.lights.iter().map(|_| Vec3::z()).collect();
+        let scatter = match scatter {
+            None => return black(),
+            Some(scatter) => scatter,
+        };
+        let depth = state.depth.after(scatter.lobe);
+        if self.exceeds_limits(&depth) {
+            return black();
+        }
+        let throughput = state.throughput * scatter.attenuation;
+        let survival = self.survival_probability(&depth, throughput);
+        if survival <= 0.0 || rand::thread_rng().gen::<f64>() >= survival {
+            return black();
+        }
+        // Surviving paths are boosted so that the expected color stays the same
+        let weight = scatter.attenuation / survival;
+        self.ray_color_per_light(
+            &scatter.ray,
+            world,
+            PathState {
+                depth,
+                throughput: throughput / survival,
+                ..state
+            },
+            wavelengths,
+        )
+        .0
+        .iter()
+        .map(|c| *c * weight)
+        .collect()
+    }
+}
+impl Integrator for PathIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        let mut wavelengths = self
+            .spectral
+            .then(|| SampledWavelengths::sample_visible(rand::thread_rng().gen()));
+        let (rays, hit) = self.ray_color_per_light(
+            ray,
+            world,
+            PathState {
+                depth: PathDepth::default(),
+                throughput: Vec3::new(1.0, 1.0, 1.0),
+                dist_so_far: 0.0,
+                medium: world.medium,
+                channel: None,
+            },
+            wavelengths.as_mut(),
+        );
+        let color = rays.iter().fold(Vec3::z(), |acc, x| acc + *x);
+        match wavelengths {
+            None => (self.compress(color), hit),
+            // Samples stay in XYZ until all of them are averaged in pixel_color
+            Some(wavelengths) => {
+                // Scaling down bright samples keeps their hue while avoiding fireflies
+                let xyz = wavelengths.estimate_xyz(color);
+                let max_luminance = self.max_light_val.exp_m1();
+                if xyz.y > max_luminance {
+                    (xyz * (max_luminance / xyz.y), hit)
+                } else {
+                    (xyz, hit)
+                }
+            }
+        }
+    }
+
+    fn pixel_color(&self, mean: Vec3) -> Vec3 {
+        if self.spectral {
+            self.compress(spectrum::xyz_to_linear_srgb(mean))
+        } else {
+            mean
+        }
+    }
+}
+
+// The integrators below are meant for debugging scenes, they ignore materials and lights
+
+#[allow(dead_code)]
+pub struct NormalIntegrator;
+impl Integrator for NormalIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        match world.objects.get_object_hit(ray) {
+            None => (Vec3::z(), None),
+            Some((obj, hit)) => {
+                let hit = obj.shading_hit(hit);
+                (util::normal_to_color(&hit.normal), Some(hit))
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct DepthIntegrator {
+    pub max_depth: f64, // Distance at which the image becomes black
+}
+impl Integrator for DepthIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        match world.objects.get_object_hit(ray) {
+            None => (Vec3::z(), None),
+            Some((_, hit)) => {
+                let dist = (hit.p - ray.origin).length();
+                let v = 1.0 - (dist / self.max_depth).min(1.0);
+                (Vec3::new(v, v, v), Some(hit))
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct AmbientOcclusionIntegrator {
+    pub samples: i32,
+    pub max_dist: f64, // Occluders further away than this don't count
+}
+impl Integrator for AmbientOcclusionIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        match world.objects.get_object_hit(ray) {
+            None => (Vec3::new(1.0, 1.0, 1.0), None),
+            Some((_, hit)) => {
+                let unoccluded = (0..self.samples)
+                    .filter(|_| {
+                        // Cosine weighted direction on the hemisphere around the normal
+                        let direction = (hit.normal + Vec3::random_unit_vector()).unit_vector();
+                        match world.objects.get_object_hit(&Ray {
+                            origin: hit.p,
+                            direction,
+                        }) {
+                            None => true,
+                            Some((_, occluder)) => occluder.t > self.max_dist,
+                        }
+                    })
+                    .count();
+                let v = unoccluded as f64 / self.samples as f64;
+                (Vec3::new(v, v, v), Some(hit))
+            }
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct BarycentricIntegrator;
+impl Integrator for BarycentricIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        match world.objects.get_object_hit(ray) {
+            None => (Vec3::z(), None),
+            // Primitives without vertices are shown in gray
+            Some((_, hit)) => (
+                hit.barycentric.unwrap_or(Vec3::new(0.5, 0.5, 0.5)),
+                Some(hit),
+            ),
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub struct ObjectIdIntegrator;
+impl Integrator for ObjectIdIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        match world.objects.get_object_hit(ray) {
+            None => (Vec3::z(), None),
+            // The address of the object is unique as long as the scene lives
+            Some((obj, hit)) => (util::id_to_color(obj as *const Object as usize), Some(hit)),
+        }
+    }
+}
+
+#[allow(dead_code)]
+pub enum CostMetric {
+    NodesVisited,
+    IntersectionTests,
+}
+
+#[allow(dead_code)]
+pub struct TraversalCostIntegrator {
+    pub metric: CostMetric,
+    pub max_cost: usize, // Cost that is shown as fully red
+}
+impl Integrator for TraversalCostIntegrator {
+    fn ray_color(&self, ray: &Ray, world: &Scene) -> (Vec3, Option<Hit>) {
+        let mut stats = TraversalStats::default();
+        let hit = world
+            .objects
+            .get_object_hit_with_stats(ray, &mut stats)
+            .map(|(_, hit)| hit);
+        let cost = match self.metric {
+            CostMetric::NodesVisited => stats.nodes_visited,
+            CostMetric::IntersectionTests => stats.intersection_tests,
+        };
+        (util::heatmap_color(cost as f64 / self.max_cost as f64), hit)
+    }
+}
@@ -11,4 +11,5 @@ pub trait Light: Sync {
 pub struct LightInfo {
     pub color: Vec3,
     pub direction: Vec3, // Direction of the light from the hit
+    pub distance: f64,   // Distance of the light from the hit
 }
@@ -2,6 +2,7 @@ use crate::hittable::ObjectContainer;
 use crate::light::Light;
 use crate::light::LightInfo;
 use crate::ray::Ray;
+use crate::util;
 use crate::vec3::Vec3;
 
 pub struct PointLight {
@@ -16,36 +17,136 @@ impl Light for PointLight {
 
     fn at(&self, origin: Vec3, world: &dyn ObjectContainer, dist_so_far: f64) -> LightInfo {
         let direction = (self.position - origin).unit_vector();
+        let distance = (self.position - origin).length();
+        LightInfo {
+            color: if is_unoccluded(origin, direction, distance, world) {
+                (self.color * self.intensity) * self.falloff(distance + dist_so_far)
+            } else {
+                Vec3::z()
+            },
+            direction,
+            distance,
+        }
+    }
+
+    fn no_hit(&self, ray: &Ray, _dist_so_far: f64) -> Vec3 {
+        point_glow(self.position, self.color * self.intensity, ray)
+    }
+}
+unsafe impl Sync for PointLight {}
+
+fn is_unoccluded(
+    origin: Vec3,
+    direction: Vec3,
+    distance: f64,
+    world: &dyn ObjectContainer,
+) -> bool {
+    // Only objects between the origin and the light block it, the direction has to be a unit vector
+    // Objects that don't cast shadows, like the boundaries of media, are skipped
+    let mut shadow_origin = origin;
+    let mut distance_left = distance;
+    loop {
         match world.get_object_hit(&Ray {
             direction,
-            origin: origin,
+            origin: shadow_origin,
         }) {
-            None => LightInfo {
-                color: (self.color * self.intensity)
-                    * self.falloff((self.position - origin).length() + dist_so_far),
-                direction,
+            Some((obj, hit)) if hit.t < distance_left => {
+                if obj.material.casts_shadow() {
+                    return false;
+                }
+                shadow_origin = hit.p;
+                distance_left -= hit.t;
+            }
+            _ => return true,
+        }
+    }
+}
+
+fn point_glow(position: Vec3, color: Vec3, ray: &Ray) -> Vec3 {
+    // Gives the light a "body" which looks good in reflections
+    let dist = ((ray.origin + ray.direction * (position - ray.origin).dot(&ray.direction))
+        - position)
+        .length();
+    ((color / (position - ray.origin).length().powf(2.0)) / dist.powf(5.0))
+        * (position - ray.origin).unit_vector().dot(&ray.direction)
+}
+
+// Point light that only shines into a cone, fading out between the inner and the outer angle
+pub struct SpotLight {
+    pub position: Vec3,
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f64,
+    pub inner_angle: f64, // In radians, from the direction to the edge of the cone
+    pub outer_angle: f64,
+}
+impl SpotLight {
+    fn cone_factor(&self, to_point: Vec3) -> f64 {
+        let cos = self.direction.unit_vector().dot(&to_point);
+        let (cos_inner, cos_outer) = (self.inner_angle.cos(), self.outer_angle.cos());
+        if cos_inner - cos_outer <= 0.0 {
+            return if cos >= cos_outer { 1.0 } else { 0.0 };
+        }
+        let t = ((cos - cos_outer) / (cos_inner - cos_outer)).clamp(0.0, 1.0);
+        t * t
+    }
+}
+impl Light for SpotLight {
+    fn falloff(&self, dist: f64) -> f64 {
+        1.0 / dist.powf(2.0)
+    }
+
+    fn at(&self, origin: Vec3, world: &dyn ObjectContainer, dist_so_far: f64) -> LightInfo {
+        let direction = (self.position - origin).unit_vector();
+        let distance = (self.position - origin).length();
+        let cone = self.cone_factor(-direction);
+        LightInfo {
+            color: if cone > 0.0 && is_unoccluded(origin, direction, distance, world) {
+                (self.color * self.intensity) * (cone * self.falloff(distance + dist_so_far))
+            } else {
+                Vec3::z()
             },
-            Some(_) => LightInfo {
-                color: Vec3::z(),
-                direction,
+            direction,
+            distance,
+        }
+    }
+
+    fn no_hit(&self, ray: &Ray, _dist_so_far: f64) -> Vec3 {
+        let cone = self.cone_factor((ray.origin - self.position).unit_vector());
+        point_glow(self.position, self.color * (self.intensity * cone), ray)
+    }
+}
+unsafe impl Sync for SpotLight {}
+
+// Light from very far away like the sun, all of it comes from the same direction without falloff
+pub struct DirectionalLight {
+    pub direction: Vec3, // Direction the light travels in
+    pub color: Vec3,
+    pub intensity: f64,
+}
+impl Light for DirectionalLight {
+    fn falloff(&self, _dist: f64) -> f64 {
+        1.0
+    }
+
+    fn at(&self, origin: Vec3, world: &dyn ObjectContainer, _dist_so_far: f64) -> LightInfo {
+        let direction = -self.direction.unit_vector();
+        LightInfo {
+            color: if is_unoccluded(origin, direction, util::INFINITY, world) {
+                self.color * self.intensity
+            } else {
+                Vec3::z()
             },
+            direction,
+            distance: util::INFINITY,
         }
     }
 
-    fn no_hit(&self, ray: &Ray, dist_so_far: f64) -> Vec3 {
-        // Gives the light a "body" which looks good in reflections
-        let dist = ((ray.origin
-            + ray.direction * (self.position - ray.origin).dot(&ray.direction))
-            - self.position)
-            .length();
-        (((self.color * self.intensity) / (self.position - ray.origin).length().powf(2.0))
-            / dist.powf(5.0))
-            * (self.position - ray.origin)
-                .unit_vector()
-                .dot(&ray.direction)
+    fn no_hit(&self, _ray: &Ray, _dist_so_far: f64) -> Vec3 {
+        Vec3::z()
     }
 }
-unsafe impl Sync for PointLight {}
+unsafe impl Sync for DirectionalLight {}
 
 pub struct AmbientLight {
     pub color_from_ray: Box<dyn Fn(&Ray) -> Vec3>,
@@ -59,10 +160,11 @@ impl Light for AmbientLight {
         LightInfo {
             color: Vec3::z(),
             direction: Vec3::z(),
+            distance: util::INFINITY,
         }
     }
 
-    fn no_hit(&self, ray: &Ray, dist_so_far: f64) -> Vec3 {
+    fn no_hit(&self, ray: &Ray, _dist_so_far: f64) -> Vec3 {
         (self.color_from_ray)(ray)
     }
 }
@@ -76,3 +178,32 @@ pub fn sky_background(brightness: f64, ray: &Ray) -> Vec3 {
         z: 1.0 * brightness,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HittableList;
+    use crate::materials::Lambertian;
+    use crate::object::Object;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn shadows_end_at_the_light() {
+        // A sphere between the origin and the light casts a shadow, one behind the light doesn't
+        let objects = vec![Object::new(
+            Box::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+            Box::new(Sphere {
+                center: Vec3::new(0.0, 5.0, 0.0),
+                radius: 1.0,
+            }),
+        )];
+        let world = HittableList { objects: &objects };
+        let light = |y: f64| PointLight {
+            position: Vec3::new(0.0, y, 0.0),
+            color: Vec3::new(1.0, 1.0, 1.0),
+            intensity: 1.0,
+        };
+        assert!(light(2.0).at(Vec3::z(), &world, 0.0).color.x > 0.0);
+        assert!(light(8.0).at(Vec3::z(), &world, 0.0).color.near_zero());
+    }
+}
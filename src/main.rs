@@ -1,18 +1,45 @@
 extern crate rayon;
 
+mod aa_box;
+mod alpha_mask;
 mod camera;
+mod cone;
+mod csg;
+mod curve;
+mod cylinder;
+mod disk;
+mod gltf;
+mod heightfield;
 mod hittable;
+mod integrator;
+mod integrators;
 mod light;
 mod lights;
 mod material;
 mod materials;
+mod media;
+mod medium;
 mod mesh;
+mod microfacet;
+mod normal_map;
 mod object;
+mod perlin;
+mod plane;
 mod polygon;
+mod polynomial;
+mod quad;
+mod quadric;
 mod ray;
 mod scene;
+mod sdf;
+mod sdfs;
+mod spectrum;
 mod sphere;
+mod subdivision;
+mod texture;
+mod textures;
 mod three_d_tree;
+mod torus;
 mod triangle;
 mod util;
 mod vec3;
@@ -27,18 +54,27 @@ use three_d_tree::build_tdtree;
 use util::EPSILON;
 
 use camera::Camera;
+use csg::Csg;
+use integrator::Integrator;
+use integrators::PathIntegrator;
 use light::Light;
 use lights::AmbientLight;
 use lights::PointLight;
 use mesh::Mesh;
 use object::Object;
+use plane::Plane;
 use ray::Ray;
 use scene::Scene;
 use sphere::Sphere;
 use triangle::Triangle;
 use vec3::Vec3;
 
-const MAX_BOUNCES: i32 = 10;
+const MAX_DIFFUSE_BOUNCES: i32 = 4;
+const MAX_SPECULAR_BOUNCES: i32 = 10;
+const MAX_TRANSMISSION_BOUNCES: i32 = 16;
+const MAX_VOLUME_BOUNCES: i32 = 128;
+const MIN_BOUNCES: i32 = 3;
+const SPECTRAL_RENDERING: bool = false;
 const BASE_SAMPLES_PER_PIXEL: i32 = 30;
 const DO_DYNAMIC_OVERSAMPLING: bool = true;
 const MAX_DYNAMIC_OVERSAMPLING_FACTOR: i32 = 30;
@@ -54,69 +90,6 @@ static ASPECT_RATIO: f64 = 16.0 / 9.0;
 static IMAGE_HEIGHT: usize = 1000;
 static IMAGE_WIDTH: usize = (IMAGE_HEIGHT as f64 * ASPECT_RATIO) as usize;
 
-fn ray_color_per_light(
-    ray: &Ray,
-    world: &Scene,
-    bounces_left: i32,
-    dist_so_far: f64,
-) -> (Vec<Vec3>, Option<Hit>) {
-    // Function that gets the color for a given ray in the scene for every light source and passes back the first hit
-    if bounces_left == 0 {
-        return (world.lights.iter().map(|_| Vec3::z()).collect(), None);
-    }
-    // Calculate hit once, then get info for all lights
-    let object_hit = world.objects.get_object_hit(ray);
-    match object_hit {
-        None => (
-            world
-                .lights
-                .iter()
-                .map(|light| light.no_hit(&ray, dist_so_far))
-                .collect(),
-            None,
-        ),
-        Some((obj, hit)) => (
-            match obj.material.scatter(&ray, &hit) {
-                None => world.lights.iter().map(|_| Vec3::z()).collect(),
-                Some(next_ray) => {
-                    ray_color_per_light(
-                        &next_ray,
-                        world,
-                        bounces_left - 1,
-                        dist_so_far + (hit.p - ray.origin).length(),
-                    )
-                    .0
-                }
-            }
-            .iter()
-            .zip(world.lights.iter())
-            .map(|(next_color, light)| {
-                obj.material.get_color(
-                    &ray,
-                    light.at(hit.p, world.objects, dist_so_far),
-                    &hit,
-                    *next_color,
-                )
-            })
-            .collect(),
-            Option::Some(hit),
-        ),
-    }
-}
-
-fn ray_color(ray: &Ray, world: &Scene, bounces_left: i32) -> (Vec3, Option<Hit>) {
-    // Gets the color of a specific ray in the scene
-    let (rays, hit) = ray_color_per_light(ray, world, bounces_left, 0.0);
-    (
-        rays.iter()
-            .fold(Vec3::z(), |acc, x| acc + *x)
-            // Fixes issues when objects become too bright
-            .ln_1p()
-            .clamp(Vec3::new(MAX_LIGHT_VAL, MAX_LIGHT_VAL, MAX_LIGHT_VAL)),
-        hit,
-    )
-}
-
 fn ray_from_image_pos(i: usize, j: usize, camera: &Camera) -> Ray {
     let horizontal_frac = (i as f64 + rand::thread_rng().gen::<f64>()) / (IMAGE_WIDTH as f64 - 1.0);
     let vertical_frac = (j as f64 + rand::thread_rng().gen::<f64>()) / (IMAGE_HEIGHT as f64 - 1.0);
@@ -144,96 +117,104 @@ fn main() {
     let mut image_var: Vec<Vec<f64>> = vec![vec![0.0; IMAGE_WIDTH]; IMAGE_HEIGHT];
 
     // World
-    let mut objects = Vec::new();
-    objects.push(Object::new(
-        Box::new(materials::Lambertian {
-            albedo: Vec3::new(0.2, 0.8, 0.2),
-        }),
-        Box::new(Sphere {
-            center: Vec3::new(1.0, 0.0, -1.0),
-            radius: 0.4,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Lambertian {
-            albedo: Vec3::new(0.2, 0.2, 0.1),
-        }),
-        Box::new(Sphere {
-            center: Vec3::new(0.0, -100.5, -1.0),
-            radius: 100.0,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Metal {
-            albedo: Vec3::new(0.8, 0.2, 0.2),
-            fuzz: 0.02,
-        }),
-        Box::new(Sphere {
-            center: Vec3::new(-1.0, 0.0, -1.0),
-            radius: 0.4,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Dielectric { ir: 1.5 }),
-        Box::new(Sphere {
-            center: Vec3::new(0.0, 0.0, -1.0),
-            radius: 0.4,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Dielectric { ir: 1.5 }),
-        Box::new(Sphere {
-            center: Vec3::new(0.0, 0.0, -1.0),
-            radius: -0.35,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Dielectric { ir: 1.5 }),
-        Box::new(Sphere {
-            center: Vec3::new(2.0, 0.0, -1.0),
-            radius: 0.4,
-        }),
-    ));
-    objects.push(Object::new(
-        Box::new(materials::Lambertian {
-            albedo: Vec3::new(0.2, 0.2, 1.0),
-        }),
-        Box::new(Triangle {
-            p1: Vec3::new(-1.0, -0.5, 0.0),
-            p2: Vec3::new(-1.0, 0.5, -0.5),
-            p3: Vec3::new(-1.0, 0.5, 0.5),
-        }),
-    ));
+    let mut objects = vec![
+        Object::new(
+            Box::new(materials::Lambertian::new(Vec3::new(0.2, 0.8, 0.2))),
+            Box::new(Sphere {
+                center: Vec3::new(1.0, 0.0, -1.0),
+                radius: 0.4,
+            }),
+        ),
+        Object::new(
+            Box::new(materials::Lambertian::new(Vec3::new(0.2, 0.2, 0.1))),
+            Box::new(Plane {
+                point: Vec3::new(0.0, -0.5, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+            }),
+        ),
+        Object::new(
+            Box::new(materials::Metal::new(Vec3::new(0.8, 0.2, 0.2), 0.02)),
+            Box::new(Sphere {
+                center: Vec3::new(-1.0, 0.0, -1.0),
+                radius: 0.4,
+            }),
+        ),
+        Object::new(
+            Box::new(materials::Dielectric::new(1.5)),
+            Box::new(Csg::difference(
+                Box::new(Sphere {
+                    center: Vec3::new(0.0, 0.0, -1.0),
+                    radius: 0.4,
+                }),
+                Box::new(Sphere {
+                    center: Vec3::new(0.0, 0.0, -1.0),
+                    radius: 0.35,
+                }),
+            )),
+        ),
+        Object::new(
+            Box::new(materials::Dielectric::new(1.5)),
+            Box::new(Sphere {
+                center: Vec3::new(2.0, 0.0, -1.0),
+                radius: 0.4,
+            }),
+        ),
+        Object::new(
+            Box::new(materials::Lambertian::new(Vec3::new(0.2, 0.2, 1.0))),
+            Box::new(Triangle {
+                p1: Vec3::new(-1.0, -0.5, 0.0),
+                p2: Vec3::new(-1.0, 0.5, -0.5),
+                p3: Vec3::new(-1.0, 0.5, 0.5),
+                uvs: None,
+                tangents: None,
+                normals: None,
+                colors: None,
+            }),
+        ),
+    ];
     let tree_mesh = Mesh::from_file(
         Path::new("data/LowPolyTree1.obj"),
         Vec3::new(2.0, -0.49, 0.1),
     )
     .unwrap();
+    for warning in &tree_mesh.warnings {
+        eprintln!("{}", warning);
+    }
     objects.push(Object::new(
-        Box::new(materials::Lambertian {
-            albedo: Vec3::new(0.1, 0.5, 0.1),
-        }),
+        Box::new(materials::Lambertian::new(Vec3::new(0.1, 0.5, 0.1))),
         Box::new(tree_mesh),
     ));
 
-    let mut lights: Vec<Box<dyn Light>> = Vec::new();
-    lights.push(Box::new(PointLight {
-        color: Vec3::new(1.0, 1.0, 1.0),
-        position: Vec3::new(3.0, 2.0, 3.0),
-        intensity: 20.0,
-    }));
-
-    lights.push(Box::new(AmbientLight {
-        color_from_ray: Box::new(|ray| lights::sky_background(1.0, ray)),
-    }));
+    let lights: Vec<Box<dyn Light>> = vec![
+        Box::new(PointLight {
+            color: Vec3::new(1.0, 1.0, 1.0),
+            position: Vec3::new(3.0, 2.0, 3.0),
+            intensity: 20.0,
+        }),
+        Box::new(AmbientLight {
+            color_from_ray: Box::new(|ray| lights::sky_background(1.0, ray)),
+        }),
+    ];
 
     let object_container = build_tdtree(&objects, 12);
 
     let scene: Scene = Scene {
         objects: &object_container,
         lights: &lights,
+        medium: None,
     };
 
+    // Swap in one of the debug integrators from the integrators module to diagnose the scene
+    let integrator: Box<dyn Integrator> = Box::new(PathIntegrator {
+        max_diffuse_bounces: MAX_DIFFUSE_BOUNCES,
+        max_specular_bounces: MAX_SPECULAR_BOUNCES,
+        max_transmission_bounces: MAX_TRANSMISSION_BOUNCES,
+        max_volume_bounces: MAX_VOLUME_BOUNCES,
+        min_bounces: MIN_BOUNCES,
+        max_light_val: MAX_LIGHT_VAL,
+        spectral: SPECTRAL_RENDERING,
+    });
+
     // Render
     image_buf
         .iter_mut()
@@ -255,7 +236,7 @@ fn main() {
                     // Get the new color samples
                     for _ in 0..BASE_SAMPLES_PER_PIXEL {
                         let ray = ray_from_image_pos(i, j, &camera);
-                        let (c, h_o) = ray_color(&ray, &scene, MAX_BOUNCES);
+                        let (c, h_o) = integrator.ray_color(&ray, &scene);
                         colors.push(c);
                         hits.push(h_o);
                     }
@@ -275,7 +256,7 @@ fn main() {
 
                     // Check value of the standard error of the mean, if it is high, get more samples
                     color_uncertain = corrected_sample_std
-                        / ((current_iteration * BASE_SAMPLES_PER_PIXEL as i32) as f64).sqrt()
+                        / ((current_iteration * BASE_SAMPLES_PER_PIXEL) as f64).sqrt()
                         > COLOR_MEAN_UNCERTAINTY_THRESHOLD;
 
                     row[i] = total_mean;
@@ -286,9 +267,9 @@ fn main() {
                 // The value will be true if all rays hit an object and the distance of the hits is lower than some threshold
                 // This should also somehow take into account if the object is transparent, e.g. the dielectric to make sure
                 // that we don't get blurry edges inside the object where another edge is refracted
-                let sum_o = hits.iter().fold(Some(Vec3::z()), |sum_o_acc, h_o| {
-                    sum_o_acc.and_then(|sum| h_o.as_ref().and_then(|h| Some(sum + h.normal)))
-                });
+                let sum_o = hits
+                    .iter()
+                    .try_fold(Vec3::z(), |sum, h_o| h_o.as_ref().map(|h| sum + h.normal));
                 normal_row[i] = sum_o.and_then(|sum_vec| {
                     let avg_vec = sum_vec * (1.0 / hits.len() as f64);
                     if hits.iter().any(|hit| {
@@ -307,7 +288,6 @@ fn main() {
 
     // Post processing
     (0..IMAGE_HEIGHT)
-        .into_iter()
         .zip(image.iter_mut())
         .par_bridge()
         .for_each(|(i, image_row)| {
@@ -336,7 +316,7 @@ fn main() {
                                     let pw = if k == SMOOTH_SIZE / 2 && l == SMOOTH_SIZE / 2 {
                                         // Base weight gets scaled depending on the variance of the pixel
                                         // If we are super sure of the pixel color (variance close to 0) this will make sure it doesn't get blurred
-                                        SMOOTHING_BASE_WEIGHT / &image_var[i][j]
+                                        SMOOTHING_BASE_WEIGHT / image_var[i][j]
                                     } else {
                                         match &image_normal[usize::try_from(pos_h).unwrap()]
                                             [usize::try_from(pos_w).unwrap()]
@@ -360,6 +340,7 @@ fn main() {
                 } else {
                     image_row[j] = image_buf[i][j];
                 }
+                image_row[j] = integrator.pixel_color(image_row[j]);
             }
         });
 
@@ -371,9 +352,8 @@ fn main() {
         + &IMAGE_HEIGHT.to_string()
         + "\n255\n";
 
-    for j in (0..IMAGE_HEIGHT).rev() {
-        for i in 0..IMAGE_WIDTH {
-            let c = image[j][i];
+    for image_row in image.iter().rev() {
+        for c in image_row {
             let r = c.x.sqrt();
             let g = c.y.sqrt();
             let b = c.z.sqrt();
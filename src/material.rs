@@ -1,9 +1,41 @@
 use crate::hittable::Hit;
 use crate::light::LightInfo;
+use crate::medium::Medium;
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum Lobe {
+    Diffuse,
+    Specular,
+    Transmission,
+    Volume, // Scattering inside of a medium
+}
+
+pub struct Scatter {
+    pub ray: Ray,
+    pub attenuation: Vec3, // How much of the color of the scattered ray is passed on
+    pub lobe: Lobe,
+}
+
 pub trait Material {
+    // next_ray_color is the color of the scattered ray, already weighted by the attenuation of the scatter
     fn get_color(&self, ray: &Ray, light_info: LightInfo, hit: &Hit, next_ray_color: Vec3) -> Vec3;
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray>;
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter>;
+    // Used by the spectral mode, the attenuation is still given in RGB and gets converted by the integrator
+    // In RGB mode it's used with the wavelength of a color channel for materials that depend on the wavelength
+    // Materials where light of different wavelengths takes different paths scatter the ray of the given wavelength
+    fn scatter_wavelength(&self, ray: &Ray, hit: &Hit, _wavelength: f64) -> Option<Scatter> {
+        self.scatter(ray, hit)
+    }
+    fn depends_on_wavelength(&self) -> bool {
+        false
+    }
+    fn casts_shadow(&self) -> bool {
+        true
+    }
+    // Medium inside of objects with this material, for materials that scatter light below their surface
+    fn interior(&self) -> Option<&dyn Medium> {
+        None
+    }
 }
@@ -1,11 +1,17 @@
 use crate::hittable::Hit;
 use crate::light::LightInfo;
-use crate::material::Material;
+use crate::material::{Lobe, Material, Scatter};
+use crate::media::HomogeneousMedium;
+use crate::medium::{HenyeyGreenstein, Medium};
+use crate::microfacet::{self, Frame};
 use crate::ray::Ray;
-use crate::util;
+use crate::texture::Texture;
+use crate::textures::ConstantTexture;
+use crate::util::{self, PI};
 use crate::vec3::Vec3;
 use rand::Rng;
 
+#[allow(dead_code)]
 pub struct NormalMaterial;
 impl Material for NormalMaterial {
     fn get_color(
@@ -18,11 +24,12 @@ impl Material for NormalMaterial {
         util::normal_to_color(&hit.normal)
     }
 
-    fn scatter(&self, _ray: &Ray, _hit: &Hit) -> Option<Ray> {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit) -> Option<Scatter> {
         Option::None
     }
 }
 
+#[allow(dead_code)]
 pub struct ConstantColorMaterial {
     pub color: Vec3,
 }
@@ -37,13 +44,50 @@ impl Material for ConstantColorMaterial {
         self.color
     }
 
-    fn scatter(&self, _ray: &Ray, _hit: &Hit) -> Option<Ray> {
+    fn scatter(&self, _ray: &Ray, _hit: &Hit) -> Option<Scatter> {
         Option::None
     }
 }
 
+// Invisible surface, used for objects that only bound the medium inside of them
+#[allow(dead_code)]
+pub struct MediumBoundary;
+impl Material for MediumBoundary {
+    fn get_color(
+        &self,
+        _ray: &Ray,
+        _light_info: LightInfo,
+        _hit: &Hit,
+        next_ray_color: Vec3,
+    ) -> Vec3 {
+        next_ray_color
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: ray.direction,
+            },
+            attenuation: Vec3::new(1.0, 1.0, 1.0),
+            lobe: Lobe::Transmission,
+        })
+    }
+
+    fn casts_shadow(&self) -> bool {
+        false
+    }
+}
+
 pub struct Lambertian {
-    pub albedo: Vec3,
+    pub albedo: Box<dyn Texture>,
+}
+impl Lambertian {
+    pub fn new(albedo: Vec3) -> Lambertian {
+        Lambertian {
+            albedo: Box::new(ConstantTexture::new(albedo)),
+        }
+    }
 }
 impl Material for Lambertian {
     fn get_color(
@@ -53,11 +97,11 @@ impl Material for Lambertian {
         hit: &Hit,
         next_ray_color: Vec3,
     ) -> Vec3 {
-        self.albedo * light_info.color * light_info.direction.dot(&hit.normal).max(0.0)
-            + self.albedo * next_ray_color
+        self.albedo.value(hit) * light_info.color * light_info.direction.dot(&hit.normal).max(0.0)
+            + next_ray_color
     }
 
-    fn scatter(&self, _ray: &Ray, hit: &Hit) -> Option<Ray> {
+    fn scatter(&self, _ray: &Ray, hit: &Hit) -> Option<Scatter> {
         let scatter_direction = hit.normal + Vec3::random_unit_vector();
         let scatter_direction = {
             if scatter_direction.near_zero() {
@@ -66,17 +110,29 @@ impl Material for Lambertian {
                 scatter_direction
             }
         };
-        Option::from(Ray {
-            origin: hit.p,
-            direction: scatter_direction,
+        Option::from(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: scatter_direction,
+            },
+            attenuation: self.albedo.value(hit),
+            lobe: Lobe::Diffuse,
         })
     }
 }
 
 pub struct Metal {
-    pub albedo: Vec3,
+    pub albedo: Box<dyn Texture>,
     pub fuzz: f64,
 }
+impl Metal {
+    pub fn new(albedo: Vec3, fuzz: f64) -> Metal {
+        Metal {
+            albedo: Box::new(ConstantTexture::new(albedo)),
+            fuzz,
+        }
+    }
+}
 impl Material for Metal {
     fn get_color(
         &self,
@@ -85,22 +141,99 @@ impl Material for Metal {
         hit: &Hit,
         next_ray_color: Vec3,
     ) -> Vec3 {
-        self.albedo * light_info.color * light_info.direction.dot(&hit.normal).max(0.0)
-            + self.albedo * next_ray_color
+        self.albedo.value(hit) * light_info.color * light_info.direction.dot(&hit.normal).max(0.0)
+            + next_ray_color
     }
 
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
         let reflected = ray.direction.unit_vector().reflect(hit.normal)
             + Vec3::random_in_unit_sphere() * self.fuzz;
-        Option::from(Ray {
-            origin: hit.p,
-            direction: reflected,
+        Option::from(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: reflected,
+            },
+            attenuation: self.albedo.value(hit),
+            lobe: Lobe::Specular,
         })
     }
 }
 
+// Wavelengths in nm that the red, green and blue color channels stand for
+pub const RGB_WAVELENGTHS: [f64; 3] = [630.0, 532.0, 465.0];
+
+// Index of refraction depending on the wavelength of the light, which splits white light into its colors
+pub enum Dispersion {
+    // n = a + b / λ² with λ in μm
+    Cauchy { a: f64, b: f64 },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i) with λ in μm
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+impl Dispersion {
+    #[allow(dead_code)]
+    pub fn crown_glass() -> Dispersion {
+        // Schott N-BK7
+        Dispersion::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        }
+    }
+    #[allow(dead_code)]
+    pub fn flint_glass() -> Dispersion {
+        // Schott N-SF11, disperses a lot more than crown glass
+        Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        }
+    }
+    #[allow(dead_code)]
+    pub fn diamond() -> Dispersion {
+        Dispersion::Cauchy {
+            a: 2.3757,
+            b: 0.0121,
+        }
+    }
+    pub fn ir(&self, wavelength: f64) -> f64 {
+        let l = wavelength / 1000.0;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => (1.0
+                + b.iter()
+                    .zip(c.iter())
+                    .map(|(b, c)| b * l2 / (l2 - c))
+                    .sum::<f64>())
+            .sqrt(),
+        }
+    }
+}
+
 pub struct Dielectric {
-    pub ir: f64, // Index of Refraction
+    pub ir: f64,          // Index of Refraction, used if there is no dispersion
+    pub absorption: Vec3, // Fraction of each color channel absorbed per unit of distance inside the medium
+    pub dispersion: Option<Dispersion>,
+}
+impl Dielectric {
+    pub fn new(ir: f64) -> Dielectric {
+        Dielectric {
+            ir,
+            absorption: Vec3::z(),
+            dispersion: None,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn with_color(mut self, color: Vec3, density: f64) -> Dielectric {
+        // Light that travels a distance of 1 / density through the medium is tinted by color (Beer-Lambert law)
+        // Channels that are fully absorbed are clamped to keep the absorption finite
+        let color = Vec3::new(color.x.max(1e-4), color.y.max(1e-4), color.z.max(1e-4));
+        self.absorption = -color.ln() * density;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_dispersion(mut self, dispersion: Dispersion) -> Dielectric {
+        self.dispersion = Some(dispersion);
+        self
+    }
 }
 impl Material for Dielectric {
     fn get_color(
@@ -113,12 +246,32 @@ impl Material for Dielectric {
         next_ray_color
     }
 
-    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Ray> {
-        let refraction_ratio = if hit.front_face {
-            1.0 / self.ir
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        // With dispersion the integrator picks a color channel per path and calls scatter_wavelength instead
+        Some(self.scatter_with_ir(ray, hit, self.ir))
+    }
+
+    fn scatter_wavelength(&self, ray: &Ray, hit: &Hit, wavelength: f64) -> Option<Scatter> {
+        let ir = match &self.dispersion {
+            None => self.ir,
+            Some(dispersion) => dispersion.ir(wavelength),
+        };
+        Some(self.scatter_with_ir(ray, hit, ir))
+    }
+
+    fn depends_on_wavelength(&self) -> bool {
+        self.dispersion.is_some()
+    }
+}
+impl Dielectric {
+    fn scatter_with_ir(&self, ray: &Ray, hit: &Hit, ir: f64) -> Scatter {
+        // Light hitting the inside of the surface travelled through the medium and got partially absorbed
+        let transmittance = if hit.front_face {
+            Vec3::new(1.0, 1.0, 1.0)
         } else {
-            self.ir
+            (-self.absorption * (hit.t * ray.direction.length())).exp()
         };
+        let refraction_ratio = if hit.front_face { 1.0 / ir } else { ir };
         let unit_dir = ray.direction.unit_vector();
         let temp = (-unit_dir).dot(&hit.normal);
         let cos_theta = if temp < 1.0 { temp } else { 1.0 };
@@ -128,18 +281,24 @@ impl Material for Dielectric {
         let reflecting = cannot_refract
             || Dielectric::reflectance(cos_theta, refraction_ratio)
                 > rand::thread_rng().gen::<f64>();
-        let direction = if reflecting {
-            unit_dir.reflect(hit.normal)
+        let (direction, lobe) = if reflecting {
+            (unit_dir.reflect(hit.normal), Lobe::Specular)
         } else {
-            unit_dir.refract(hit.normal, refraction_ratio)
+            (
+                unit_dir.refract(hit.normal, refraction_ratio),
+                Lobe::Transmission,
+            )
         };
-        Option::from(Ray {
-            origin: hit.p,
-            direction,
-        })
+        Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction,
+            },
+            attenuation: transmittance,
+            lobe,
+        }
     }
-}
-impl Dielectric {
+
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         // Use Schlick's approximation for reflectance.
         let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
@@ -147,3 +306,871 @@ impl Dielectric {
         r0 + (1.0 - r0) * (1.0 - cosine).powf(5.0)
     }
 }
+
+pub struct Conductor {
+    pub eta: Vec3, // Real part of the complex index of refraction per color channel
+    pub k: Vec3,   // Imaginary part, i.e. the absorption coefficient
+    pub roughness: f64,
+}
+impl Conductor {
+    #[allow(dead_code)]
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Vec3::new(0.143, 0.374, 1.442),
+            k: Vec3::new(3.983, 2.385, 1.603),
+            roughness,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Vec3::new(0.200, 0.924, 1.102),
+            k: Vec3::new(3.912, 2.452, 2.142),
+            roughness,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor {
+            eta: Vec3::new(1.657, 0.880, 0.521),
+            k: Vec3::new(9.224, 6.270, 4.837),
+            roughness,
+        }
+    }
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        // BRDF times the cosine of the incoming light, with directions in the local frame
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::z();
+        }
+        let alpha = microfacet::roughness_to_alpha(self.roughness);
+        let m = (wo + wi).unit_vector();
+        microfacet::fresnel_conductor(wi.dot(&m), self.eta, self.k)
+            * (microfacet::ggx_d(m, alpha) * microfacet::smith_g2(wo, wi, alpha) / (4.0 * wo.z))
+    }
+}
+impl Material for Conductor {
+    fn get_color(&self, ray: &Ray, light_info: LightInfo, hit: &Hit, next_ray_color: Vec3) -> Vec3 {
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let wi = frame.to_local(light_info.direction);
+        light_info.color * self.eval(wo, wi) + next_ray_color
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        let alpha = microfacet::roughness_to_alpha(self.roughness);
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let m = microfacet::sample_ggx_vndf(wo, alpha);
+        let wi = (-wo).reflect(m);
+        if wi.z <= 0.0 {
+            // Reflected into the surface
+            return None;
+        }
+        // With visible normal sampling most terms cancel out
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: frame.to_world(wi),
+            },
+            attenuation: microfacet::fresnel_conductor(wi.dot(&m), self.eta, self.k)
+                * microfacet::smith_g1(wi, alpha),
+            lobe: Lobe::Specular,
+        })
+    }
+}
+
+pub struct RoughDielectric {
+    pub ir: f64, // Index of Refraction
+    pub roughness: f64,
+}
+impl RoughDielectric {
+    pub fn eval_reflection(&self, wo: Vec3, wi: Vec3, eta: f64) -> f64 {
+        // Reflective part of the BSDF times the cosine of the incoming light, with directions in the local frame
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let alpha = microfacet::roughness_to_alpha(self.roughness);
+        let m = (wo + wi).unit_vector();
+        microfacet::fresnel_dielectric(wi.dot(&m), eta)
+            * microfacet::ggx_d(m, alpha)
+            * microfacet::smith_g2(wo, wi, alpha)
+            / (4.0 * wo.z)
+    }
+}
+impl Material for RoughDielectric {
+    fn get_color(&self, ray: &Ray, light_info: LightInfo, hit: &Hit, next_ray_color: Vec3) -> Vec3 {
+        // Only the reflection of lights is added directly, light shining through is blocked by the shadow ray
+        let eta = if hit.front_face {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let wi = frame.to_local(light_info.direction);
+        light_info.color * self.eval_reflection(wo, wi, eta) + next_ray_color
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        // eta is the ratio of the index of refraction on the other side to the one of the side the ray comes from
+        let eta = if hit.front_face {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        let alpha = microfacet::roughness_to_alpha(self.roughness);
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let m = microfacet::sample_ggx_vndf(wo, alpha);
+        let reflectance = microfacet::fresnel_dielectric(wo.dot(&m), eta);
+        // Choose between reflection and refraction proportional to the Fresnel term, which then cancels out
+        let (wi, lobe) = if reflectance > rand::thread_rng().gen::<f64>() {
+            ((-wo).reflect(m), Lobe::Specular)
+        } else {
+            ((-wo).refract(m, 1.0 / eta), Lobe::Transmission)
+        };
+        if (lobe == Lobe::Specular) != (wi.z > 0.0) {
+            // Scattered to the wrong side of the surface
+            return None;
+        }
+        let g1 = microfacet::smith_g1(wi, alpha);
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: frame.to_world(wi),
+            },
+            attenuation: Vec3::new(g1, g1, g1),
+            lobe,
+        })
+    }
+}
+
+// Disney style material that blends diffuse, specular, clearcoat, sheen and transmission lobes
+// Every parameter is a texture so it can vary over the surface, scalar parameters are in [0, 1]
+pub struct Principled {
+    pub base_color: Box<dyn Texture>,
+    pub metallic: Box<dyn Texture>,
+    pub roughness: Box<dyn Texture>,
+    pub specular: Box<dyn Texture>, // Reflectance of non-metals, 0.5 corresponds to 4% at normal incidence
+    pub clearcoat: Box<dyn Texture>,
+    pub clearcoat_roughness: Box<dyn Texture>,
+    pub sheen: Box<dyn Texture>,
+    pub transmission: Box<dyn Texture>,
+    pub ir: f64, // Index of Refraction of the transmissive part
+}
+
+// The parameters of a Principled material at a specific hit
+struct PrincipledSample {
+    base_color: Vec3,
+    metallic: f64,
+    roughness: f64,
+    f0: Vec3, // Reflectance of the specular lobe at normal incidence
+    clearcoat: f64,
+    clearcoat_roughness: f64,
+    sheen: f64,
+    transmission: f64,
+}
+impl PrincipledSample {
+    fn diffuse_weight(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+    fn transmission_weight(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+    fn clearcoat_weight(&self) -> f64 {
+        0.25 * self.clearcoat
+    }
+    fn lobe_probabilities(&self) -> [f64; 4] {
+        // Probabilities of sampling the diffuse, specular, clearcoat and transmission lobes
+        let weights = [
+            self.diffuse_weight() * (self.base_color.x + self.base_color.y + self.base_color.z)
+                / 3.0,
+            ((self.f0.x + self.f0.y + self.f0.z) / 3.0).max(0.1),
+            self.clearcoat_weight(),
+            self.transmission_weight(),
+        ];
+        let total: f64 = weights.iter().sum();
+        weights.map(|w| w / total)
+    }
+}
+
+impl Principled {
+    pub fn new(base_color: Vec3) -> Principled {
+        // Rough plastic, other parameters can be set using struct update syntax
+        Principled {
+            base_color: Box::new(ConstantTexture::new(base_color)),
+            metallic: Box::new(ConstantTexture::gray(0.0)),
+            roughness: Box::new(ConstantTexture::gray(0.5)),
+            specular: Box::new(ConstantTexture::gray(0.5)),
+            clearcoat: Box::new(ConstantTexture::gray(0.0)),
+            clearcoat_roughness: Box::new(ConstantTexture::gray(0.03)),
+            sheen: Box::new(ConstantTexture::gray(0.0)),
+            transmission: Box::new(ConstantTexture::gray(0.0)),
+            ir: 1.5,
+        }
+    }
+
+    fn sample_at(&self, hit: &Hit) -> PrincipledSample {
+        let base_color = self.base_color.value(hit);
+        let metallic = self.metallic.scalar(hit).clamp(0.0, 1.0);
+        let dielectric_f0 = self.specular.scalar(hit) * 0.08;
+        PrincipledSample {
+            base_color,
+            metallic,
+            roughness: self.roughness.scalar(hit).clamp(0.0, 1.0),
+            f0: Vec3::new(dielectric_f0, dielectric_f0, dielectric_f0) * (1.0 - metallic)
+                + base_color * metallic,
+            clearcoat: self.clearcoat.scalar(hit).clamp(0.0, 1.0),
+            clearcoat_roughness: self.clearcoat_roughness.scalar(hit).clamp(0.0, 1.0),
+            sheen: self.sheen.scalar(hit).max(0.0),
+            transmission: self.transmission.scalar(hit).clamp(0.0, 1.0),
+        }
+    }
+
+    fn schlick(f0: Vec3, cosine: f64) -> Vec3 {
+        f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * (1.0 - cosine).max(0.0).powf(5.0)
+    }
+
+    fn diffuse_factor(s: &PrincipledSample, wo: Vec3, wi: Vec3) -> Vec3 {
+        // Burley diffuse retro-reflection and sheen relative to a Lambertian surface
+        let cos_d = wi.dot(&(wo + wi).unit_vector());
+        let fd90 = 0.5 + 2.0 * s.roughness * cos_d * cos_d;
+        let fl = (1.0 - wi.z).powf(5.0);
+        let fv = (1.0 - wo.z).powf(5.0);
+        let retro = (1.0 + (fd90 - 1.0) * fl) * (1.0 + (fd90 - 1.0) * fv);
+        let sheen = s.sheen * PI * (1.0 - cos_d).powf(5.0);
+        s.base_color * retro + Vec3::new(sheen, sheen, sheen)
+    }
+
+    fn transmissive(&self, s: &PrincipledSample) -> RoughDielectric {
+        RoughDielectric {
+            ir: self.ir,
+            roughness: s.roughness,
+        }
+    }
+
+    fn eval(&self, s: &PrincipledSample, wo: Vec3, wi: Vec3, eta: f64) -> Vec3 {
+        // BSDF times the cosine of the incoming light, with directions in the local frame
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::z();
+        }
+        let m = (wo + wi).unit_vector();
+        let alpha = microfacet::roughness_to_alpha(s.roughness);
+        let specular = Principled::schlick(s.f0, wi.dot(&m))
+            * (microfacet::ggx_d(m, alpha) * microfacet::smith_g2(wo, wi, alpha) / (4.0 * wo.z));
+        let cc_alpha = microfacet::roughness_to_alpha(s.clearcoat_roughness);
+        let clearcoat = Principled::schlick(Vec3::new(0.04, 0.04, 0.04), wi.dot(&m)).x
+            * microfacet::ggx_d(m, cc_alpha)
+            * microfacet::smith_g2(wo, wi, cc_alpha)
+            / (4.0 * wo.z);
+        Principled::diffuse_factor(s, wo, wi) * (s.diffuse_weight() * wi.z / PI)
+            + specular
+            + s.base_color
+                * (self.transmissive(s).eval_reflection(wo, wi, eta) * s.transmission_weight())
+            + Vec3::new(1.0, 1.0, 1.0) * (clearcoat * s.clearcoat_weight())
+    }
+}
+impl Material for Principled {
+    fn get_color(&self, ray: &Ray, light_info: LightInfo, hit: &Hit, next_ray_color: Vec3) -> Vec3 {
+        let s = self.sample_at(hit);
+        let eta = if hit.front_face {
+            self.ir
+        } else {
+            1.0 / self.ir
+        };
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let wi = frame.to_local(light_info.direction);
+        light_info.color * self.eval(&s, wo, wi, eta) + next_ray_color
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        let s = self.sample_at(hit);
+        if !hit.front_face && s.transmission_weight() > 0.0 {
+            // Inside of a transmissive object only the dielectric makes sense
+            return self
+                .transmissive(&s)
+                .scatter(ray, hit)
+                .map(|scatter| Scatter {
+                    attenuation: scatter.attenuation * s.base_color,
+                    ..scatter
+                });
+        }
+        let frame = Frame::new(hit.normal);
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        // Pick one lobe at random and weigh its contribution by the inverse of the probability of picking it
+        let probabilities = s.lobe_probabilities();
+        let mut choice = rand::thread_rng().gen::<f64>();
+        let lobe = probabilities
+            .iter()
+            .position(|p| {
+                choice -= p;
+                choice < 0.0
+            })
+            .unwrap_or(1);
+        let probability = probabilities[lobe];
+        let (wi, attenuation, kind) = match lobe {
+            0 => {
+                let wi = (Vec3::new(0.0, 0.0, 1.0) + Vec3::random_unit_vector()).unit_vector();
+                (
+                    wi,
+                    Principled::diffuse_factor(&s, wo, wi) * s.diffuse_weight(),
+                    Lobe::Diffuse,
+                )
+            }
+            1 | 2 => {
+                let (alpha, f0, weight) = if lobe == 1 {
+                    (microfacet::roughness_to_alpha(s.roughness), s.f0, 1.0)
+                } else {
+                    (
+                        microfacet::roughness_to_alpha(s.clearcoat_roughness),
+                        Vec3::new(0.04, 0.04, 0.04),
+                        s.clearcoat_weight(),
+                    )
+                };
+                let m = microfacet::sample_ggx_vndf(wo, alpha);
+                let wi = (-wo).reflect(m);
+                (
+                    wi,
+                    Principled::schlick(f0, wi.dot(&m))
+                        * (microfacet::smith_g1(wi, alpha) * weight),
+                    Lobe::Specular,
+                )
+            }
+            _ => {
+                return self
+                    .transmissive(&s)
+                    .scatter(ray, hit)
+                    .map(|scatter| Scatter {
+                        attenuation: scatter.attenuation
+                            * s.base_color
+                            * (s.transmission_weight() / probability),
+                        ..scatter
+                    });
+            }
+        };
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: frame.to_world(wi),
+            },
+            attenuation: attenuation / probability,
+            lobe: kind,
+        })
+    }
+}
+
+// Light enters the object and scatters around inside of it before it leaves again, like in wax, skin or marble
+// The inside is a medium that paths do a random walk through
+pub struct Subsurface {
+    pub ir: f64,
+    pub medium: HomogeneousMedium,
+}
+impl Subsurface {
+    #[allow(dead_code)]
+    pub fn new(albedo: Vec3, mean_free_path: Vec3) -> Subsurface {
+        // Albedo is the color of the object, mean_free_path is the average distance light travels in between scattering
+        // The single scattering albedo of the medium that results in the given color after many bounces
+        // "Practical and Controllable Subsurface Scattering for Production Path Tracing", Chiang et al. 2016
+        let single_scattering = |a: f64| {
+            let s = 4.09712 + 4.20863 * a - (9.59217 + 41.6808 * a + 17.7126 * a * a).sqrt();
+            1.0 - s * s
+        };
+        let albedo = Vec3::new(
+            single_scattering(albedo.x),
+            single_scattering(albedo.y),
+            single_scattering(albedo.z),
+        );
+        let sigma_t = Vec3::new(
+            1.0 / mean_free_path.x,
+            1.0 / mean_free_path.y,
+            1.0 / mean_free_path.z,
+        );
+        Subsurface {
+            ir: 1.4,
+            medium: HomogeneousMedium {
+                sigma_a: sigma_t * (Vec3::new(1.0, 1.0, 1.0) - albedo),
+                sigma_s: sigma_t * albedo,
+                phase: HenyeyGreenstein { g: 0.0 },
+            },
+        }
+    }
+}
+impl Material for Subsurface {
+    fn get_color(
+        &self,
+        _ray: &Ray,
+        light_info: LightInfo,
+        hit: &Hit,
+        next_ray_color: Vec3,
+    ) -> Vec3 {
+        // Light only gets in where paths leave the object, which is treated like a diffuse surface
+        if hit.front_face {
+            next_ray_color
+        } else {
+            light_info.color * light_info.direction.dot(&-hit.normal).max(0.0) + next_ray_color
+        }
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        if hit.front_face {
+            // Smooth surface, the light either gets reflected or goes inside
+            let unit_dir = ray.direction.unit_vector();
+            let cos_theta = (-unit_dir).dot(&hit.normal).min(1.0);
+            let reflecting =
+                Dielectric::reflectance(cos_theta, 1.0 / self.ir) > rand::thread_rng().gen::<f64>();
+            let (direction, lobe) = if reflecting {
+                (unit_dir.reflect(hit.normal), Lobe::Specular)
+            } else {
+                (
+                    unit_dir.refract(hit.normal, 1.0 / self.ir),
+                    Lobe::Transmission,
+                )
+            };
+            Some(Scatter {
+                ray: Ray {
+                    origin: hit.p,
+                    direction,
+                },
+                attenuation: Vec3::new(1.0, 1.0, 1.0),
+                lobe,
+            })
+        } else {
+            // Paths leave the object in a cosine weighted direction, so that they can be lit directly
+            let direction = -hit.normal + Vec3::random_unit_vector();
+            let direction = if direction.near_zero() {
+                -hit.normal
+            } else {
+                direction
+            };
+            Some(Scatter {
+                ray: Ray {
+                    origin: hit.p,
+                    direction,
+                },
+                attenuation: Vec3::new(1.0, 1.0, 1.0),
+                lobe: Lobe::Transmission,
+            })
+        }
+    }
+
+    fn interior(&self) -> Option<&dyn Medium> {
+        Some(&self.medium)
+    }
+}
+
+// Hair fiber scattering, light reflects off the surface (R), goes through the fiber (TT) or reflects inside of it (TRT)
+// Needs hits with a tangent along the fiber and v going across it, like the ones of curves
+// "A Practical and Controllable Hair and Fur Model for Production Path Tracing", Chiang et al. 2016, as in pbrt-v3
+pub struct Hair {
+    pub sigma_a: Vec3, // Absorption inside of the fiber, per diameter
+    pub beta_m: f64,   // Longitudinal roughness from 0 to 1
+    pub beta_n: f64,   // Azimuthal roughness from 0 to 1
+    pub alpha: f64,    // Tilt of the scales on the surface in radians
+    pub eta: f64,
+}
+
+// Number of lobes that are handled separately, all longer paths are summed up in the last one
+const HAIR_P_MAX: usize = 3;
+
+impl Hair {
+    pub fn new(sigma_a: Vec3, beta_m: f64, beta_n: f64) -> Hair {
+        Hair {
+            sigma_a,
+            beta_m,
+            beta_n,
+            alpha: 2.0_f64.to_radians(),
+            eta: 1.55,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn from_melanin(eumelanin: f64, pheomelanin: f64, beta_m: f64, beta_n: f64) -> Hair {
+        // Eumelanin makes hair black to brown, pheomelanin red, and without either it's blond to white
+        let sigma_a =
+            Vec3::new(0.419, 0.697, 1.37) * eumelanin + Vec3::new(0.187, 0.4, 1.05) * pheomelanin;
+        Hair::new(sigma_a, beta_m, beta_n)
+    }
+
+    #[allow(dead_code)]
+    pub fn from_color(color: Vec3, beta_m: f64, beta_n: f64) -> Hair {
+        // Absorption that gives roughly the color after many bounces between fibers
+        let b = beta_n;
+        let denom = 5.969 - 0.215 * b + 2.532 * b.powi(2) - 10.73 * b.powi(3)
+            + 5.574 * b.powi(4)
+            + 0.245 * b.powi(5);
+        let sigma_a = |c: f64| (c.max(1e-4).ln() / denom).powi(2);
+        Hair::new(
+            Vec3::new(sigma_a(color.x), sigma_a(color.y), sigma_a(color.z)),
+            beta_m,
+            beta_n,
+        )
+    }
+
+    fn variances(&self) -> [f64; HAIR_P_MAX + 1] {
+        // Longitudinal variance of each lobe, the ones after R get rougher
+        let b = self.beta_m;
+        let v = (0.726 * b + 0.812 * b * b + 3.7 * b.powi(20)).powi(2);
+        [v, 0.25 * v, 4.0 * v, 4.0 * v]
+    }
+
+    fn logistic_scale(&self) -> f64 {
+        let b = self.beta_n;
+        (PI / 8.0).sqrt() * (0.265 * b + 1.194 * b * b + 5.372 * b.powi(22))
+    }
+
+    fn rotated_theta(&self, p: usize, sin_theta_o: f64, cos_theta_o: f64) -> (f64, f64) {
+        // The scales shift R towards the root and TT and TRT towards the tip
+        let (sin_a, cos_a) = self.alpha.sin_cos();
+        let (sin_2a, cos_2a) = (2.0 * cos_a * sin_a, cos_a * cos_a - sin_a * sin_a);
+        let (sin_4a, cos_4a) = (2.0 * cos_2a * sin_2a, cos_2a * cos_2a - sin_2a * sin_2a);
+        let (sin, cos) = match p {
+            0 => (
+                sin_theta_o * cos_2a - cos_theta_o * sin_2a,
+                cos_theta_o * cos_2a + sin_theta_o * sin_2a,
+            ),
+            1 => (
+                sin_theta_o * cos_a + cos_theta_o * sin_a,
+                cos_theta_o * cos_a - sin_theta_o * sin_a,
+            ),
+            2 => (
+                sin_theta_o * cos_4a + cos_theta_o * sin_4a,
+                cos_theta_o * cos_4a - sin_theta_o * sin_4a,
+            ),
+            _ => (sin_theta_o, cos_theta_o),
+        };
+        (sin, cos.abs())
+    }
+
+    fn attenuations(
+        &self,
+        cos_theta_o: f64,
+        h: f64,
+        transmittance: Vec3,
+    ) -> [Vec3; HAIR_P_MAX + 1] {
+        // Fraction of the light in each lobe, from Fresnel reflection and absorption inside of the fiber
+        let cos_gamma_o = (1.0 - h * h).max(0.0).sqrt();
+        let f = microfacet::fresnel_dielectric(cos_theta_o * cos_gamma_o, self.eta);
+        let mut a = [Vec3::z(); HAIR_P_MAX + 1];
+        a[0] = Vec3::new(f, f, f);
+        a[1] = transmittance * (1.0 - f).powi(2);
+        for p in 2..HAIR_P_MAX {
+            a[p] = a[p - 1] * transmittance * f;
+        }
+        let tf = transmittance * f;
+        a[HAIR_P_MAX] = a[HAIR_P_MAX - 1]
+            * tf
+            * Vec3::new(1.0 / (1.0 - tf.x), 1.0 / (1.0 - tf.y), 1.0 / (1.0 - tf.z));
+        a
+    }
+
+    fn frame(hit: &Hit) -> Option<Frame> {
+        // x goes along the fiber and z is the normal, which faces the ray
+        let (tangent, _) = hit.tangent?;
+        let x = (tangent - hit.normal * hit.normal.dot(&tangent)).unit_vector();
+        Some(Frame {
+            t: x,
+            b: hit.normal.cross(&x),
+            n: hit.normal,
+        })
+    }
+
+    fn eval_and_pdf(&self, wo: Vec3, wi: Vec3, h: f64) -> (Vec3, f64) {
+        // BSDF times the cosine of the incoming light and the pdf of sampling wi, in the local frame of the fiber
+        let sin_theta_o = wo.x;
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(0.0).sqrt();
+        let phi_o = wo.z.atan2(wo.y);
+        let sin_theta_i = wi.x;
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+        let phi_i = wi.z.atan2(wi.y);
+        let (gamma_o, gamma_t, transmittance) = self.refracted(sin_theta_o, cos_theta_o, h);
+        let attenuations = self.attenuations(cos_theta_o, h, transmittance);
+        let pdfs = hair_lobe_pdfs(&attenuations);
+        let v = self.variances();
+        let s = self.logistic_scale();
+        let phi = phi_i - phi_o;
+        let mut f = Vec3::z();
+        let mut pdf = 0.0;
+        for p in 0..HAIR_P_MAX {
+            let (sin_theta_op, cos_theta_op) = self.rotated_theta(p, sin_theta_o, cos_theta_o);
+            let mn = hair_mp(cos_theta_i, cos_theta_op, sin_theta_i, sin_theta_op, v[p])
+                * hair_np(phi, p, s, gamma_o, gamma_t);
+            f += attenuations[p] * mn;
+            pdf += pdfs[p] * mn;
+        }
+        let m = hair_mp(
+            cos_theta_i,
+            cos_theta_o,
+            sin_theta_i,
+            sin_theta_o,
+            v[HAIR_P_MAX],
+        ) / (2.0 * PI);
+        f += attenuations[HAIR_P_MAX] * m;
+        pdf += pdfs[HAIR_P_MAX] * m;
+        (f, pdf)
+    }
+
+    fn refracted(&self, sin_theta_o: f64, cos_theta_o: f64, h: f64) -> (f64, f64, Vec3) {
+        // Angles of the ray entering the fiber and the transmittance of going through it once
+        let gamma_o = h.clamp(-1.0, 1.0).asin();
+        let sin_theta_t = sin_theta_o / self.eta;
+        let cos_theta_t = (1.0 - sin_theta_t * sin_theta_t).max(0.0).sqrt();
+        let etap = (self.eta * self.eta - sin_theta_o * sin_theta_o).sqrt() / cos_theta_o;
+        let sin_gamma_t = (h / etap).clamp(-1.0, 1.0);
+        let cos_gamma_t = (1.0 - sin_gamma_t * sin_gamma_t).sqrt();
+        let transmittance = (-self.sigma_a * (2.0 * cos_gamma_t / cos_theta_t)).exp();
+        (gamma_o, sin_gamma_t.asin(), transmittance)
+    }
+}
+
+fn hair_lobe_pdfs(attenuations: &[Vec3; HAIR_P_MAX + 1]) -> [f64; HAIR_P_MAX + 1] {
+    // Lobes are picked by their average attenuation
+    let weights = attenuations.map(|a| (a.x + a.y + a.z) / 3.0);
+    let sum: f64 = weights.iter().sum();
+    weights.map(|w| w / sum)
+}
+
+fn bessel_i0(x: f64) -> f64 {
+    // Modified Bessel function of the first kind
+    let mut val = 0.0;
+    let mut x2i = 1.0;
+    let mut ifact = 1.0;
+    let mut i4 = 1.0;
+    for i in 0..10 {
+        if i > 1 {
+            ifact *= i as f64;
+        }
+        val += x2i / (i4 * ifact * ifact);
+        x2i *= x * x;
+        i4 *= 4.0;
+    }
+    val
+}
+
+fn log_bessel_i0(x: f64) -> f64 {
+    if x > 12.0 {
+        x + 0.5 * (-(2.0 * PI).ln() + (1.0 / x).ln() + 1.0 / (8.0 * x))
+    } else {
+        bessel_i0(x).ln()
+    }
+}
+
+fn hair_mp(cos_theta_i: f64, cos_theta_o: f64, sin_theta_i: f64, sin_theta_o: f64, v: f64) -> f64 {
+    // Longitudinal scattering, the log form stays stable for low roughness
+    let a = cos_theta_i * cos_theta_o / v;
+    let b = sin_theta_i * sin_theta_o / v;
+    if v <= 0.1 {
+        (log_bessel_i0(a) - b - 1.0 / v + std::f64::consts::LN_2 + (1.0 / (2.0 * v)).ln()).exp()
+    } else {
+        (-b).exp() * bessel_i0(a) / ((1.0 / v).sinh() * 2.0 * v)
+    }
+}
+
+fn hair_phi(p: usize, gamma_o: f64, gamma_t: f64) -> f64 {
+    2.0 * p as f64 * gamma_t - 2.0 * gamma_o + p as f64 * PI
+}
+
+fn logistic(x: f64, s: f64) -> f64 {
+    let x = x.abs();
+    (-x / s).exp() / (s * (1.0 + (-x / s).exp()).powi(2))
+}
+
+fn logistic_cdf(x: f64, s: f64) -> f64 {
+    1.0 / (1.0 + (-x / s).exp())
+}
+
+fn hair_np(phi: f64, p: usize, s: f64, gamma_o: f64, gamma_t: f64) -> f64 {
+    // Azimuthal scattering, a logistic distribution around the direction of the lobe trimmed to -pi..pi
+    let mut dphi = phi - hair_phi(p, gamma_o, gamma_t);
+    while dphi > PI {
+        dphi -= 2.0 * PI;
+    }
+    while dphi < -PI {
+        dphi += 2.0 * PI;
+    }
+    logistic(dphi, s) / (logistic_cdf(PI, s) - logistic_cdf(-PI, s))
+}
+
+fn sample_trimmed_logistic(u: f64, s: f64) -> f64 {
+    let k = logistic_cdf(PI, s) - logistic_cdf(-PI, s);
+    let x = -s * (1.0 / (u * k + logistic_cdf(-PI, s)) - 1.0).ln();
+    x.clamp(-PI, PI)
+}
+
+impl Material for Hair {
+    fn get_color(&self, ray: &Ray, light_info: LightInfo, hit: &Hit, next_ray_color: Vec3) -> Vec3 {
+        let frame = match Hair::frame(hit) {
+            Some(frame) => frame,
+            None => return next_ray_color,
+        };
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let wi = frame.to_local(light_info.direction);
+        let h = 2.0 * hit.uv.1 - 1.0;
+        light_info.color * self.eval_and_pdf(wo, wi, h).0 + next_ray_color
+    }
+
+    fn scatter(&self, ray: &Ray, hit: &Hit) -> Option<Scatter> {
+        let frame = Hair::frame(hit)?;
+        let wo = frame.to_local(-ray.direction.unit_vector());
+        let h = 2.0 * hit.uv.1 - 1.0;
+        let mut rng = rand::thread_rng();
+
+        // Pick a lobe
+        let sin_theta_o = wo.x;
+        let cos_theta_o = (1.0 - sin_theta_o * sin_theta_o).max(0.0).sqrt();
+        let phi_o = wo.z.atan2(wo.y);
+        let (gamma_o, gamma_t, transmittance) = self.refracted(sin_theta_o, cos_theta_o, h);
+        let pdfs = hair_lobe_pdfs(&self.attenuations(cos_theta_o, h, transmittance));
+        let mut choice = rng.gen::<f64>();
+        let p = pdfs
+            .iter()
+            .position(|pdf| {
+                choice -= pdf;
+                choice < 0.0
+            })
+            .unwrap_or(HAIR_P_MAX);
+
+        // Sample the longitudinal angle around the mirror direction of the lobe
+        let v = self.variances()[p];
+        let (sin_theta_op, cos_theta_op) = self.rotated_theta(p, sin_theta_o, cos_theta_o);
+        let u = rng.gen::<f64>().max(1e-5);
+        let cos_theta = 1.0 + v * (u + (1.0 - u) * (-2.0 / v).exp()).ln();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let cos_phi = (2.0 * PI * rng.gen::<f64>()).cos();
+        let sin_theta_i = -cos_theta * sin_theta_op + sin_theta * cos_phi * cos_theta_op;
+        let cos_theta_i = (1.0 - sin_theta_i * sin_theta_i).max(0.0).sqrt();
+
+        // Sample the azimuthal angle
+        let dphi = if p < HAIR_P_MAX {
+            hair_phi(p, gamma_o, gamma_t)
+                + sample_trimmed_logistic(rng.gen(), self.logistic_scale())
+        } else {
+            2.0 * PI * rng.gen::<f64>()
+        };
+        let phi_i = phi_o + dphi;
+        let wi = Vec3::new(
+            sin_theta_i,
+            cos_theta_i * phi_i.cos(),
+            cos_theta_i * phi_i.sin(),
+        );
+
+        let (f, pdf) = self.eval_and_pdf(wo, wi, h);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(Scatter {
+            ray: Ray {
+                origin: hit.p,
+                direction: frame.to_world(wi),
+            },
+            attenuation: f / pdf,
+            // Reflections off the surface are glossy, the other lobes go through the fiber
+            lobe: if p == 0 {
+                Lobe::Specular
+            } else {
+                Lobe::Transmission
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fiber along x seen from a few directions, with the ray hitting it at different offsets from the middle
+    fn hair_hits() -> Vec<(Ray, Hit)> {
+        let mut hits = Vec::new();
+        for direction in [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.5, 0.3, -1.0),
+            Vec3::new(-0.8, -0.2, -0.4),
+        ] {
+            for v in [0.1, 0.5, 0.8] {
+                let ray = Ray {
+                    origin: Vec3::new(0.0, 0.0, 1.0),
+                    direction: direction.unit_vector(),
+                };
+                let hit = Hit::new(Vec3::z(), Vec3::new(0.0, 0.0, 1.0), 1.0, &ray)
+                    .with_uv(0.5, v)
+                    .with_tangent(Vec3::new(1.0, 0.0, 0.0), 1.0);
+                hits.push((ray, hit));
+            }
+        }
+        hits
+    }
+
+    fn sphere_grid() -> Vec<(Vec3, f64)> {
+        // Directions in the local frame of the fiber and their solid angles for the midpoint rule
+        // The lobes are too narrow to integrate over them with random directions
+        let (n_theta, n_phi) = (200, 400);
+        let mut grid = Vec::new();
+        for i in 0..n_theta {
+            let theta = ((i as f64 + 0.5) / n_theta as f64 - 0.5) * PI;
+            for j in 0..n_phi {
+                let phi = ((j as f64 + 0.5) / n_phi as f64 - 0.5) * 2.0 * PI;
+                let wi = Vec3::new(
+                    theta.sin(),
+                    theta.cos() * phi.cos(),
+                    theta.cos() * phi.sin(),
+                );
+                let d_omega = theta.cos() * (PI / n_theta as f64) * (2.0 * PI / n_phi as f64);
+                grid.push((wi, d_omega));
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn hair_white_furnace() {
+        // Without absorption all the light is scattered somewhere
+        let hair = Hair::new(Vec3::z(), 0.3, 0.3);
+        let grid = sphere_grid();
+        for (ray, hit) in hair_hits() {
+            let frame = Hair::frame(&hit).unwrap();
+            let wo = frame.to_local(-ray.direction);
+            let h = 2.0 * hit.uv.1 - 1.0;
+            let (f, pdf) = grid.iter().fold((0.0, 0.0), |(f, pdf), (wi, d_omega)| {
+                let (f_i, pdf_i) = hair.eval_and_pdf(wo, *wi, h);
+                (f + f_i.x * d_omega, pdf + pdf_i * d_omega)
+            });
+            assert!((f - 1.0).abs() < 0.02, "{}", f);
+            assert!((pdf - 1.0).abs() < 0.02, "{}", pdf);
+        }
+    }
+
+    #[test]
+    fn hair_sampling_matches_pdf() {
+        // Sampled directions have to land in each bin around the fiber as often as the pdf says
+        // The lobes are asymmetric for hits off the middle of the fiber, so this catches lobes sampled on the wrong side
+        let hair = Hair::new(Vec3::new(0.2, 0.5, 1.0), 0.3, 0.3);
+        let samples = 50000;
+        let bin = |wi: Vec3| {
+            let phi = wi.z.atan2(wi.y);
+            let k = ((phi + PI) / (2.0 * PI) * 8.0) as usize;
+            2 * k.min(7) + (wi.x > 0.0) as usize
+        };
+        let grid = sphere_grid();
+        for (ray, hit) in hair_hits() {
+            let frame = Hair::frame(&hit).unwrap();
+            let wo = frame.to_local(-ray.direction);
+            let h = 2.0 * hit.uv.1 - 1.0;
+            let mut sampled = [0.0; 16];
+            for _ in 0..samples {
+                if let Some(scatter) = hair.scatter(&ray, &hit) {
+                    sampled[bin(frame.to_local(scatter.ray.direction))] += 1.0 / samples as f64;
+                }
+            }
+            let mut expected = [0.0; 16];
+            for (wi, d_omega) in &grid {
+                expected[bin(*wi)] += hair.eval_and_pdf(wo, *wi, h).1 * d_omega;
+            }
+            for (s, e) in sampled.iter().zip(expected.iter()) {
+                assert!((s - e).abs() < 0.02, "{:?} {:?}", sampled, expected);
+            }
+        }
+    }
+}
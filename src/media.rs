@@ -0,0 +1,348 @@
+use crate::hittable::BoundingBox;
+use crate::medium::{HenyeyGreenstein, Medium, MediumSample};
+use crate::perlin::Perlin;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Medium with the same density everywhere
+pub struct HomogeneousMedium {
+    pub sigma_a: Vec3, // Absorption coefficient per color channel, i.e. the fraction absorbed per unit of distance
+    pub sigma_s: Vec3, // Scattering coefficient per color channel
+    pub phase: HenyeyGreenstein,
+}
+impl HomogeneousMedium {
+    #[allow(dead_code)]
+    pub fn new(albedo: Vec3, density: f64, g: f64) -> HomogeneousMedium {
+        // Albedo is the fraction of the light that gets scattered instead of absorbed when it interacts with the medium
+        HomogeneousMedium {
+            sigma_a: (Vec3::new(1.0, 1.0, 1.0) - albedo) * density,
+            sigma_s: albedo * density,
+            phase: HenyeyGreenstein { g },
+        }
+    }
+    fn sigma_t(&self) -> Vec3 {
+        self.sigma_a + self.sigma_s
+    }
+}
+impl Medium for HomogeneousMedium {
+    fn sample(&self, ray: &Ray, t_max: f64, throughput: Vec3) -> MediumSample {
+        // The distance is sampled using the density of a random color channel, picked proportional to the throughput
+        // The pdf is the weighted average over all channels so that channels with a different density are weighted correctly
+        let length = ray.direction.length();
+        let sigma_t = self.sigma_t();
+        let total = throughput.x + throughput.y + throughput.z;
+        let channel_weights = if total > 0.0 {
+            throughput / total
+        } else {
+            Vec3::new(1.0, 1.0, 1.0) / 3.0
+        };
+        let u = rand::thread_rng().gen::<f64>();
+        let channel_sigma_t = if u < channel_weights.x {
+            sigma_t.x
+        } else if u < channel_weights.x + channel_weights.y {
+            sigma_t.y
+        } else {
+            sigma_t.z
+        };
+        let max_dist = t_max * length;
+        let dist = if channel_sigma_t > 0.0 {
+            -(1.0 - rand::thread_rng().gen::<f64>()).ln() / channel_sigma_t
+        } else {
+            max_dist
+        };
+        if dist < max_dist {
+            let tr = beer_lambert(sigma_t, dist);
+            let pdf = channel_weights.dot(&(sigma_t * tr));
+            MediumSample {
+                t: Some(dist / length),
+                weight: self.sigma_s * tr / pdf,
+            }
+        } else {
+            let tr = beer_lambert(sigma_t, max_dist);
+            let pdf = channel_weights.dot(&tr);
+            MediumSample {
+                t: None,
+                weight: if pdf > 0.0 { tr / pdf } else { Vec3::z() },
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> Vec3 {
+        beer_lambert(self.sigma_t(), t_max * ray.direction.length())
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
+
+fn beer_lambert(sigma_t: Vec3, dist: f64) -> Vec3 {
+    let channel = |sigma_t: f64| {
+        if sigma_t > 0.0 {
+            (-sigma_t * dist).exp()
+        } else {
+            1.0
+        }
+    };
+    Vec3::new(channel(sigma_t.x), channel(sigma_t.y), channel(sigma_t.z))
+}
+
+// Medium with a density that varies over space, given by a grid of values that fills the bounds
+// Outside of the bounds the medium is empty
+pub struct GridMedium {
+    pub bounds: BoundingBox,
+    size: [usize; 3],
+    densities: Vec<f64>, // x changes fastest, then y, then z
+    max_density: f64,
+    pub density_scale: f64, // Extinction coefficient at a density of 1
+    pub albedo: Vec3,
+    pub phase: HenyeyGreenstein,
+}
+impl GridMedium {
+    pub fn new(bounds: BoundingBox, size: [usize; 3], densities: Vec<f64>) -> GridMedium {
+        let max_density = densities.iter().fold(0.0, |acc: f64, d| acc.max(*d));
+        GridMedium {
+            bounds,
+            size,
+            densities,
+            max_density,
+            density_scale: 1.0,
+            albedo: Vec3::new(1.0, 1.0, 1.0),
+            phase: HenyeyGreenstein { g: 0.0 },
+        }
+    }
+    #[allow(dead_code)]
+    pub fn with_density_scale(mut self, density_scale: f64) -> GridMedium {
+        self.density_scale = density_scale;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_albedo(mut self, albedo: Vec3) -> GridMedium {
+        self.albedo = albedo;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_phase(mut self, g: f64) -> GridMedium {
+        self.phase = HenyeyGreenstein { g };
+        self
+    }
+
+    pub fn from_fn(
+        bounds: BoundingBox,
+        size: [usize; 3],
+        density: impl Fn(Vec3) -> f64,
+    ) -> GridMedium {
+        // Evaluates the density at the center of every cell of the grid
+        let extent = bounds.higher() - bounds.lower();
+        let mut densities = Vec::with_capacity(size[0] * size[1] * size[2]);
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let p = bounds.lower()
+                        + extent
+                            * Vec3::new(
+                                (x as f64 + 0.5) / size[0] as f64,
+                                (y as f64 + 0.5) / size[1] as f64,
+                                (z as f64 + 0.5) / size[2] as f64,
+                            );
+                    densities.push(density(p).max(0.0));
+                }
+            }
+        }
+        GridMedium::new(bounds, size, densities)
+    }
+
+    #[allow(dead_code)]
+    pub fn cloud(bounds: BoundingBox, size: [usize; 3], seed: u64) -> GridMedium {
+        // Puffy noise that fades out towards the bounds
+        let perlin = Perlin::new(seed);
+        let center = (bounds.lower() + bounds.higher()) * 0.5;
+        let half_extent = (bounds.higher() - bounds.lower()) * 0.5;
+        GridMedium::from_fn(bounds, size, |p| {
+            let local = p - center;
+            let r = Vec3::new(
+                local.x / half_extent.x,
+                local.y / half_extent.y,
+                local.z / half_extent.z,
+            )
+            .length();
+            1.0 - r + 0.6 * perlin.fbm(&(local * 4.0), 5)
+        })
+    }
+
+    #[allow(dead_code)]
+    pub fn from_file(
+        path: &Path,
+        bounds: BoundingBox,
+    ) -> Result<GridMedium, Box<dyn std::error::Error>> {
+        // Reads a 3D grid of densities from a .nrrd file, or a .nhdr header with a separate raw data file
+        // Integer values are mapped to [0, 1]
+        let data = fs::read(path)?;
+        let header_end = data
+            .windows(2)
+            .position(|w| w == b"\n\n")
+            .map_or(data.len(), |i| i + 2);
+        let header = String::from_utf8_lossy(&data[..header_end]);
+        let mut lines = header.lines();
+        if !lines.next().is_some_and(|l| l.starts_with("NRRD")) {
+            return Err(format!("Not a NRRD file: {}", path.display()).into());
+        }
+        let mut fields: HashMap<String, String> = HashMap::new();
+        for line in lines {
+            // Lines starting with # are comments, key:=value lines are key/value pairs that we don't need
+            if let Some((key, value)) = line.split_once(": ") {
+                if !key.starts_with('#') {
+                    fields.insert(key.trim().to_lowercase(), value.trim().to_string());
+                }
+            }
+        }
+        let field = |key: &str| fields.get(key).ok_or(format!("Missing NRRD field {}", key));
+
+        if field("dimension")? != "3" {
+            return Err("Only NRRD files with 3 dimensions are supported".into());
+        }
+        let sizes: Vec<usize> = field("sizes")?
+            .split_whitespace()
+            .map(|s| s.parse())
+            .collect::<Result<_, _>>()?;
+        let size = [sizes[0], sizes[1], sizes[2]];
+        let count = size[0] * size[1] * size[2];
+        let little_endian = fields.get("endian").is_none_or(|e| e == "little");
+
+        let values = match fields.get("data file").or(fields.get("datafile")) {
+            Some(file) => fs::read(path.parent().unwrap_or(Path::new("")).join(file))?,
+            None => data[header_end..].to_vec(),
+        };
+        let densities = match field("encoding")?.as_str() {
+            "raw" => GridMedium::parse_raw(&values, field("type")?, little_endian, count)?,
+            "ascii" | "text" | "txt" => String::from_utf8_lossy(&values)
+                .split_whitespace()
+                .take(count)
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<_, _>>()?,
+            encoding => return Err(format!("Unsupported NRRD encoding: {}", encoding).into()),
+        };
+        if densities.len() < count {
+            return Err("Unexpected end of NRRD data".into());
+        }
+        Ok(GridMedium::new(bounds, size, densities))
+    }
+
+    fn parse_raw(
+        bytes: &[u8],
+        value_type: &str,
+        little_endian: bool,
+        count: usize,
+    ) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        let value_size = match value_type {
+            "uchar" | "unsigned char" | "uint8" | "uint8_t" => 1,
+            "ushort" | "unsigned short" | "uint16" | "uint16_t" => 2,
+            "float" => 4,
+            "double" => 8,
+            _ => return Err(format!("Unsupported NRRD type: {}", value_type).into()),
+        };
+        let bytes = bytes
+            .get(..count * value_size)
+            .ok_or("Unexpected end of NRRD data")?;
+        Ok(bytes
+            .chunks(value_size)
+            .map(|c| {
+                let mut b = c.to_vec();
+                if !little_endian {
+                    b.reverse();
+                }
+                match value_size {
+                    1 => b[0] as f64 / 255.0,
+                    2 => u16::from_le_bytes([b[0], b[1]]) as f64 / 65535.0,
+                    4 => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+                    _ => f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+                }
+            })
+            .collect())
+    }
+
+    fn density_at(&self, p: Vec3) -> f64 {
+        // Trilinear interpolation between the centers of the cells
+        let extent = self.bounds.higher() - self.bounds.lower();
+        let local = p - self.bounds.lower();
+        let coords = [
+            local.x / extent.x * self.size[0] as f64 - 0.5,
+            local.y / extent.y * self.size[1] as f64 - 0.5,
+            local.z / extent.z * self.size[2] as f64 - 0.5,
+        ];
+        let mut density = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = 0;
+            let mut stride = 1;
+            for (axis, coord) in coords.iter().enumerate() {
+                let low = coord.floor();
+                let frac = coord - low;
+
+                let (cell, w) = if corner >> axis & 1 == 0 {
+                    (low, 1.0 - frac)
+                } else {
+                    (low + 1.0, frac)
+                };
+                let cell = (cell.max(0.0) as usize).min(self.size[axis] - 1);
+                weight *= w;
+                index += cell * stride;
+                stride *= self.size[axis];
+            }
+            density += weight * self.densities[index];
+        }
+        density
+    }
+}
+impl Medium for GridMedium {
+    fn sample(&self, ray: &Ray, t_max: f64, _throughput: Vec3) -> MediumSample {
+        // Delta tracking, collisions get sampled as if the medium had its maximum density everywhere
+        // Collisions with the made up part of the medium are ignored and the ray keeps going
+        let passes = MediumSample {
+            t: None,
+            weight: Vec3::new(1.0, 1.0, 1.0),
+        };
+        let sigma_max = self.max_density * self.density_scale * ray.direction.length();
+        let (mut t, t_end) = match self.bounds.intersection(ray, 0.0, t_max) {
+            Some(interval) if sigma_max > 0.0 => interval,
+            _ => return passes,
+        };
+        loop {
+            t -= (1.0 - rand::thread_rng().gen::<f64>()).ln() / sigma_max;
+            if t >= t_end {
+                return passes;
+            }
+            if rand::thread_rng().gen::<f64>() < self.density_at(ray.at(t)) / self.max_density {
+                return MediumSample {
+                    t: Some(t),
+                    weight: self.albedo,
+                };
+            }
+        }
+    }
+
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> Vec3 {
+        // Ratio tracking, every made up collision keeps the fraction of the density that is not real
+        let sigma_max = self.max_density * self.density_scale * ray.direction.length();
+        let (mut t, t_end) = match self.bounds.intersection(ray, 0.0, t_max) {
+            Some(interval) if sigma_max > 0.0 => interval,
+            _ => return Vec3::new(1.0, 1.0, 1.0),
+        };
+        let mut transmittance = 1.0;
+        loop {
+            t -= (1.0 - rand::thread_rng().gen::<f64>()).ln() / sigma_max;
+            if t >= t_end {
+                return Vec3::new(transmittance, transmittance, transmittance);
+            }
+            transmittance *= 1.0 - self.density_at(ray.at(t)) / self.max_density;
+        }
+    }
+
+    fn phase(&self) -> &HenyeyGreenstein {
+        &self.phase
+    }
+}
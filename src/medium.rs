@@ -0,0 +1,51 @@
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::util::PI;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+pub struct MediumSample {
+    pub t: Option<f64>, // Where along the ray the light scatters in the medium, None if it reaches the end of the ray
+    pub weight: Vec3, // Transmittance, times the scattering coefficient if it scatters, divided by the pdf of the sample
+}
+
+// Participating medium like fog or smoke that fills the space inside of an object or the whole scene
+pub trait Medium: Sync {
+    // Samples the distance the ray travels through the medium before it scatters, up to t_max
+    // The throughput of the path tells how much each color channel still matters
+    fn sample(&self, ray: &Ray, t_max: f64, throughput: Vec3) -> MediumSample;
+    // Fraction of the light that travels through the medium from the origin of the ray to t_max
+    fn transmittance(&self, ray: &Ray, t_max: f64) -> Vec3;
+    fn phase(&self) -> &HenyeyGreenstein;
+}
+
+// Phase function that describes in which direction light scatters in a medium
+pub struct HenyeyGreenstein {
+    pub g: f64, // From -1 to 1, negative values scatter light back, positive ones forward and 0 is uniform
+}
+impl HenyeyGreenstein {
+    pub fn eval(&self, direction: Vec3, scattered: Vec3) -> f64 {
+        let cos_theta = direction.unit_vector().dot(&scattered.unit_vector());
+        let denom = 1.0 + self.g * self.g - 2.0 * self.g * cos_theta;
+        (1.0 - self.g * self.g) / (4.0 * PI * denom * denom.sqrt())
+    }
+
+    pub fn sample(&self, direction: Vec3) -> Vec3 {
+        // Samples the scattered direction proportional to the phase function
+        let u = rand::thread_rng().gen::<f64>();
+        let g = self.g;
+        let cos_theta = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+            ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rand::thread_rng().gen::<f64>();
+        Frame::new(direction.unit_vector()).to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
@@ -1,54 +1,324 @@
-use crate::hittable::{hit_list, BoundingBox, Hit, Hittable};
+use crate::alpha_mask::AlphaMask;
+use crate::hittable::{BoundingBox, Hit, Hittable};
+use crate::polygon::Polygon;
 use crate::ray::Ray;
-use crate::triangle::Triangle;
+use crate::subdivision::ControlMesh;
+use crate::texture::Texture;
+use crate::textures::{ConstantTexture, ImageTexture};
+use crate::triangle::{uv_derivatives, FaceGeometry, Triangle, WatertightRay};
 use crate::vec3::Vec3;
-use std::fs::File;
+use std::collections::HashMap;
+use std::fs::{self, File};
 use std::io::{prelude::*, BufReader};
 use std::path;
 
+// Indices of a vertex of a face in an .obj file, they start at 1
+#[derive(Clone, Copy)]
+struct FaceVertex {
+    vertex: usize,
+    tex_coord: Option<usize>,
+    normal: Option<usize>,
+}
+impl FaceVertex {
+    fn parse(s: &str) -> Result<FaceVertex, Box<dyn std::error::Error>> {
+        // Either v, v/vt, v//vn or v/vt/vn
+        let mut indices = s.split('/');
+        let vertex = indices.next().unwrap().parse::<usize>()?;
+        let tex_coord = match indices.next() {
+            None | Some("") => None,
+            Some(i) => Some(i.parse::<usize>()?),
+        };
+        // The normals in .obj files aren't used, faces are flat unless they are subdivided
+        Ok(FaceVertex {
+            vertex,
+            tex_coord,
+            normal: None,
+        })
+    }
+}
+
+// Faces with a lower opacity in their material are cut out
+const ALPHA_CUTOFF: f64 = 0.5;
+
+// Changes to the geometry that are made while loading the mesh
+pub struct MeshOptions {
+    pub subdivision_levels: usize,
+    pub crease_angle: f64, // In degrees, edges where the faces meet at a sharper angle stay sharp when subdividing
+    pub crease_edges: Vec<(usize, usize)>, // Edges that stay sharp regardless of the angle, by the indices of their vertices starting at 0
+    // Moves the vertices along their normals by the value of the texture times the scale, after subdividing
+    pub displacement: Option<(Box<dyn Texture>, f64)>,
+}
+impl Default for MeshOptions {
+    fn default() -> MeshOptions {
+        MeshOptions {
+            subdivision_levels: 0,
+            crease_angle: 180.0,
+            crease_edges: Vec::new(),
+            displacement: None,
+        }
+    }
+}
+impl MeshOptions {
+    #[allow(dead_code)]
+    pub fn with_subdivision(mut self, levels: usize, crease_angle: f64) -> MeshOptions {
+        self.subdivision_levels = levels;
+        self.crease_angle = crease_angle;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_crease_edges(mut self, edges: Vec<(usize, usize)>) -> MeshOptions {
+        self.crease_edges = edges;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn with_displacement(mut self, texture: Box<dyn Texture>, scale: f64) -> MeshOptions {
+        self.displacement = Some((texture, scale));
+        self
+    }
+}
+
+// Geometry as it's read from a file, indices start at 1 like in .obj files
+struct MeshData {
+    vertices: Vec<Vec3>,
+    tex_coords: Vec<(f64, f64)>,
+    normals: Option<Vec<Vec3>>, // Indexed by the normals of the face vertices
+    colors: Option<Vec<Vec3>>,  // For every vertex
+    polygons: Vec<Vec<FaceVertex>>,
+    polygon_alpha_masks: Vec<Option<usize>>,
+    alpha_masks: Vec<AlphaMask>,
+    warnings: Vec<String>, // Problems with the file that were worked around
+}
+
+impl MeshData {
+    fn check_indices(&self) -> Result<(), Box<dyn std::error::Error>> {
+        // Files can refer to anything, make sure that the faces only use what's there
+        if self.polygons.is_empty() {
+            return Err("Mesh without faces".into());
+        }
+        let in_range = |index: Option<usize>, len: usize| index.is_none_or(|i| i >= 1 && i <= len);
+        let normals = self.normals.as_ref().map_or(0, |normals| normals.len());
+        for v in self.polygons.iter().flatten() {
+            if !in_range(Some(v.vertex), self.vertices.len()) {
+                return Err(format!("Vertex index {} out of range", v.vertex).into());
+            }
+            if !in_range(v.tex_coord, self.tex_coords.len()) {
+                return Err(format!(
+                    "Texture coordinate index {} out of range",
+                    v.tex_coord.unwrap()
+                )
+                .into());
+            }
+            if !in_range(v.normal, normals) {
+                return Err(format!("Normal index {} out of range", v.normal.unwrap()).into());
+            }
+        }
+        if self
+            .colors
+            .as_ref()
+            .is_some_and(|colors| colors.len() != self.vertices.len())
+        {
+            return Err("Not every vertex has a color".into());
+        }
+        Ok(())
+    }
+}
+
+// Triangle mesh with shared vertices, the attributes of a vertex are at the same index in each buffer
 pub struct Mesh {
-    faces: Vec<Triangle>,
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    tex_coords: Option<Vec<(f64, f64)>>,
+    tangents: Option<Vec<Option<(Vec3, f64)>>>, // Missing where the texture coordinates are degenerate
+    colors: Option<Vec<Vec3>>,
+    faces: Vec<[u32; 3]>, // Indices of the vertices of every triangle
+    face_geometry: Vec<FaceGeometry>, // Normal and tangent of every face, so that hits don't compute them again
+    face_alpha_masks: Vec<Option<usize>>, // Index into alpha_masks for every face
+    alpha_masks: Vec<AlphaMask>,
     offset: Vec3,
     bounds: BoundingBox,
+    pub warnings: Vec<String>, // Problems with the file that were worked around, e.g. a missing material library
 }
 impl Mesh {
     pub fn from_file(path: &path::Path, offset: Vec3) -> Result<Mesh, Box<dyn std::error::Error>> {
-        let file = File::open(path)?;
-        let mut faces = Vec::new();
-        let mut vertices: Vec<Vec3> = Vec::new();
-        let reader = BufReader::new(file);
-        for line in reader.lines() {
-            let line = line?;
-            let mut parts = line.split_whitespace();
-            let line_type = parts.next();
-            match line_type {
-                Some("v") => {
-                    // Vertex
-                    let parsed: Result<Vec<f64>, _> = parts.map(|s| s.parse::<f64>()).collect();
-                    let vs: Vec<f64> = parsed?;
-                    vertices.push(Vec3::new(vs[0], vs[1], vs[2]))
-                }
-                Some("f") => {
-                    // Face
-                    // Currently only works with triangles
-                    let parsed: Result<Vec<usize>, _> = parts
-                        .map(|s| s.split("/").next().unwrap().parse::<usize>())
-                        .collect();
-                    let vertex_indicies: Vec<usize> = parsed?;
-                    faces.push(Triangle {
-                        p1: vertices[vertex_indicies[0] - 1],
-                        p2: vertices[vertex_indicies[1] - 1],
-                        p3: vertices[vertex_indicies[2] - 1],
+        Mesh::from_file_with_options(path, offset, MeshOptions::default())
+    }
+
+    pub fn from_file_with_options(
+        path: &path::Path,
+        offset: Vec3,
+        options: MeshOptions,
+    ) -> Result<Mesh, Box<dyn std::error::Error>> {
+        let data = match path.extension().and_then(|e| e.to_str()) {
+            Some("obj") => Mesh::read_obj(path)?,
+            Some("ply") => Mesh::read_ply(path)?,
+            Some("stl") => Mesh::read_stl(path)?,
+            _ => return Err(format!("Unsupported mesh format: {}", path.display()).into()),
+        };
+        data.check_indices()?;
+        Mesh::from_data(data, offset, options)
+    }
+
+    pub fn from_buffers(
+        vertices: Vec<Vec3>,
+        normals: Option<Vec<Vec3>>,
+        tex_coords: Option<Vec<(f64, f64)>>,
+        colors: Option<Vec<Vec3>>,
+        triangles: &[[usize; 3]],
+    ) -> Result<Mesh, Box<dyn std::error::Error>> {
+        // Vertex attributes with triangles as indices into them starting at 0, like in glTF files
+        if let Some(i) = triangles.iter().flatten().find(|i| **i >= vertices.len()) {
+            return Err(format!("Vertex index {} out of range", i).into());
+        }
+        let has_tex_coords = tex_coords.is_some();
+        let has_normals = normals.is_some();
+        let data = MeshData {
+            vertices,
+            tex_coords: tex_coords.unwrap_or_default(),
+            normals,
+            colors,
+            polygons: triangles
+                .iter()
+                .map(|triangle| {
+                    triangle
+                        .iter()
+                        .map(|i| FaceVertex {
+                            vertex: i + 1,
+                            tex_coord: has_tex_coords.then_some(i + 1),
+                            normal: has_normals.then_some(i + 1),
+                        })
+                        .collect()
+                })
+                .collect(),
+            polygon_alpha_masks: vec![None; triangles.len()],
+            alpha_masks: Vec::new(),
+            warnings: Vec::new(),
+        };
+        Mesh::from_data(data, Vec3::z(), MeshOptions::default())
+    }
+
+    fn from_data(
+        data: MeshData,
+        offset: Vec3,
+        options: MeshOptions,
+    ) -> Result<Mesh, Box<dyn std::error::Error>> {
+        let MeshData {
+            mut vertices,
+            mut tex_coords,
+            mut normals,
+            mut colors,
+            mut polygons,
+            mut polygon_alpha_masks,
+            alpha_masks,
+            warnings,
+        } = data;
+        if options.subdivision_levels > 0 || options.displacement.is_some() {
+            // The surface changes shape, so the normals are computed again from the new positions
+            // They are smooth except at the creases, colors are interpolated like the texture coordinates
+            let mut control_mesh = ControlMesh::new(
+                vertices,
+                tex_coords,
+                colors,
+                polygons
+                    .iter()
+                    .map(|polygon| {
+                        polygon
+                            .iter()
+                            .map(|v| (v.vertex - 1, v.tex_coord.map(|t| t - 1)))
+                            .collect()
                     })
-                }
-                Some("#") => continue, // Comment, ignore
-                Some(_) => continue,   // Anything else, unhandled
-                None => continue,      // Empty line
+                    .collect(),
+            );
+            control_mesh.mark_creases(options.crease_angle.to_radians());
+            control_mesh.mark_sharp_edges(&options.crease_edges);
+            let mut subdivided = control_mesh.subdivide(options.subdivision_levels);
+            if let Some((texture, scale)) = &options.displacement {
+                Mesh::displace(&mut subdivided, texture.as_ref(), *scale);
             }
+            let (vertex_normals, corner_normals) = subdivided.vertex_normals();
+            polygons = subdivided
+                .faces
+                .iter()
+                .zip(corner_normals)
+                .map(|(face, face_normals)| {
+                    face.iter()
+                        .zip(face_normals)
+                        .map(|((v, t), n)| FaceVertex {
+                            vertex: v + 1,
+                            tex_coord: t.map(|t| t + 1),
+                            normal: Some(n + 1),
+                        })
+                        .collect()
+                })
+                .collect();
+            polygon_alpha_masks = subdivided
+                .parents
+                .iter()
+                .map(|parent| polygon_alpha_masks[*parent])
+                .collect();
+            vertices = subdivided.positions;
+            tex_coords = subdivided.tex_coords;
+            colors = subdivided.colors;
+            normals = Some(vertex_normals);
         }
-        Ok(Mesh {
+
+        let mut face_indices: Vec<[FaceVertex; 3]> = Vec::new();
+        let mut face_alpha_masks: Vec<Option<usize>> = Vec::new();
+        for (polygon, alpha_mask) in polygons.iter().zip(polygon_alpha_masks) {
+            let triangles = if polygon.len() == 3 {
+                vec![[0, 1, 2]]
+            } else {
+                let outline: Vec<Vec3> = polygon.iter().map(|v| vertices[v.vertex - 1]).collect();
+                Polygon::triangulate(&outline, &[])
+            };
+            for [a, b, c] in triangles {
+                face_indices.push([polygon[a], polygon[b], polygon[c]]);
+                face_alpha_masks.push(alpha_mask);
+            }
+        }
+        let tangents = Mesh::vertex_tangents(&vertices, &tex_coords, &face_indices);
+        // Vertices that have the same position but different texture coordinates or normals are split,
+        // so that all attributes of a vertex can share one index
+        let mut shared_vertices: HashMap<(usize, Option<usize>, Option<usize>), u32> =
+            HashMap::new();
+        let mut keys: Vec<(usize, Option<usize>, Option<usize>)> = Vec::new();
+        let faces = face_indices
+            .iter()
+            .map(|face| {
+                face.map(|v| {
+                    *shared_vertices
+                        .entry((v.vertex, v.tex_coord, v.normal))
+                        .or_insert_with(|| {
+                            keys.push((v.vertex, v.tex_coord, v.normal));
+                            (keys.len() - 1) as u32
+                        })
+                })
+            })
+            .collect();
+        let has_tex_coords = keys.iter().any(|(_, t, _)| t.is_some());
+        let mut mesh = Mesh {
+            positions: keys.iter().map(|(v, _, _)| vertices[v - 1]).collect(),
+            normals: normals.map(|normals| {
+                keys.iter()
+                    .map(|(_, _, n)| n.map_or(Vec3::z(), |n| normals[n - 1]))
+                    .collect()
+            }),
+            tex_coords: has_tex_coords.then(|| {
+                keys.iter()
+                    .map(|(_, t, _)| t.map_or((0.0, 0.0), |t| tex_coords[t - 1]))
+                    .collect()
+            }),
+            tangents: has_tex_coords.then(|| {
+                keys.iter()
+                    .map(|(v, t, _)| t.and_then(|t| tangents.get(&(*v, t)).copied()))
+                    .collect()
+            }),
+            colors: colors.map(|colors| keys.iter().map(|(v, _, _)| colors[v - 1]).collect()),
             faces,
+            face_geometry: Vec::new(),
+            face_alpha_masks,
+            alpha_masks,
             offset,
+            warnings,
             bounds: BoundingBox::new(
                 vertices
                     .iter()
@@ -87,24 +357,552 @@ impl Mesh {
                     .z
                     + offset.z,
             ),
+        };
+        mesh.face_geometry = mesh
+            .faces
+            .iter()
+            .map(|face| mesh.triangle(face).face_geometry())
+            .collect();
+        Ok(mesh)
+    }
+}
+impl Mesh {
+    fn read_obj(path: &path::Path) -> Result<MeshData, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        let mut polygons: Vec<Vec<FaceVertex>> = Vec::new();
+        let mut polygon_alpha_masks: Vec<Option<usize>> = Vec::new();
+        let mut alpha_masks: Vec<AlphaMask> = Vec::new();
+        let mut material_alpha_masks: HashMap<String, usize> = HashMap::new();
+        let mut current_alpha_mask: Option<usize> = None;
+        let mut warnings: Vec<String> = Vec::new();
+        let mut vertices: Vec<Vec3> = Vec::new();
+        let mut tex_coords: Vec<(f64, f64)> = Vec::new();
+        let reader = BufReader::new(file);
+        for line in reader.lines() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            let line_type = parts.next();
+            match line_type {
+                Some("v") => {
+                    // Vertex
+                    let parsed: Result<Vec<f64>, _> = parts.map(|s| s.parse::<f64>()).collect();
+                    let vs: Vec<f64> = parsed?;
+                    if vs.len() < 3 {
+                        return Err("Vertex with less than three coordinates".into());
+                    }
+                    vertices.push(Vec3::new(vs[0], vs[1], vs[2]))
+                }
+                Some("vt") => {
+                    // Texture coordinate, v is optional
+                    let parsed: Result<Vec<f64>, _> = parts.map(|s| s.parse::<f64>()).collect();
+                    let vts: Vec<f64> = parsed?;
+                    let u = *vts.first().ok_or("Texture coordinate without values")?;
+                    tex_coords.push((u, vts.get(1).copied().unwrap_or(0.0)))
+                }
+                Some("f") => {
+                    // Face, polygons with more than three vertices are split into triangles later
+                    let parsed: Result<Vec<FaceVertex>, _> = parts.map(FaceVertex::parse).collect();
+                    let indices = parsed?;
+                    if indices.len() < 3 {
+                        return Err(format!("Face with {} vertices", indices.len()).into());
+                    }
+                    polygons.push(indices);
+                    polygon_alpha_masks.push(current_alpha_mask);
+                }
+                Some("mtllib") => {
+                    // Material library, paths are relative to the .obj file
+                    let mtl_path = path
+                        .parent()
+                        .unwrap_or(path::Path::new(""))
+                        .join(parts.collect::<Vec<&str>>().join(" "));
+                    // The mesh is still usable without its materials, it just won't get alpha masks
+                    let materials = match Mesh::read_mtl_alpha_masks(&mtl_path) {
+                        Ok(materials) => materials,
+                        Err(e) => {
+                            warnings.push(format!(
+                                "Ignoring material library {}: {}",
+                                mtl_path.display(),
+                                e
+                            ));
+                            Vec::new()
+                        }
+                    };
+                    for (name, alpha_mask) in materials {
+                        material_alpha_masks.insert(name, alpha_masks.len());
+                        alpha_masks.push(alpha_mask);
+                    }
+                }
+                Some("usemtl") => {
+                    current_alpha_mask = parts
+                        .next()
+                        .and_then(|name| material_alpha_masks.get(name).copied());
+                }
+                Some("#") => continue, // Comment, ignore
+                Some(_) => continue,   // Anything else, unhandled
+                None => continue,      // Empty line
+            }
+        }
+        Ok(MeshData {
+            vertices,
+            tex_coords,
+            normals: None,
+            colors: None,
+            polygons,
+            polygon_alpha_masks,
+            alpha_masks,
+            warnings,
+        })
+    }
+
+    fn read_ply(path: &path::Path) -> Result<MeshData, Box<dyn std::error::Error>> {
+        // Supports the ASCII and both binary formats, with normals, colors and texture coordinates of the vertices
+        let data = fs::read(path)?;
+        let header_end = data
+            .windows(11)
+            .position(|w| w == b"end_header\n" || w == b"end_header\r")
+            .ok_or("Missing end of PLY header")?;
+        let header = String::from_utf8_lossy(&data[..header_end]);
+        let mut body = header_end + "end_header".len();
+        // The header ends with a single newline, which might be \r\n
+        if data.get(body) == Some(&b'\r') {
+            body += 1;
+        }
+        body += 1;
+
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = Vec::new();
+        for line in header.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts[..] {
+                ["format", f, _] => format = Some(f.to_string()),
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count.parse()?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count_type, item_type, name] => elements
+                    .last_mut()
+                    .ok_or("PLY property before element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        data_type: item_type.to_string(),
+                        count_type: Some(count_type.to_string()),
+                    }),
+                ["property", data_type, name] => elements
+                    .last_mut()
+                    .ok_or("PLY property before element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        data_type: data_type.to_string(),
+                        count_type: None,
+                    }),
+                _ => continue,
+            }
+        }
+        let mut reader = match format.as_deref() {
+            Some("ascii") => PlyReader::Ascii(
+                String::from_utf8_lossy(&data[body..])
+                    .split_whitespace()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>()
+                    .into_iter(),
+            ),
+            Some("binary_little_endian") => PlyReader::Binary(&data[body..], false),
+            Some("binary_big_endian") => PlyReader::Binary(&data[body..], true),
+            _ => return Err("Unsupported PLY format".into()),
+        };
+
+        let mut mesh = MeshData {
+            vertices: Vec::new(),
+            tex_coords: Vec::new(),
+            normals: None,
+            colors: None,
+            polygons: Vec::new(),
+            polygon_alpha_masks: Vec::new(),
+            alpha_masks: Vec::new(),
+            warnings: Vec::new(),
+        };
+        for element in &elements {
+            let index = |names: &[&str]| {
+                element
+                    .properties
+                    .iter()
+                    .position(|p| names.contains(&p.name.as_str()))
+            };
+            let position = [index(&["x"]), index(&["y"]), index(&["z"])];
+            let normal = [index(&["nx"]), index(&["ny"]), index(&["nz"])];
+            let color = [
+                index(&["red", "r"]),
+                index(&["green", "g"]),
+                index(&["blue", "b"]),
+            ];
+            let tex_coord = [
+                index(&["u", "s", "texture_u", "texture_s"]),
+                index(&["v", "t", "texture_v", "texture_t"]),
+            ];
+            let face_indices = index(&["vertex_indices", "vertex_index"]);
+            for _ in 0..element.count {
+                let values: Vec<Vec<f64>> = element
+                    .properties
+                    .iter()
+                    .map(|property| reader.read_property(property))
+                    .collect::<Result<_, _>>()?;
+                // Lists are only used for the faces, other properties that are empty lists count as missing
+                let get = |indices: &[Option<usize>]| -> Option<Vec<f64>> {
+                    indices
+                        .iter()
+                        .map(|i| i.and_then(|i| values[i].first().copied()))
+                        .collect()
+                };
+                match element.name.as_str() {
+                    "vertex" => {
+                        let p = get(&position).ok_or("PLY vertex without position")?;
+                        mesh.vertices.push(Vec3::new(p[0], p[1], p[2]));
+                        if let Some(n) = get(&normal) {
+                            mesh.normals
+                                .get_or_insert_with(Vec::new)
+                                .push(Vec3::new(n[0], n[1], n[2]));
+                        }
+                        if let Some(c) = get(&color) {
+                            // Integer colors go up to 255, colors are stored with a gamma of 2 like images
+                            let data_type =
+                                element.properties[color[0].unwrap()].data_type.as_str();
+                            let integer =
+                                !matches!(data_type, "float" | "float32" | "double" | "float64");
+                            let scale = if integer { 1.0 / 255.0 } else { 1.0 };
+                            mesh.colors.get_or_insert_with(Vec::new).push(Vec3::new(
+                                (c[0] * scale).powi(2),
+                                (c[1] * scale).powi(2),
+                                (c[2] * scale).powi(2),
+                            ));
+                        }
+                        if let Some(t) = get(&tex_coord) {
+                            mesh.tex_coords.push((t[0], t[1]));
+                        }
+                    }
+                    "face" => {
+                        let indices = &values[face_indices.ok_or("PLY face without vertices")?];
+                        if indices.len() < 3 {
+                            return Err(format!("Face with {} vertices", indices.len()).into());
+                        }
+                        if let Some(i) = indices.iter().find(|i| **i < 0.0) {
+                            return Err(format!("Vertex index {} out of range", i).into());
+                        }
+                        // Texture coordinates and normals belong to the vertices, so they have the same indices
+                        let has_tex_coords = !mesh.tex_coords.is_empty();
+                        let has_normals = mesh.normals.is_some();
+                        mesh.polygons.push(
+                            indices
+                                .iter()
+                                .map(|i| FaceVertex {
+                                    vertex: *i as usize + 1,
+                                    tex_coord: has_tex_coords.then_some(*i as usize + 1),
+                                    normal: has_normals.then_some(*i as usize + 1),
+                                })
+                                .collect(),
+                        );
+                        mesh.polygon_alpha_masks.push(None);
+                    }
+                    _ => continue, // Other elements are read but not used
+                }
+            }
+        }
+        Ok(mesh)
+    }
+
+    fn read_stl(path: &path::Path) -> Result<MeshData, Box<dyn std::error::Error>> {
+        // Supports the ASCII and the binary format, the normals of the facets are ignored since they are flat anyway
+        let data = fs::read(path)?;
+        let binary_size = data
+            .get(80..84)
+            .map(|count| 84 + 50 * u32::from_le_bytes(count.try_into().unwrap()) as usize);
+        // ASCII files start with "solid", but some binary files do as well, so check the size
+        let triangles: Vec<[Vec3; 3]> = if binary_size == Some(data.len()) {
+            data[84..]
+                .chunks_exact(50)
+                .map(|facet| {
+                    let float = |i: usize| {
+                        f32::from_le_bytes(facet[4 * i..4 * i + 4].try_into().unwrap()) as f64
+                    };
+                    // The first three floats are the normal
+                    [
+                        Vec3::new(float(3), float(4), float(5)),
+                        Vec3::new(float(6), float(7), float(8)),
+                        Vec3::new(float(9), float(10), float(11)),
+                    ]
+                })
+                .collect()
+        } else {
+            let text = String::from_utf8_lossy(&data);
+            let vertices: Vec<Vec3> = text
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("vertex "))
+                .map(|line| {
+                    let vs: Vec<f64> = line
+                        .split_whitespace()
+                        .map(|s| s.parse::<f64>())
+                        .collect::<Result<_, _>>()?;
+                    if vs.len() != 3 {
+                        return Err("Invalid STL vertex".into());
+                    }
+                    Ok(Vec3::new(vs[0], vs[1], vs[2]))
+                })
+                .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+            if !vertices.len().is_multiple_of(3) {
+                return Err("STL facets need three vertices".into());
+            }
+            vertices.chunks(3).map(|v| [v[0], v[1], v[2]]).collect()
+        };
+
+        // Every facet has its own copy of its vertices, merge the ones that are the same
+        let mut indices: HashMap<[u64; 3], usize> = HashMap::new();
+        let mut vertices: Vec<Vec3> = Vec::new();
+        let polygons = triangles
+            .iter()
+            .map(|triangle| {
+                triangle
+                    .iter()
+                    .map(|p| {
+                        let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+                        let vertex = *indices.entry(key).or_insert_with(|| {
+                            vertices.push(*p);
+                            vertices.len()
+                        });
+                        FaceVertex {
+                            vertex,
+                            tex_coord: None,
+                            normal: None,
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Ok(MeshData {
+            vertices,
+            tex_coords: Vec::new(),
+            normals: None,
+            colors: None,
+            polygon_alpha_masks: vec![None; triangles.len()],
+            polygons,
+            alpha_masks: Vec::new(),
+            warnings: Vec::new(),
         })
     }
+
+    fn displace(mesh: &mut ControlMesh, texture: &dyn Texture, scale: f64) {
+        // Vertices move along the sum of the normals of the faces around them, weighted by their area
+        // The direction is the same for all faces, even at creases, so that the surface doesn't tear
+        let mut normals = vec![Vec3::z(); mesh.positions.len()];
+        // Texture coordinate of every vertex, at seams one of them is used
+        let mut vertex_tex_coords: Vec<Option<(f64, f64)>> = vec![None; mesh.positions.len()];
+        for face in &mesh.faces {
+            let n = face.len();
+            let normal = (0..n).fold(Vec3::z(), |acc, i| {
+                let a = mesh.positions[face[i].0];
+                let b = mesh.positions[face[(i + 1) % n].0];
+                acc + a.cross(&b)
+            });
+            for (v, t) in face {
+                normals[*v] += normal;
+                if let Some(t) = t {
+                    vertex_tex_coords[*v].get_or_insert(mesh.tex_coords[*t]);
+                }
+            }
+        }
+        for (i, vertex) in mesh.positions.iter_mut().enumerate() {
+            if normals[i].near_zero() {
+                continue;
+            }
+            let normal = normals[i].unit_vector();
+            let (u, v) = vertex_tex_coords[i].unwrap_or((0.0, 0.0));
+            let hit = Hit {
+                p: *vertex,
+                normal,
+                t: 0.0,
+                front_face: true,
+                barycentric: None,
+                uv: (u, v),
+                tangent: None,
+                color: None,
+            };
+            *vertex += normal * (texture.scalar(&hit) * scale);
+        }
+    }
+
+    fn read_mtl_alpha_masks(
+        path: &path::Path,
+    ) -> Result<Vec<(String, AlphaMask)>, Box<dyn std::error::Error>> {
+        // Only the opacity of the materials is used, materials that are fully opaque don't get a mask
+        let reader = BufReader::new(File::open(path)?);
+        // Name, dissolve and dissolve texture of every material
+        let mut materials: Vec<(String, f64, Option<path::PathBuf>)> = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            let mut parts = line.split_whitespace();
+            match (parts.next(), materials.last_mut()) {
+                (Some("newmtl"), _) => {
+                    materials.push((parts.collect::<Vec<&str>>().join(" "), 1.0, None));
+                }
+                (Some("d"), Some(material)) => {
+                    material.1 = parts.next().ok_or("Missing value for d")?.parse()?;
+                }
+                (Some("Tr"), Some(material)) => {
+                    // Transparency, the inverse of dissolve
+                    material.1 =
+                        1.0 - parts.next().ok_or("Missing value for Tr")?.parse::<f64>()?;
+                }
+                (Some("map_d"), Some(material)) => {
+                    // Options like -clamp come before the file name
+                    let file = parts.last().ok_or("Missing file for map_d")?;
+                    material.2 = Some(path.parent().unwrap_or(path::Path::new("")).join(file));
+                }
+                _ => continue,
+            }
+        }
+        materials
+            .into_iter()
+            .filter(|(_, dissolve, texture)| *dissolve < 1.0 || texture.is_some())
+            .map(|(name, dissolve, texture)| {
+                let opacity: Box<dyn Texture> = match texture {
+                    Some(texture) => Box::new(ImageTexture::alpha_from_file(&texture)?),
+                    None => Box::new(ConstantTexture::gray(dissolve)),
+                };
+                Ok((
+                    name,
+                    AlphaMask {
+                        opacity,
+                        cutoff: ALPHA_CUTOFF,
+                    },
+                ))
+            })
+            .collect()
+    }
+
+    fn vertex_tangents(
+        vertices: &[Vec3],
+        tex_coords: &[(f64, f64)],
+        faces: &[[FaceVertex; 3]],
+    ) -> HashMap<(usize, usize), (Vec3, f64)> {
+        // Averages the tangents of the faces around each vertex, weighted by their angle at the vertex like MikkTSpace does
+        // Vertices are identified by position and texture coordinate, so seams in the texture get separate tangents
+        let mut sums: HashMap<(usize, usize), (Vec3, Vec3, Vec3)> = HashMap::new();
+        for face in faces {
+            let keys: Option<Vec<(usize, usize)>> = face
+                .iter()
+                .map(|v| v.tex_coord.map(|t| (v.vertex, t)))
+                .collect();
+            let keys = match keys {
+                Some(keys) => keys,
+                None => continue,
+            };
+            let p: Vec<Vec3> = keys.iter().map(|k| vertices[k.0 - 1]).collect();
+            let uv: Vec<(f64, f64)> = keys.iter().map(|k| tex_coords[k.1 - 1]).collect();
+            let e1 = p[1] - p[0];
+            let e2 = p[2] - p[0];
+            let normal = e1.cross(&e2);
+            let derivatives = uv_derivatives(
+                e1,
+                e2,
+                (uv[1].0 - uv[0].0, uv[1].1 - uv[0].1),
+                (uv[2].0 - uv[0].0, uv[2].1 - uv[0].1),
+            );
+            let (t, b) = match derivatives {
+                Some(d) if !normal.near_zero() => d,
+                _ => continue,
+            };
+            for (i, key) in keys.iter().enumerate() {
+                let a = (p[(i + 1) % 3] - p[i]).unit_vector();
+                let c = (p[(i + 2) % 3] - p[i]).unit_vector();
+                let angle = a.dot(&c).clamp(-1.0, 1.0).acos();
+                let sum = sums
+                    .entry(*key)
+                    .or_insert((Vec3::z(), Vec3::z(), Vec3::z()));
+                sum.0 += t.unit_vector() * angle;
+                sum.1 += b.unit_vector() * angle;
+                sum.2 += normal.unit_vector() * angle;
+            }
+        }
+        sums.into_iter()
+            .filter_map(|(key, (t, b, n))| {
+                // Make the tangent orthogonal to the vertex normal
+                let n = n.unit_vector();
+                let t = t - n * n.dot(&t);
+                if t.near_zero() {
+                    return None;
+                }
+                let handedness = if n.cross(&t).dot(&b) < 0.0 { -1.0 } else { 1.0 };
+                Some((key, (t.unit_vector(), handedness)))
+            })
+            .collect()
+    }
+}
+impl Mesh {
+    fn face_hit(&self, face: usize, ray: &Ray, t: f64, barycentric: Vec3) -> Hit {
+        self.triangle(&self.faces[face]).hit_with_geometry(
+            ray,
+            t,
+            barycentric,
+            &self.face_geometry[face],
+        )
+    }
+
+    fn triangle(&self, face: &[u32; 3]) -> Triangle {
+        // Copies the vertices of a face out of the shared buffers, which is only done for faces that are hit
+        let [a, b, c] = face.map(|i| i as usize);
+        let attribute = |values: &Vec<Vec3>| [values[a], values[b], values[c]];
+        Triangle {
+            p1: self.positions[a],
+            p2: self.positions[b],
+            p3: self.positions[c],
+            uvs: self.tex_coords.as_ref().map(|uvs| [uvs[a], uvs[b], uvs[c]]),
+            tangents: self
+                .tangents
+                .as_ref()
+                .and_then(|tangents| Some([tangents[a]?, tangents[b]?, tangents[c]?])),
+            normals: self.normals.as_ref().map(attribute),
+            colors: self.colors.as_ref().map(attribute),
+        }
+    }
 }
 impl Hittable for Mesh {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
         // Check if the bounding box hits, only then continue with the more expensive check
         if self.bounds.intersects(ray, t_min, t_max) {
-            hit_list(
-                &self.faces.iter().collect(), // Convert to Vec of references
-                &Ray {
-                    // Instead of moving the mesh, we just move the ray in the opposite direction
-                    origin: ray.origin - self.offset,
-                    direction: ray.direction,
-                },
-                t_min,
-                t_max,
-            )
-            .map(|(_, h)| h)
+            // Instead of moving the mesh, we just move the ray in the opposite direction
+            let local_ray = Ray {
+                origin: ray.origin - self.offset,
+                direction: ray.direction,
+            };
+            // Only the closest face gets a hit with all of its attributes, and faces that have to check their alpha mask
+            let mut closest: Option<(usize, f64, Vec3)> = None;
+            let watertight_ray = WatertightRay::new(&local_ray);
+            for (i, (face, alpha_mask)) in self.faces.iter().zip(&self.face_alpha_masks).enumerate()
+            {
+                let t_max = closest.map_or(t_max, |(_, t, _)| t);
+                let points = face.map(|i| self.positions[i as usize]);
+                let (t, barycentric) =
+                    match Triangle::intersect(points, &watertight_ray, t_min, t_max) {
+                        Some(intersection) => intersection,
+                        None => continue,
+                    };
+                // Cut out parts of faces are skipped, also for shadow rays
+                if alpha_mask.is_none_or(|mask| {
+                    let hit = self.face_hit(i, &local_ray, t, barycentric);
+                    self.alpha_masks[mask].is_opaque(&hit)
+                }) {
+                    closest = Some((i, t, barycentric));
+                }
+            }
+            // Move the hit back to where the mesh actually is
+            closest.map(|(i, t, barycentric)| {
+                let hit = self.face_hit(i, &local_ray, t, barycentric);
+                Hit {
+                    p: hit.p + self.offset,
+                    ..hit
+                }
+            })
         } else {
             None
         }
@@ -114,3 +912,441 @@ impl Hittable for Mesh {
         self.bounds.clone()
     }
 }
+
+struct PlyProperty {
+    name: String,
+    data_type: String,
+    count_type: Option<String>, // Type of the length of list properties
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+enum PlyReader<'a> {
+    Ascii(std::vec::IntoIter<String>),
+    Binary(&'a [u8], bool), // Remaining data and whether it's big endian
+}
+impl PlyReader<'_> {
+    fn read_value(&mut self, data_type: &str) -> Result<f64, Box<dyn std::error::Error>> {
+        match self {
+            PlyReader::Ascii(tokens) => {
+                Ok(tokens.next().ok_or("Unexpected end of PLY file")?.parse()?)
+            }
+            PlyReader::Binary(data, big_endian) => {
+                let size = match data_type {
+                    "char" | "int8" | "uchar" | "uint8" => 1,
+                    "short" | "int16" | "ushort" | "uint16" => 2,
+                    "int" | "int32" | "uint" | "uint32" | "float" | "float32" => 4,
+                    "double" | "float64" => 8,
+                    _ => return Err(format!("Unknown PLY type {}", data_type).into()),
+                };
+                if data.len() < size {
+                    return Err("Unexpected end of PLY file".into());
+                }
+                let mut bytes = [0; 8];
+                bytes[..size].copy_from_slice(&data[..size]);
+                if *big_endian {
+                    bytes[..size].reverse();
+                }
+                *data = &data[size..];
+                Ok(match data_type {
+                    "char" | "int8" => bytes[0] as i8 as f64,
+                    "uchar" | "uint8" => bytes[0] as f64,
+                    "short" | "int16" => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    "ushort" | "uint16" => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+                    "int" | "int32" => i32::from_le_bytes(bytes[..4].try_into()?) as f64,
+                    "uint" | "uint32" => u32::from_le_bytes(bytes[..4].try_into()?) as f64,
+                    "float" | "float32" => f32::from_le_bytes(bytes[..4].try_into()?) as f64,
+                    _ => f64::from_le_bytes(bytes),
+                })
+            }
+        }
+    }
+
+    fn read_property(
+        &mut self,
+        property: &PlyProperty,
+    ) -> Result<Vec<f64>, Box<dyn std::error::Error>> {
+        // Single values are returned as a list with one element
+        match &property.count_type {
+            Some(count_type) => {
+                let count = self.read_value(count_type)? as usize;
+                (0..count)
+                    .map(|_| self.read_value(&property.data_type))
+                    .collect()
+            }
+            None => Ok(vec![self.read_value(&property.data_type)?]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube() -> MeshData {
+        // Corners at ±1, the bits of the index are the signs of x, y and z, the faces point outwards
+        let vertices = (0..8)
+            .map(|i| {
+                let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Vec3::new(sign(1), sign(2), sign(4))
+            })
+            .collect();
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        MeshData {
+            vertices,
+            tex_coords: Vec::new(),
+            normals: None,
+            colors: None,
+            polygons: faces
+                .iter()
+                .map(|face| {
+                    face.iter()
+                        .map(|v| FaceVertex {
+                            vertex: v + 1,
+                            tex_coord: None,
+                            normal: None,
+                        })
+                        .collect()
+                })
+                .collect(),
+            polygon_alpha_masks: vec![None; faces.len()],
+            alpha_masks: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn displacement_normals() {
+        // Corners move along the average of the faces around them, then get their normals from the moved faces
+        let displacement = || Box::new(ConstantTexture::gray(1.0));
+        let options = MeshOptions::default().with_displacement(displacement(), 0.5);
+        let mesh = Mesh::from_data(cube(), Vec3::z(), options).unwrap();
+        let normals = mesh.normals.as_ref().unwrap();
+        assert_eq!(mesh.positions.len(), 8);
+        let diagonal = Vec3::new(1.0, 1.0, 1.0).unit_vector();
+        for (p, n) in mesh.positions.iter().zip(normals) {
+            let expected = p.unit_vector();
+            assert!((p.length() - 3f64.sqrt() - 0.5).abs() < 1e-9);
+            assert!((*n - expected).length() < 1e-9);
+        }
+        assert!((mesh.bounds.higher() - diagonal * (3f64.sqrt() + 0.5)).length() < 1e-9);
+        // Creases keep the faces flat, every corner gets the normal of its face
+        let options = MeshOptions::default()
+            .with_subdivision(0, 45.0)
+            .with_displacement(displacement(), 0.5);
+        let mesh = Mesh::from_data(cube(), Vec3::z(), options).unwrap();
+        let normals = mesh.normals.as_ref().unwrap();
+        assert_eq!(mesh.positions.len(), 24);
+        for face in &mesh.faces {
+            let [a, b, c] = face.map(|i| mesh.positions[i as usize]);
+            let face_normal = (b - a).cross(&(c - a)).unit_vector();
+            for i in face {
+                assert!((normals[*i as usize] - face_normal).length() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn closest_hits() {
+        let ray = Ray {
+            origin: Vec3::new(1.2, 0.3, 5.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        let mesh =
+            Mesh::from_data(cube(), Vec3::new(1.0, 0.0, 0.0), MeshOptions::default()).unwrap();
+        let hit = mesh.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.0).abs() < 1e-9);
+        assert!((hit.p - Vec3::new(1.2, 0.3, 1.0)).length() < 1e-9);
+        assert!((hit.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!(mesh.hit(&ray, 0.0, 3.9).is_none());
+        // A cut out front face lets the ray through to the back
+        let mut data = cube();
+        data.polygon_alpha_masks[5] = Some(0);
+        data.alpha_masks.push(AlphaMask {
+            opacity: Box::new(ConstantTexture::gray(0.0)),
+            cutoff: ALPHA_CUTOFF,
+        });
+        let mesh = Mesh::from_data(data, Vec3::new(1.0, 0.0, 0.0), MeshOptions::default()).unwrap();
+        let hit = mesh.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 6.0).abs() < 1e-9);
+        assert!((hit.p - Vec3::new(1.2, 0.3, -1.0)).length() < 1e-9);
+    }
+
+    fn write(name: &str, contents: &[u8]) -> path::PathBuf {
+        // Every test uses its own file names, since the tests run in parallel
+        let path = std::env::temp_dir().join(format!("ray_tracer_{}", name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn error(result: Result<Mesh, Box<dyn std::error::Error>>) -> String {
+        result
+            .err()
+            .expect("the mesh should be rejected")
+            .to_string()
+    }
+
+    fn ply(format: &str, body: &[u8]) -> Vec<u8> {
+        // Square with a triangle on top, the vertices have normals, colors and texture coordinates
+        let header = format!(
+            "ply\r\nformat {} 1.0\r\ncomment test\r\nelement vertex 5\r\n\
+             property float x\r\nproperty float y\r\nproperty float z\r\n\
+             property float nx\r\nproperty float ny\r\nproperty float nz\r\n\
+             property uchar red\r\nproperty uchar green\r\nproperty uchar blue\r\n\
+             property float u\r\nproperty float v\r\n\
+             element face 2\r\nproperty list uchar int vertex_indices\r\nend_header\r\n",
+            format
+        );
+        [header.as_bytes(), body].concat()
+    }
+
+    const PLY_VERTICES: [[f64; 11]; 5] = [
+        [0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 255.0, 0.0, 0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 255.0, 0.0, 1.0, 0.0],
+        [1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 255.0, 1.0, 1.0],
+        [0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 51.0, 51.0, 51.0, 0.0, 1.0],
+        [0.5, 2.0, 0.0, 0.0, 0.0, 1.0, 255.0, 255.0, 255.0, 0.5, 2.0],
+    ];
+    const PLY_FACES: [&[i32]; 2] = [&[0, 1, 2, 3], &[3, 2, 4]];
+
+    fn ply_binary(big_endian: bool) -> Vec<u8> {
+        let mut body = Vec::new();
+        for vertex in PLY_VERTICES {
+            for (i, value) in vertex.iter().enumerate() {
+                if (6..9).contains(&i) {
+                    body.push(*value as u8);
+                } else if big_endian {
+                    body.extend((*value as f32).to_be_bytes());
+                } else {
+                    body.extend((*value as f32).to_le_bytes());
+                }
+            }
+        }
+        for face in PLY_FACES {
+            body.push(face.len() as u8);
+            for i in face {
+                if big_endian {
+                    body.extend(i.to_be_bytes());
+                } else {
+                    body.extend(i.to_le_bytes());
+                }
+            }
+        }
+        let format = if big_endian {
+            "binary_big_endian"
+        } else {
+            "binary_little_endian"
+        };
+        ply(format, &body)
+    }
+
+    fn ply_ascii(faces: &[&[i32]]) -> Vec<u8> {
+        let mut body = String::new();
+        for vertex in PLY_VERTICES {
+            let values: Vec<String> = vertex.iter().map(|v| v.to_string()).collect();
+            body += &format!("{}\n", values.join(" "));
+        }
+        for face in faces {
+            let indices: Vec<String> = face.iter().map(|i| i.to_string()).collect();
+            body += &format!("{} {}\n", face.len(), indices.join(" "));
+        }
+        ply("ascii", body.as_bytes())
+    }
+
+    #[test]
+    fn ply_formats() {
+        let files = [
+            ("ascii.ply", ply_ascii(&PLY_FACES)),
+            ("little_endian.ply", ply_binary(false)),
+            ("big_endian.ply", ply_binary(true)),
+        ];
+        for (name, contents) in files {
+            let data = Mesh::read_ply(&write(name, &contents)).unwrap();
+            assert_eq!(data.vertices.len(), 5);
+            assert_eq!((data.vertices[4].x, data.vertices[4].y), (0.5, 2.0));
+            assert_eq!(data.normals.as_ref().unwrap()[2].z, 1.0);
+            // Integer colors are scaled to 1 and stored with a gamma of 2
+            let gray = data.colors.as_ref().unwrap()[3];
+            assert!((gray.y - 0.04).abs() < 1e-9);
+            assert_eq!(data.tex_coords[2], (1.0, 1.0));
+            let polygons: Vec<Vec<usize>> = data
+                .polygons
+                .iter()
+                .map(|polygon| polygon.iter().map(|v| v.vertex).collect())
+                .collect();
+            assert_eq!(polygons, [vec![1, 2, 3, 4], vec![4, 3, 5]]);
+            assert!(data.polygons[1]
+                .iter()
+                .all(|v| v.tex_coord == Some(v.vertex)));
+            // The square is split into two triangles
+            let mesh = Mesh::from_file(&write(name, &contents), Vec3::z()).unwrap();
+            assert_eq!(mesh.faces.len(), 3);
+            assert_eq!(mesh.positions.len(), 5);
+        }
+    }
+
+    #[test]
+    fn malformed_ply() {
+        let read =
+            |name: &str, contents: &[u8]| error(Mesh::from_file(&write(name, contents), Vec3::z()));
+        let ascii = ply_ascii(&PLY_FACES);
+        let text = String::from_utf8(ascii.clone()).unwrap();
+        assert!(
+            read("no_end.ply", text.replace("end_header", "end").as_bytes())
+                .contains("end of PLY header")
+        );
+        assert!(
+            read("format.ply", text.replace("ascii", "utf8").as_bytes()).contains("Unsupported")
+        );
+        let binary = ply_binary(false);
+        assert!(read("truncated.ply", &binary[..binary.len() - 1]).contains("Unexpected end"));
+        assert!(read("truncated_ascii.ply", &ascii[..ascii.len() - 4]).contains("Unexpected end"));
+        // Indices that point outside of the vertices, or faces that are too small
+        assert!(read("range.ply", &ply_ascii(&[&[0, 1, 2], &[3, 2, 5]]))
+            .contains("index 6 out of range"));
+        assert!(
+            read("negative.ply", &ply_ascii(&[&[0, 1, 2], &[3, 2, -1]])).contains("out of range")
+        );
+        assert!(read("line.ply", &ply_ascii(&[&[0, 1, 2], &[3, 2]])).contains("Face with 2"));
+        // Positions given as empty lists
+        let empty = "ply\nformat ascii 1.0\nelement vertex 3\nproperty list uchar float x\n\
+                     property float y\nproperty float z\nelement face 1\n\
+                     property list uchar int vertex_indices\nend_header\n\
+                     0 0 0\n0 1 0\n0 0 1\n3 0 1 2\n";
+        assert!(read("empty_list.ply", empty.as_bytes()).contains("without position"));
+    }
+
+    fn stl_ascii(vertices: &[[f64; 3]]) -> Vec<u8> {
+        let mut text = String::from("solid test\n");
+        for facet in vertices.chunks(3) {
+            text += "facet normal 0 0 0\n  outer loop\n";
+            for v in facet {
+                text += &format!("    vertex {} {} {}\n", v[0], v[1], v[2]);
+            }
+            text += "  endloop\nendfacet\n";
+        }
+        text += "endsolid test\n";
+        text.into_bytes()
+    }
+
+    // Corners of the facets of a tetrahedron, each one has its own copy of its corners
+    const TETRAHEDRON: [[f64; 3]; 12] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 0.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0],
+        [0.0, 1.0, 0.0],
+        [1.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0],
+        [0.0, 0.0, 1.0],
+    ];
+
+    #[test]
+    fn stl_formats() {
+        // Binary files can start with "solid" as well
+        let mut binary = b"solid binary".to_vec();
+        binary.resize(80, 0);
+        binary.extend(4u32.to_le_bytes());
+        for facet in TETRAHEDRON.chunks(3) {
+            binary.extend([0u8; 12]);
+            for v in facet.iter().flatten() {
+                binary.extend((*v as f32).to_le_bytes());
+            }
+            binary.extend([0u8; 2]);
+        }
+        let files = [
+            ("ascii.stl", stl_ascii(&TETRAHEDRON)),
+            ("binary.stl", binary),
+        ];
+        for (name, contents) in files {
+            let data = Mesh::read_stl(&write(name, &contents)).unwrap();
+            // The copies of the corners are merged
+            assert_eq!(data.vertices.len(), 4);
+            assert_eq!(data.polygons.len(), 4);
+            let last: Vec<usize> = data.polygons[3].iter().map(|v| v.vertex).collect();
+            assert_eq!(last, [3, 2, 4]);
+            let mesh = Mesh::from_file(&write(name, &contents), Vec3::z()).unwrap();
+            assert_eq!(mesh.faces.len(), 4);
+        }
+    }
+
+    #[test]
+    fn malformed_stl() {
+        let read =
+            |name: &str, contents: &[u8]| error(Mesh::from_file(&write(name, contents), Vec3::z()));
+        assert!(
+            read("short_vertex.stl", b"solid a\nvertex 0 1\nendsolid a\n")
+                .contains("Invalid STL vertex")
+        );
+        assert!(
+            read("number.stl", b"solid a\nvertex 0 1 x\nendsolid a\n").contains("invalid float")
+        );
+        assert!(read("facet.stl", &stl_ascii(&TETRAHEDRON[..4])).contains("three vertices"));
+        // A binary file that's cut off doesn't have the size from its header, and isn't text either
+        let mut binary = vec![0u8; 80];
+        binary.extend(4u32.to_le_bytes());
+        binary.extend([0u8; 150]);
+        assert!(read("truncated.stl", &binary).contains("without faces"));
+    }
+
+    #[test]
+    fn obj_indices() {
+        let read = |name: &str, contents: &str| {
+            error(Mesh::from_file(
+                &write(name, contents.as_bytes()),
+                Vec3::z(),
+            ))
+        };
+        let vertices = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\n";
+        assert!(read("zero.obj", &format!("{}f 0 1 2\n", vertices)).contains("Vertex index 0"));
+        assert!(read("range.obj", &format!("{}f 1 2 4\n", vertices)).contains("Vertex index 4"));
+        assert!(
+            read("tex_coord.obj", &format!("{}f 1/1 2/1 3/2\n", vertices))
+                .contains("Texture coordinate index 2")
+        );
+        assert!(read("vertex.obj", "v 0 0\n").contains("three coordinates"));
+        assert!(read("empty.obj", vertices).contains("without faces"));
+    }
+
+    #[test]
+    fn offset_hits() {
+        let path = write("offset.obj", b"v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n");
+        let mesh = Mesh::from_file(&path, Vec3::new(0.0, 0.0, -5.0)).unwrap();
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 1.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        // The hit point is in world space, not relative to the mesh
+        let hit = mesh.hit(&ray, 0.0, 100.0).unwrap();
+        assert!((hit.p.z + 5.0).abs() < 1e-9);
+        assert!((hit.t - 6.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn missing_material_library() {
+        // The mesh is loaded without the materials and the library is reported back
+        let path = write(
+            "missing_mtllib.obj",
+            b"mtllib ray_tracer_missing.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl leaves\nf 1 2 3\n",
+        );
+        let mesh = Mesh::from_file(&path, Vec3::z()).unwrap();
+        assert_eq!(mesh.faces.len(), 1);
+        assert_eq!(mesh.warnings.len(), 1);
+        assert!(mesh.warnings[0].contains("ray_tracer_missing.mtl"));
+    }
+}
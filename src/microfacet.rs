@@ -0,0 +1,119 @@
+// Helpers for microfacet materials using the GGX / Trowbridge-Reitz distribution
+// All directions are in the local frame of the surface where the normal is the z-axis
+use crate::util::PI;
+use crate::vec3::Vec3;
+use rand::Rng;
+
+// Orthonormal basis around a normal, used to go to and from the local frame
+pub struct Frame {
+    pub t: Vec3,
+    pub b: Vec3,
+    pub n: Vec3,
+}
+impl Frame {
+    pub fn new(n: Vec3) -> Frame {
+        // Branchless basis construction by Duff et al.
+        let sign = 1.0_f64.copysign(n.z);
+        let a = -1.0 / (sign + n.z);
+        let b = n.x * n.y * a;
+        Frame {
+            t: Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+            b: Vec3::new(b, sign + n.y * n.y * a, -n.y),
+            n,
+        }
+    }
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(&self.t), v.dot(&self.b), v.dot(&self.n))
+    }
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        self.t * v.x + self.b * v.y + self.n * v.z
+    }
+}
+
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    // Squaring makes the roughness perceptually linear, very small values cause numerical issues
+    (roughness * roughness).max(0.001)
+}
+
+pub fn ggx_d(m: Vec3, alpha: f64) -> f64 {
+    // Normal distribution function, i.e. the density of microfacets with normal m
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let d = m.z * m.z * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+pub fn smith_g1(v: Vec3, alpha: f64) -> f64 {
+    // Fraction of the microfacets visible from direction v
+    let cos2 = v.z * v.z;
+    if cos2 == 0.0 {
+        return 0.0;
+    }
+    let tan2 = (1.0 - cos2) / cos2;
+    2.0 / (1.0 + (1.0 + alpha * alpha * tan2).sqrt())
+}
+
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    smith_g1(wo, alpha) * smith_g1(wi, alpha)
+}
+
+pub fn sample_ggx_vndf(wo: Vec3, alpha: f64) -> Vec3 {
+    // Samples a microfacet normal proportional to its visible area from wo
+    // "Sampling the GGX Distribution of Visible Normals", Heitz 2018
+    let vh = Vec3::new(alpha * wo.x, alpha * wo.y, wo.z).unit_vector();
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(&t1);
+    let r = rand::thread_rng().gen::<f64>().sqrt();
+    let phi = 2.0 * PI * rand::thread_rng().gen::<f64>();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+    Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1e-6)).unit_vector()
+}
+
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    // Exact Fresnel reflectance for unpolarized light, eta is the ratio of the indices of refraction n_t / n_i
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+pub fn fresnel_conductor(cos_i: f64, eta: Vec3, k: Vec3) -> Vec3 {
+    // Fresnel reflectance of a conductor with the complex index of refraction eta + ik, per color channel
+    Vec3::new(
+        fresnel_conductor_channel(cos_i, eta.x, k.x),
+        fresnel_conductor_channel(cos_i, eta.y, k.y),
+        fresnel_conductor_channel(cos_i, eta.z, k.z),
+    )
+}
+
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    (rs + rp) / 2.0
+}
@@ -0,0 +1,61 @@
+use crate::hittable::Hit;
+use crate::texture::Texture;
+
+// Changes the shading normal of a hit to add detail without adding geometry
+#[allow(dead_code)]
+pub enum NormalMap {
+    // Normals in tangent space encoded as colors, the texture should be read with a gamma of 1.0
+    TangentSpace {
+        texture: Box<dyn Texture>,
+        strength: f64,
+    },
+    // Heights in [0, 1], the normal gets tilted along the slope of the height
+    Bump {
+        height: Box<dyn Texture>,
+        strength: f64,
+    },
+}
+
+// Step in texture coordinates used for the finite differences of the height
+const BUMP_DELTA: f64 = 0.001;
+
+impl NormalMap {
+    pub fn apply(&self, hit: Hit) -> Hit {
+        let (tangent, handedness) = match hit.tangent {
+            Some(t) => t,
+            None => return hit,
+        };
+        // Build an orthonormal tangent frame around the outward normal
+        let n = hit.outward_normal();
+        let t = (tangent - n * n.dot(&tangent)).unit_vector();
+        let b = n.cross(&t) * handedness;
+        let outward_normal = match self {
+            NormalMap::TangentSpace { texture, strength } => {
+                let c = texture.value(&hit);
+                let (x, y, z) = (2.0 * c.x - 1.0, 2.0 * c.y - 1.0, 2.0 * c.z - 1.0);
+                t * (x * strength) + b * (y * strength) + n * z
+            }
+            NormalMap::Bump { height, strength } => {
+                let (u, v) = hit.uv;
+                let h = height.scalar(&hit);
+                let dh_du =
+                    (height.scalar(&hit.clone().with_uv(u + BUMP_DELTA, v)) - h) / BUMP_DELTA;
+                let dh_dv =
+                    (height.scalar(&hit.clone().with_uv(u, v + BUMP_DELTA)) - h) / BUMP_DELTA;
+                n - (t * dh_du + b * dh_dv) * *strength
+            }
+        };
+        if outward_normal.near_zero() {
+            return hit;
+        }
+        let outward_normal = outward_normal.unit_vector();
+        Hit {
+            normal: if hit.front_face {
+                outward_normal
+            } else {
+                -outward_normal
+            },
+            ..hit
+        }
+    }
+}
@@ -1,20 +1,60 @@
+use crate::alpha_mask::AlphaMask;
 use crate::hittable::BoundingBox;
 use crate::hittable::Hit;
 use crate::hittable::Hittable;
 use crate::material::Material;
+use crate::medium::Medium;
+use crate::normal_map::NormalMap;
 use crate::ray::Ray;
+use crate::util::EPSILON;
 pub struct Object {
     pub material: Box<dyn Material>,
     pub shape: Box<dyn Hittable>,
+    pub normal_map: Option<NormalMap>,
+    pub alpha_mask: Option<AlphaMask>,
+    pub interior: Option<Box<dyn Medium>>, // Medium inside of the object, the shape has to be closed
 }
 impl Object {
     pub fn new(material: Box<dyn Material>, shape: Box<dyn Hittable>) -> Object {
-        Object { material, shape }
+        Object {
+            material,
+            shape,
+            normal_map: None,
+            alpha_mask: None,
+            interior: None,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn with_interior(mut self, interior: Box<dyn Medium>) -> Object {
+        self.interior = Some(interior);
+        self
+    }
+    pub fn with_alpha_mask(mut self, alpha_mask: AlphaMask) -> Object {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+    pub fn with_normal_map(mut self, normal_map: NormalMap) -> Object {
+        self.normal_map = Some(normal_map);
+        self
+    }
+    // Hit as seen by the material, only needed for the closest hit so it's not done in hit
+    pub fn shading_hit(&self, hit: Hit) -> Hit {
+        match &self.normal_map {
+            Some(normal_map) => normal_map.apply(hit),
+            None => hit,
+        }
     }
 }
 impl Hittable for Object {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
-        self.shape.hit(ray, t_min, t_max)
+        let mut hit = self.shape.hit(ray, t_min, t_max)?;
+        if let Some(alpha_mask) = &self.alpha_mask {
+            // Keep looking behind the hits that are cut out
+            while !alpha_mask.is_opaque(&hit) {
+                hit = self.shape.hit(ray, hit.t + EPSILON, t_max)?;
+            }
+        }
+        Some(hit)
     }
 
     fn get_bounds(&self) -> BoundingBox {
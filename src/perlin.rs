@@ -0,0 +1,100 @@
+use crate::vec3::Vec3;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise as described in "Ray Tracing: The Next Week"
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        // Seeded so the same texture looks the same in every render
+        let mut rng = StdRng::seed_from_u64(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| {
+                Vec3::new(
+                    rng.gen::<f64>() * 2.0 - 1.0,
+                    rng.gen::<f64>() * 2.0 - 1.0,
+                    rng.gen::<f64>() * 2.0 - 1.0,
+                )
+                .unit_vector()
+            })
+            .collect();
+        let mut permutation = || {
+            let mut perm: Vec<usize> = (0..POINT_COUNT).collect();
+            perm.shuffle(&mut rng);
+            perm
+        };
+        let perm_x = permutation();
+        let perm_y = permutation();
+        let perm_z = permutation();
+        Perlin {
+            gradients,
+            perm_x,
+            perm_y,
+            perm_z,
+        }
+    }
+
+    pub fn noise(&self, p: &Vec3) -> f64 {
+        // Smooth noise in [-1, 1]
+        let (i, j, k) = (p.x.floor(), p.y.floor(), p.z.floor());
+        let (u, v, w) = (p.x - i, p.y - j, p.z - k);
+        // Hermite smoothing to get rid of the grid artifacts
+        let (uu, vv, ww) = (
+            u * u * (3.0 - 2.0 * u),
+            v * v * (3.0 - 2.0 * v),
+            w * w * (3.0 - 2.0 * w),
+        );
+        let mut acc = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.perm_x
+                        [(i as i64 + di).rem_euclid(POINT_COUNT as i64) as usize]
+                        ^ self.perm_y[(j as i64 + dj).rem_euclid(POINT_COUNT as i64) as usize]
+                        ^ self.perm_z[(k as i64 + dk).rem_euclid(POINT_COUNT as i64) as usize]];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    acc += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(&weight);
+                }
+            }
+        }
+        acc
+    }
+
+    pub fn fbm(&self, p: &Vec3, octaves: i32) -> f64 {
+        // Fractal brownian motion, sum of noise at increasing frequencies and decreasing amplitudes
+        let mut acc = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            acc += weight * self.noise(&p);
+            weight *= 0.5;
+            p = p * 2.0;
+        }
+        acc
+    }
+
+    pub fn turbulence(&self, p: &Vec3, octaves: i32) -> f64 {
+        // Like fbm but with absolute values, which gives sharp creases
+        let mut acc = 0.0;
+        let mut p = *p;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            acc += weight * self.noise(&p).abs();
+            weight *= 0.5;
+            p = p * 2.0;
+        }
+        acc
+    }
+}
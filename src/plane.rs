@@ -0,0 +1,51 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Infinite plane through point with the given normal
+pub struct Plane {
+    pub point: Vec3,
+    pub normal: Vec3,
+}
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let normal = self.normal.unit_vector();
+        let denom = normal.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            // Ray is parallel to the plane
+            return None;
+        }
+        let t = (self.point - ray.origin).dot(&normal) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.at(t);
+        // The texture repeats every unit along the plane
+        let frame = Frame::new(normal);
+        let local = p - self.point;
+        Some(
+            Hit::new(p, normal, t, ray)
+                .with_uv(local.dot(&frame.t), local.dot(&frame.b))
+                .with_tangent(frame.t, 1.0),
+        )
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        // Infinite along every axis, unless the plane is orthogonal to it
+        let normal = self.normal.unit_vector();
+        let extent = |n: f64, p: f64| {
+            if n.abs() == 1.0 {
+                (p, p)
+            } else {
+                (f64::NEG_INFINITY, f64::INFINITY)
+            }
+        };
+        let (x_low, x_high) = extent(normal.x, self.point.x);
+        let (y_low, y_high) = extent(normal.y, self.point.y);
+        let (z_low, z_high) = extent(normal.z, self.point.z);
+        BoundingBox::new(x_low, x_high, y_low, y_high, z_low, z_high)
+    }
+}
@@ -1,24 +1,367 @@
-use crate::triangle::Triangle;
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::microfacet::Frame;
+use crate::ray::Ray;
+use crate::triangle::{Triangle, WatertightRay};
 use crate::vec3::Vec3;
 
+// Planar polygon that can be concave and have holes, it's split into triangles by ear clipping
+// The normal follows the winding of the outline like for triangles, holes can be wound either way
+#[allow(dead_code)]
 pub struct Polygon {
     pub vertices: Vec<Vec3>,
+    pub holes: Vec<Vec<Vec3>>,
     triangles: Vec<Triangle>,
+    bounds: BoundingBox,
 }
 impl Polygon {
-    fn new(vertices: Vec<Vec3>) -> Polygon {
-        let mut triangles: Vec<Triangle> = Vec::new();
-        // Turn n-polygon into n-2 triangles. Trivial, but only works for convex shapes
-        for i in 1..vertices.len() - 2 {
-            triangles.push(Triangle {
-                p1: vertices[i],
-                p2: vertices[i + 1],
-                p3: vertices[i + 2],
+    #[allow(dead_code)]
+    pub fn new(vertices: Vec<Vec3>) -> Polygon {
+        Polygon::with_holes(vertices, Vec::new())
+    }
+
+    pub fn with_holes(vertices: Vec<Vec3>, holes: Vec<Vec<Vec3>>) -> Polygon {
+        let points: Vec<Vec3> = vertices
+            .iter()
+            .chain(holes.iter().flatten())
+            .copied()
+            .collect();
+        let triangles = Polygon::triangulate(&vertices, &holes)
+            .iter()
+            .map(|[a, b, c]| Triangle {
+                p1: points[*a],
+                p2: points[*b],
+                p3: points[*c],
+                uvs: None,
+                tangents: None,
+                normals: None,
+                colors: None,
             })
-        }
+            .collect();
+        let bounds = BoundingBox::new(
+            vertices.iter().fold(f64::INFINITY, |a, p| a.min(p.x)),
+            vertices.iter().fold(f64::NEG_INFINITY, |a, p| a.max(p.x)),
+            vertices.iter().fold(f64::INFINITY, |a, p| a.min(p.y)),
+            vertices.iter().fold(f64::NEG_INFINITY, |a, p| a.max(p.y)),
+            vertices.iter().fold(f64::INFINITY, |a, p| a.min(p.z)),
+            vertices.iter().fold(f64::NEG_INFINITY, |a, p| a.max(p.z)),
+        );
         Polygon {
             vertices,
+            holes,
             triangles,
+            bounds,
+        }
+    }
+
+    pub fn triangulate(outline: &[Vec3], holes: &[Vec<Vec3>]) -> Vec<[usize; 3]> {
+        // Gives the triangles as indices into the outline followed by the vertices of the holes
+        // "Triangulation by Ear Clipping", Eberly 2002
+        if outline.len() < 3 {
+            return Vec::new();
+        }
+        // Work in 2-D in the plane of the polygon, the normal from Newell's method is robust for concave shapes
+        let normal = (0..outline.len()).fold(Vec3::z(), |acc, i| {
+            let (a, b) = (outline[i], outline[(i + 1) % outline.len()]);
+            acc + Vec3::new(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            )
+        });
+        if normal.near_zero() {
+            return Vec::new();
+        }
+        let frame = Frame::new(normal.unit_vector());
+        let points: Vec<(f64, f64)> = outline
+            .iter()
+            .chain(holes.iter().flatten())
+            .map(|p| {
+                let p = frame.to_local(*p);
+                (p.x, p.y)
+            })
+            .collect();
+
+        // The outline is counterclockwise in the plane, holes have to go clockwise to be joined into it
+        let mut polygon: Vec<usize> = (0..outline.len()).collect();
+        let mut start = outline.len();
+        let mut hole_loops: Vec<Vec<usize>> = holes
+            .iter()
+            .filter(|hole| hole.len() >= 3)
+            .map(|hole| {
+                let mut indices: Vec<usize> = (start..start + hole.len()).collect();
+                start += hole.len();
+                if signed_area(&points, &indices) > 0.0 {
+                    indices.reverse();
+                }
+                indices
+            })
+            .collect();
+        // Join the holes from right to left, so that the bridges don't cross holes that haven't been joined yet
+        let max_x = |indices: &Vec<usize>| {
+            indices
+                .iter()
+                .fold(f64::NEG_INFINITY, |a, i| a.max(points[*i].0))
+        };
+        hole_loops.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap());
+        for (k, hole) in hole_loops.iter().enumerate() {
+            polygon = join_hole(&points, polygon, hole, &hole_loops[k + 1..]);
+        }
+
+        // Clip ears until only one triangle is left
+        let mut triangles = Vec::new();
+        while polygon.len() > 3 {
+            let n = polygon.len();
+            let ear = (0..n)
+                .find(|i| is_ear(&points, &polygon, *i))
+                // Only happens for degenerate polygons, clip the vertex anyway so that it terminates
+                .unwrap_or(0);
+            triangles.push([
+                polygon[(ear + n - 1) % n],
+                polygon[ear],
+                polygon[(ear + 1) % n],
+            ]);
+            polygon.remove(ear);
         }
+        triangles.push([polygon[0], polygon[1], polygon[2]]);
+        triangles
+    }
+}
+
+fn signed_area(points: &[(f64, f64)], indices: &[usize]) -> f64 {
+    (0..indices.len()).fold(0.0, |acc, i| {
+        let a = points[indices[i]];
+        let b = points[indices[(i + 1) % indices.len()]];
+        acc + a.0 * b.1 - b.0 * a.1
+    }) / 2.0
+}
+
+fn cross(o: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn segments_cross(a: (f64, f64), b: (f64, f64), c: (f64, f64), d: (f64, f64)) -> bool {
+    // Proper crossing only, touching at the ends doesn't count
+    let d1 = cross(a, b, c);
+    let d2 = cross(a, b, d);
+    let d3 = cross(c, d, a);
+    let d4 = cross(c, d, b);
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+fn join_hole(
+    points: &[(f64, f64)],
+    polygon: Vec<usize>,
+    hole: &[usize],
+    other_holes: &[Vec<usize>],
+) -> Vec<usize> {
+    // Connects the rightmost vertex of the hole to the closest vertex of the polygon it can see
+    // The polygon then goes into the hole, around it and back out along the same bridge
+    let m = (0..hole.len())
+        .max_by(|a, b| points[hole[*a]].0.partial_cmp(&points[hole[*b]].0).unwrap())
+        .unwrap();
+    let pm = points[hole[m]];
+    let edges = |indices: &[usize]| -> Vec<(usize, usize)> {
+        (0..indices.len())
+            .map(|i| (indices[i], indices[(i + 1) % indices.len()]))
+            .collect()
+    };
+    let mut all_edges = edges(&polygon);
+    all_edges.extend(edges(hole));
+    for other in other_holes {
+        all_edges.extend(edges(other));
+    }
+    let mut candidates: Vec<usize> = (0..polygon.len()).collect();
+    let distance = |i: &usize| {
+        let p = points[polygon[*i]];
+        (p.0 - pm.0).powi(2) + (p.1 - pm.1).powi(2)
+    };
+    candidates.sort_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap());
+    let bridge = candidates
+        .iter()
+        .copied()
+        .find(|i| {
+            let p = points[polygon[*i]];
+            all_edges
+                .iter()
+                .all(|(a, b)| !segments_cross(pm, p, points[*a], points[*b]))
+        })
+        .unwrap_or(candidates[0]);
+
+    let mut joined = polygon[..=bridge].to_vec();
+    joined.extend((0..=hole.len()).map(|k| hole[(m + k) % hole.len()]));
+    joined.extend_from_slice(&polygon[bridge..]);
+    joined
+}
+
+fn is_ear(points: &[(f64, f64)], polygon: &[usize], i: usize) -> bool {
+    // The corner has to be convex and no other vertex may lie in the triangle
+    let n = polygon.len();
+    let (a, b, c) = (polygon[(i + n - 1) % n], polygon[i], polygon[(i + 1) % n]);
+    let (pa, pb, pc) = (points[a], points[b], points[c]);
+    if cross(pa, pb, pc) <= 0.0 {
+        return false;
+    }
+    polygon.iter().all(|j| {
+        // The vertices at the ends of a bridge appear twice
+        let p = points[*j];
+        if *j == a || *j == b || *j == c || p == pa || p == pb || p == pc {
+            return true;
+        }
+        cross(pa, pb, p) < 0.0 || cross(pb, pc, p) < 0.0 || cross(pc, pa, p) < 0.0
+    })
+}
+
+impl Hittable for Polygon {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        if !self.bounds.intersects(ray, t_min, t_max) {
+            return None;
+        }
+        let mut closest: Option<(&Triangle, f64, Vec3)> = None;
+        let watertight_ray = WatertightRay::new(ray);
+        for triangle in &self.triangles {
+            let t_max = closest.map_or(t_max, |(_, t, _)| t);
+            let points = [triangle.p1, triangle.p2, triangle.p3];
+            if let Some((t, barycentric)) =
+                Triangle::intersect(points, &watertight_ray, t_min, t_max)
+            {
+                closest = Some((triangle, t, barycentric));
+            }
+        }
+        closest.map(|(triangle, t, barycentric)| triangle.hit_at(ray, t, barycentric))
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(coords: &[(f64, f64)]) -> Vec<Vec3> {
+        coords.iter().map(|(x, y)| Vec3::new(*x, *y, 0.0)).collect()
+    }
+
+    fn area(triangle: [Vec3; 3]) -> f64 {
+        // Signed, positive for counterclockwise triangles in the xy-plane
+        (triangle[1] - triangle[0])
+            .cross(&(triangle[2] - triangle[0]))
+            .z
+            / 2.0
+    }
+
+    fn check(outline: Vec<Vec3>, holes: Vec<Vec<Vec3>>, expected_area: f64) -> Vec<[Vec3; 3]> {
+        // Every triangle has the winding of the outline and together they cover exactly the polygon
+        let all: Vec<Vec3> = outline
+            .iter()
+            .chain(holes.iter().flatten())
+            .copied()
+            .collect();
+        let sign = expected_area.signum();
+        let triangles: Vec<[Vec3; 3]> = Polygon::triangulate(&outline, &holes)
+            .iter()
+            .map(|[a, b, c]| [all[*a], all[*b], all[*c]])
+            .collect();
+        assert_eq!(triangles.len(), all.len() + 2 * holes.len() - 2);
+        assert!(triangles.iter().all(|t| sign * area(*t) > 0.0));
+        let total: f64 = triangles.iter().map(|t| area(*t)).sum();
+        assert!((total - expected_area).abs() < 1e-9, "{}", total);
+        triangles
+    }
+
+    #[test]
+    fn concave_outlines() {
+        let l_shape = points(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        check(l_shape, Vec::new(), 3.0);
+        // A comb with three teeth and a star, both with several reflex vertices in a row
+        let comb = points(&[
+            (0.0, 0.0),
+            (5.0, 0.0),
+            (5.0, 3.0),
+            (4.0, 3.0),
+            (4.0, 1.0),
+            (3.0, 1.0),
+            (3.0, 3.0),
+            (2.0, 3.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 3.0),
+            (0.0, 3.0),
+        ]);
+        check(comb, Vec::new(), 11.0);
+        let star: Vec<Vec3> = (0..10)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::PI / 5.0;
+                let r = if i % 2 == 0 { 2.0 } else { 0.8 };
+                Vec3::new(r * angle.cos(), r * angle.sin(), 0.0)
+            })
+            .collect();
+        let star_area = 10.0 * 0.5 * 2.0 * 0.8 * (std::f64::consts::PI / 5.0).sin();
+        check(star, Vec::new(), star_area);
+        // Wound clockwise the polygon faces the other way
+        let mut clockwise = points(&[
+            (0.0, 0.0),
+            (2.0, 0.0),
+            (2.0, 1.0),
+            (1.0, 1.0),
+            (1.0, 2.0),
+            (0.0, 2.0),
+        ]);
+        clockwise.reverse();
+        check(clockwise, Vec::new(), -3.0);
+    }
+
+    #[test]
+    fn holes() {
+        let square = points(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+        let hole = points(&[(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 2.0)]);
+        // Holes can be wound either way
+        check(square.clone(), vec![hole.clone()], 15.0);
+        let mut reversed = hole.clone();
+        reversed.reverse();
+        check(square.clone(), vec![reversed], 15.0);
+        // Two holes next to each other and a concave outline around a hole
+        let other = points(&[(2.5, 2.5), (3.5, 2.5), (3.5, 3.5), (2.5, 3.5)]);
+        let triangles = check(square, vec![hole.clone(), other], 14.0);
+        let in_hole = |p: Vec3| p.x > 1.0 && p.x < 2.0 && p.y > 1.0 && p.y < 2.0;
+        assert!(triangles
+            .iter()
+            .all(|t| !in_hole((t[0] + t[1] + t[2]) / 3.0)));
+        let u_shape = points(&[
+            (0.0, 0.0),
+            (6.0, 0.0),
+            (6.0, 4.0),
+            (4.0, 4.0),
+            (4.0, 3.0),
+            (3.0, 3.0),
+            (3.0, 4.0),
+            (0.0, 4.0),
+        ]);
+        check(u_shape, vec![hole], 22.0);
+    }
+
+    #[test]
+    fn rays_go_through_holes() {
+        let polygon = Polygon::with_holes(
+            points(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]),
+            vec![points(&[(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (1.0, 3.0)])],
+        );
+        let ray = |x: f64, y: f64| Ray {
+            origin: Vec3::new(x, y, 1.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        assert!(polygon.hit(&ray(2.0, 2.0), 0.0, f64::INFINITY).is_none());
+        let hit = polygon.hit(&ray(0.5, 2.0), 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 1.0).abs() < 1e-12 && hit.front_face);
     }
 }
@@ -0,0 +1,138 @@
+// Real roots of polynomials, used to intersect rays with curved surfaces
+// All functions return the roots in ascending order
+use crate::util::PI;
+
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // a x² + b x + c = 0, written to avoid cancellation when b is large
+    if a.abs() < 1e-12 {
+        return if b.abs() < 1e-12 {
+            Vec::new()
+        } else {
+            vec![-c / b]
+        };
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return Vec::new();
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    let mut roots = if q == 0.0 {
+        vec![0.0, 0.0]
+    } else {
+        vec![q / a, c / q]
+    };
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    // x³ + a x² + b x + c = 0, trigonometric method for three roots and Cardano's formula for one
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let q3 = q * q * q;
+    if r * r < q3 {
+        let theta = (r / q3.sqrt()).clamp(-1.0, 1.0).acos();
+        let s = -2.0 * q.sqrt();
+        let mut roots: Vec<f64> = (0..3)
+            .map(|k| s * ((theta + 2.0 * PI * k as f64) / 3.0).cos() - a / 3.0)
+            .collect();
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        roots
+    } else {
+        let big_a = -r.signum() * (r.abs() + (r * r - q3).sqrt()).cbrt();
+        let big_b = if big_a == 0.0 { 0.0 } else { q / big_a };
+        vec![big_a + big_b - a / 3.0]
+    }
+}
+
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    // x⁴ + a x³ + b x² + c x + d = 0 using Ferrari's method
+    // Substituting x = y - a / 4 gives y⁴ + p y² + q y + r = 0
+    let a2 = a * a;
+    let p = b - 3.0 * a2 / 8.0;
+    let q = c - a * b / 2.0 + a2 * a / 8.0;
+    let r = d - a * c / 4.0 + a2 * b / 16.0 - 3.0 * a2 * a2 / 256.0;
+    let ys: Vec<f64> = if q.abs() < 1e-12 {
+        // Quadratic in y²
+        solve_quadratic(1.0, p, r)
+            .into_iter()
+            .filter(|z| *z >= 0.0)
+            .flat_map(|z| [-z.sqrt(), z.sqrt()])
+            .collect()
+    } else {
+        // Splits into two quadratics using a positive root of the resolvent cubic
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0)
+            .into_iter()
+            .fold(f64::NEG_INFINITY, f64::max);
+        if m <= 0.0 {
+            return Vec::new();
+        }
+        let s = (2.0 * m).sqrt();
+        let mut ys = solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s));
+        ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+        ys
+    };
+    // The closed form loses precision, so polish the roots with a few steps of Newton's method
+    let mut roots: Vec<f64> = ys
+        .into_iter()
+        .map(|y| {
+            let mut x = y - a / 4.0;
+            for _ in 0..2 {
+                let f = (((x + a) * x + b) * x + c) * x + d;
+                let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+                if df != 0.0 {
+                    x -= f / df;
+                }
+            }
+            x
+        })
+        .collect();
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roots(actual: Vec<f64>, expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{:?}", actual);
+        }
+    }
+
+    #[test]
+    fn quadratic() {
+        // (x - 1)(x - 3), also with a large b where the textbook formula cancels
+        assert_roots(solve_quadratic(2.0, -8.0, 6.0), &[1.0, 3.0]);
+        assert_roots(solve_quadratic(1.0, -1e8, 1.0), &[1e-8, 1e8]);
+        assert_roots(solve_quadratic(1.0, -2.0, 1.0), &[1.0, 1.0]);
+        assert_roots(solve_quadratic(0.0, 2.0, -1.0), &[0.5]);
+        assert!(solve_quadratic(1.0, 0.0, 1.0).is_empty());
+        assert!(solve_quadratic(0.0, 0.0, 1.0).is_empty());
+    }
+
+    #[test]
+    fn cubic() {
+        // (x + 2)(x - 1)(x - 4) and (x - 2)(x² + 1)
+        assert_roots(solve_cubic(-3.0, -6.0, 8.0), &[-2.0, 1.0, 4.0]);
+        assert_roots(solve_cubic(-2.0, 1.0, -2.0), &[2.0]);
+    }
+
+    #[test]
+    fn quartic() {
+        // (x + 3)(x + 1)(x - 2)(x - 5), (x² - 4)(x² + 1) without the odd terms and (x² + 1)(x² + 4)
+        assert_roots(
+            solve_quartic(-3.0, -15.0, 19.0, 30.0),
+            &[-3.0, -1.0, 2.0, 5.0],
+        );
+        assert_roots(solve_quartic(0.0, -3.0, 0.0, -4.0), &[-2.0, 2.0]);
+        assert!(solve_quartic(0.0, 5.0, 0.0, 4.0).is_empty());
+        // Two double roots, like a ray that touches a torus on both sides: (x - 1)²(x - 3)²
+        let roots = solve_quartic(-8.0, 22.0, -24.0, 9.0);
+        assert!(roots
+            .iter()
+            .all(|x| (x - 1.0).abs() < 1e-4 || (x - 3.0).abs() < 1e-4));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Parallelogram spanned by the edges u and v from corner, the normal is u x v
+#[allow(dead_code)]
+pub struct Quad {
+    pub corner: Vec3,
+    pub u: Vec3,
+    pub v: Vec3,
+}
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let n = self.u.cross(&self.v);
+        let denom = n.dot(&ray.direction);
+        if denom.abs() < 1e-12 {
+            // Ray is parallel to the quad
+            return None;
+        }
+        let t = (self.corner - ray.origin).dot(&n) / denom;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.at(t);
+        // Coordinates of the hit along the edges, both are between 0 and 1 inside of the quad
+        let w = n / n.length_squared();
+        let planar = p - self.corner;
+        let alpha = w.dot(&planar.cross(&self.v));
+        let beta = w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+        Some(
+            Hit::new(p, n.unit_vector(), t, ray)
+                .with_uv(alpha, beta)
+                .with_tangent(self.u.unit_vector(), 1.0),
+        )
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        let corners = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.v,
+            self.corner + self.u + self.v,
+        ];
+        let min = |f: fn(&Vec3) -> f64| corners.iter().map(f).fold(f64::INFINITY, f64::min);
+        let max = |f: fn(&Vec3) -> f64| corners.iter().map(f).fold(f64::NEG_INFINITY, f64::max);
+        BoundingBox::new(
+            min(|c| c.x),
+            max(|c| c.x),
+            min(|c| c.y),
+            max(|c| c.y),
+            min(|c| c.z),
+            max(|c| c.z),
+        )
+    }
+}
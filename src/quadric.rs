@@ -0,0 +1,130 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::polynomial::solve_quadratic;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::vec3::Vec3;
+
+// Surface where Ax² + By² + Cz² + Dxy + Exz + Fyz + Gx + Hy + Iz + J = 0, cut off at the bounds
+// The outside is where the left hand side is positive
+pub struct Quadric {
+    pub coefficients: [f64; 10], // A to J
+    pub bounds: BoundingBox,
+}
+impl Quadric {
+    #[allow(dead_code)]
+    pub fn ellipsoid(center: Vec3, radii: Vec3) -> Quadric {
+        let (a, b, c) = (
+            1.0 / (radii.x * radii.x),
+            1.0 / (radii.y * radii.y),
+            1.0 / (radii.z * radii.z),
+        );
+        Quadric {
+            coefficients: [
+                a,
+                b,
+                c,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 * a * center.x,
+                -2.0 * b * center.y,
+                -2.0 * c * center.z,
+                a * center.x * center.x + b * center.y * center.y + c * center.z * center.z - 1.0,
+            ],
+            bounds: BoundingBox::new(
+                center.x - radii.x,
+                center.x + radii.x,
+                center.y - radii.y,
+                center.y + radii.y,
+                center.z - radii.z,
+                center.z + radii.z,
+            ),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn hyperboloid(center: Vec3, radius: f64, curvature: f64, half_height: f64) -> Quadric {
+        // Hyperboloid of one sheet around the y-axis, (x² + z²) / radius² - y² / curvature² = 1
+        // The radius at the top and bottom is radius * sqrt(1 + half_height² / curvature²)
+        let a = 1.0 / (radius * radius);
+        let b = -1.0 / (curvature * curvature);
+        let max_radius =
+            radius * (1.0 + half_height * half_height / (curvature * curvature)).sqrt();
+        Quadric {
+            coefficients: [
+                a,
+                b,
+                a,
+                0.0,
+                0.0,
+                0.0,
+                -2.0 * a * center.x,
+                -2.0 * b * center.y,
+                -2.0 * a * center.z,
+                a * center.x * center.x + b * center.y * center.y + a * center.z * center.z - 1.0,
+            ],
+            bounds: BoundingBox::new(
+                center.x - max_radius,
+                center.x + max_radius,
+                center.y - half_height,
+                center.y + half_height,
+                center.z - max_radius,
+                center.z + max_radius,
+            ),
+        }
+    }
+
+    fn gradient(&self, p: Vec3) -> Vec3 {
+        let [a, b, c, d, e, f, g, h, i, _] = self.coefficients;
+        Vec3::new(
+            2.0 * a * p.x + d * p.y + e * p.z + g,
+            2.0 * b * p.y + d * p.x + f * p.z + h,
+            2.0 * c * p.z + e * p.x + f * p.y + i,
+        )
+    }
+}
+impl Hittable for Quadric {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let [a, b, c, d, e, f, g, h, i, j] = self.coefficients;
+        let (o, v) = (ray.origin, ray.direction);
+        let t = solve_quadratic(
+            a * v.x * v.x
+                + b * v.y * v.y
+                + c * v.z * v.z
+                + d * v.x * v.y
+                + e * v.x * v.z
+                + f * v.y * v.z,
+            2.0 * (a * o.x * v.x + b * o.y * v.y + c * o.z * v.z)
+                + d * (o.x * v.y + o.y * v.x)
+                + e * (o.x * v.z + o.z * v.x)
+                + f * (o.y * v.z + o.z * v.y)
+                + g * v.x
+                + h * v.y
+                + i * v.z,
+            a * o.x * o.x
+                + b * o.y * o.y
+                + c * o.z * o.z
+                + d * o.x * o.y
+                + e * o.x * o.z
+                + f * o.y * o.z
+                + g * o.x
+                + h * o.y
+                + i * o.z
+                + j,
+        )
+        .into_iter()
+        .find(|t| *t >= t_min && *t <= t_max && self.bounds.encloses_point(&ray.at(*t)))?;
+        let p = ray.at(t);
+        let normal = self.gradient(p).unit_vector();
+        // Mapped like a sphere around the center of the bounds
+        let center = (self.bounds.lower() + self.bounds.higher()) * 0.5;
+        let (u, v) = Sphere::get_uv(&(p - center).unit_vector());
+        Some(Hit::new(p, normal, t, ray).with_uv(u, v))
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+}
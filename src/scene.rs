@@ -1,9 +1,12 @@
 use crate::hittable::ObjectContainer;
 use crate::light::Light;
+use crate::medium::Medium;
 
 pub struct Scene<'a> {
     pub objects: &'a dyn ObjectContainer,
     pub lights: &'a Vec<Box<dyn Light>>,
+    // Medium that fills the scene outside of objects, e.g. fog. It never ends, so it also hides the sky
+    pub medium: Option<&'a dyn Medium>,
 }
 
 unsafe impl<'a> Sync for Scene<'a> {}
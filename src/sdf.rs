@@ -0,0 +1,82 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::sphere::Sphere;
+use crate::vec3::Vec3;
+
+// Signed distance function, negative inside of the shape
+// The distance may be underestimated but never overestimated, or sphere tracing steps through the surface
+pub trait Sdf {
+    fn distance(&self, p: Vec3) -> f64;
+}
+
+const MAX_STEPS: usize = 512;
+const HIT_DISTANCE: f64 = 1e-5;
+
+// Shape defined by a signed distance function, rendered by sphere tracing inside of the bounds
+// "Sphere tracing: a geometric method for the antialiased ray tracing of implicit surfaces", Hart 1996
+pub struct SdfShape {
+    pub sdf: Box<dyn Sdf>,
+    pub bounds: BoundingBox, // Has to contain the whole surface
+    pub step_scale: f64, // Fraction of the distance that is stepped, lower it for distorted functions that overestimate the distance
+}
+impl SdfShape {
+    #[allow(dead_code)]
+    pub fn new(sdf: Box<dyn Sdf>, bounds: BoundingBox) -> SdfShape {
+        SdfShape {
+            sdf,
+            bounds,
+            step_scale: 1.0,
+        }
+    }
+    #[allow(dead_code)]
+    pub fn with_step_scale(mut self, step_scale: f64) -> SdfShape {
+        self.step_scale = step_scale;
+        self
+    }
+
+    fn normal(&self, p: Vec3) -> Vec3 {
+        // Gradient of the distance by central differences
+        let h = HIT_DISTANCE;
+        let d = |offset: Vec3| self.sdf.distance(p + offset) - self.sdf.distance(p - offset);
+        Vec3::new(
+            d(Vec3::new(h, 0.0, 0.0)),
+            d(Vec3::new(0.0, h, 0.0)),
+            d(Vec3::new(0.0, 0.0, h)),
+        )
+        .unit_vector()
+    }
+}
+impl Hittable for SdfShape {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t_start, t_end) = self.bounds.intersection(ray, t_min, t_max)?;
+        let length = ray.direction.length();
+        // Rays that start inside of the shape look for the point where the distance becomes positive
+        let side = self.sdf.distance(ray.at(t_start)).signum();
+        let mut t = t_start;
+        for step in 0..MAX_STEPS {
+            let distance = side * self.sdf.distance(ray.at(t));
+            if distance < HIT_DISTANCE {
+                // Rays leaving a surface start right next to it, don't hit it again
+                if step > 0 || t_start > t_min {
+                    let p = ray.at(t);
+                    let center = (self.bounds.lower() + self.bounds.higher()) * 0.5;
+                    let (u, v) = Sphere::get_uv(&(p - center).unit_vector());
+                    return Some(Hit::new(p, self.normal(p), t, ray).with_uv(u, v));
+                }
+                t += 2.0 * HIT_DISTANCE / length;
+            } else {
+                t += self.step_scale * distance / length;
+            }
+            if t > t_end {
+                return None;
+            }
+        }
+        None
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        self.bounds.clone()
+    }
+}
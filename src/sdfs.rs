@@ -0,0 +1,292 @@
+use crate::sdf::Sdf;
+use crate::vec3::Vec3;
+
+// The shapes are centered on the origin, move them with Translate and resize them with Scale
+// Distance functions from https://iquilezles.org/articles/distfunctions/
+
+#[allow(dead_code)]
+pub struct SdfSphere {
+    pub radius: f64,
+}
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Vec3) -> f64 {
+        p.length() - self.radius
+    }
+}
+
+#[allow(dead_code)]
+pub struct RoundedBox {
+    pub half_size: Vec3, // Including the rounding
+    pub radius: f64,
+}
+impl Sdf for RoundedBox {
+    fn distance(&self, p: Vec3) -> f64 {
+        let q = Vec3::new(p.x.abs(), p.y.abs(), p.z.abs()) - self.half_size
+            + Vec3::new(self.radius, self.radius, self.radius);
+        q.max(0.0).length() + q.max_component().min(0.0) - self.radius
+    }
+}
+
+#[allow(dead_code)]
+pub struct SdfTorus {
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Vec3) -> f64 {
+        // Lies in the xz-plane
+        let ring = (p.x * p.x + p.z * p.z).sqrt() - self.major_radius;
+        (ring * ring + p.y * p.y).sqrt() - self.minor_radius
+    }
+}
+
+// Cube with crosses cut out of it recursively, with a side length of 2
+#[allow(dead_code)]
+pub struct MengerSponge {
+    pub iterations: usize,
+}
+impl Sdf for MengerSponge {
+    fn distance(&self, p: Vec3) -> f64 {
+        let cube = |p: Vec3, half_size: f64| {
+            let q = Vec3::new(p.x.abs(), p.y.abs(), p.z.abs())
+                - Vec3::new(half_size, half_size, half_size);
+            q.max(0.0).length() + q.max_component().min(0.0)
+        };
+        let mut distance = cube(p, 1.0);
+        let mut scale = 1.0;
+        for _ in 0..self.iterations {
+            // Fold the space into a cell of the current level, the cross goes through the middle of it
+            let a = Vec3::new(
+                (p.x * scale).rem_euclid(2.0) - 1.0,
+                (p.y * scale).rem_euclid(2.0) - 1.0,
+                (p.z * scale).rem_euclid(2.0) - 1.0,
+            );
+            scale *= 3.0;
+            let r = Vec3::new(
+                (1.0 - 3.0 * a.x.abs()).abs(),
+                (1.0 - 3.0 * a.y.abs()).abs(),
+                (1.0 - 3.0 * a.z.abs()).abs(),
+            );
+            let cross = (r.x.max(r.y).min(r.y.max(r.z)).min(r.z.max(r.x)) - 1.0) / scale;
+            distance = distance.max(cross);
+        }
+        distance
+    }
+}
+
+// Three dimensional version of the Mandelbrot set, fits in a sphere of radius 1.2
+#[allow(dead_code)]
+pub struct Mandelbulb {
+    pub power: f64,
+    pub iterations: usize,
+}
+impl Sdf for Mandelbulb {
+    fn distance(&self, p: Vec3) -> f64 {
+        // Distance estimate from the derivative of the iteration
+        let mut z = p;
+        let mut dr = 1.0;
+        let mut r = z.length();
+        for _ in 0..self.iterations {
+            if r > 2.0 {
+                break;
+            }
+            let theta = (z.y / r).acos() * self.power;
+            let phi = z.z.atan2(z.x) * self.power;
+            dr = self.power * r.powf(self.power - 1.0) * dr + 1.0;
+            let zr = r.powf(self.power);
+            z = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ) * zr
+                + p;
+            r = z.length();
+        }
+        0.5 * r.ln() * r / dr
+    }
+}
+
+#[allow(dead_code)]
+pub struct Translate {
+    pub sdf: Box<dyn Sdf>,
+    pub offset: Vec3,
+}
+impl Sdf for Translate {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.sdf.distance(p - self.offset)
+    }
+}
+
+#[allow(dead_code)]
+pub struct Scale {
+    pub sdf: Box<dyn Sdf>,
+    pub factor: f64,
+}
+impl Sdf for Scale {
+    fn distance(&self, p: Vec3) -> f64 {
+        self.sdf.distance(p / self.factor) * self.factor
+    }
+}
+
+// Union that blends the shapes together where they are closer than k
+#[allow(dead_code)]
+pub struct SmoothUnion {
+    pub a: Box<dyn Sdf>,
+    pub b: Box<dyn Sdf>,
+    pub k: f64,
+}
+impl Sdf for SmoothUnion {
+    fn distance(&self, p: Vec3) -> f64 {
+        let d1 = self.a.distance(p);
+        let d2 = self.b.distance(p);
+        let h = (0.5 + 0.5 * (d2 - d1) / self.k).clamp(0.0, 1.0);
+        d2 + (d1 - d2) * h - self.k * h * (1.0 - h)
+    }
+}
+
+// Rotates the shape around the y-axis by rate radians per unit of height
+// This overestimates the distance, render it with a lower step scale
+#[allow(dead_code)]
+pub struct Twist {
+    pub sdf: Box<dyn Sdf>,
+    pub rate: f64,
+}
+impl Sdf for Twist {
+    fn distance(&self, p: Vec3) -> f64 {
+        let (sin, cos) = (self.rate * p.y).sin_cos();
+        self.sdf
+            .distance(Vec3::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+    }
+}
+
+// Repeats the shape in a grid with the given spacing, the shape has to fit in a cell
+#[allow(dead_code)]
+pub struct Repeat {
+    pub sdf: Box<dyn Sdf>,
+    pub period: Vec3,
+}
+impl Sdf for Repeat {
+    fn distance(&self, p: Vec3) -> f64 {
+        let wrap = |x: f64, period: f64| x - period * (x / period).round();
+        self.sdf.distance(Vec3::new(
+            wrap(p.x, self.period.x),
+            wrap(p.y, self.period.y),
+            wrap(p.z, self.period.z),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::{BoundingBox, Hittable};
+    use crate::ray::Ray;
+    use crate::sdf::SdfShape;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn primitives() {
+        let sphere = SdfSphere { radius: 1.0 };
+        assert_close(sphere.distance(Vec3::new(0.0, 3.0, 0.0)), 2.0);
+        assert_close(sphere.distance(Vec3::new(0.0, 0.0, 0.0)), -1.0);
+        let rounded = RoundedBox {
+            half_size: Vec3::new(1.0, 2.0, 3.0),
+            radius: 0.5,
+        };
+        // Next to a face, inside and diagonally off a rounded corner
+        assert_close(rounded.distance(Vec3::new(3.0, 0.0, 0.0)), 2.0);
+        assert_close(rounded.distance(Vec3::new(0.0, 0.0, 0.0)), -1.0);
+        let corner = Vec3::new(0.5, 1.5, 2.5);
+        let p = corner + Vec3::new(1.0, 1.0, 1.0).unit_vector() * 1.5;
+        assert_close(rounded.distance(p), 1.0);
+        let torus = SdfTorus {
+            major_radius: 2.0,
+            minor_radius: 0.5,
+        };
+        assert_close(torus.distance(Vec3::new(0.0, 0.0, 0.0)), 1.5);
+        assert_close(torus.distance(Vec3::new(0.0, 1.0, 2.0)), 0.5);
+        assert_close(torus.distance(Vec3::new(2.0, 0.0, 0.0)), -0.5);
+    }
+
+    #[test]
+    fn fractals() {
+        // The center of the sponge is cut out from the first iteration on, the corners stay
+        let sponge = MengerSponge { iterations: 3 };
+        assert!(sponge.distance(Vec3::new(0.0, 0.0, 0.0)) > 0.0);
+        assert!(sponge.distance(Vec3::new(0.99, 0.99, 0.99)) < 0.0);
+        assert_close(sponge.distance(Vec3::new(3.0, 0.9, 0.9)), 2.0);
+        // The estimate of the bulb must never overshoot, the bulb fits in a sphere of radius 1.2
+        let bulb = Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+        };
+        let d = bulb.distance(Vec3::new(3.0, 0.0, 0.0));
+        assert!(d > 0.0 && d <= 3.0);
+        assert!(bulb.distance(Vec3::new(0.0, 0.1, 0.0)) < 1e-3);
+    }
+
+    #[test]
+    fn operations() {
+        let sphere = || Box::new(SdfSphere { radius: 1.0 });
+        let moved = Translate {
+            sdf: sphere(),
+            offset: Vec3::new(5.0, 0.0, 0.0),
+        };
+        assert_close(moved.distance(Vec3::new(5.0, 2.0, 0.0)), 1.0);
+        let scaled = Scale {
+            sdf: sphere(),
+            factor: 2.0,
+        };
+        assert_close(scaled.distance(Vec3::new(0.0, 0.0, 5.0)), 3.0);
+        // Far away from both shapes the smooth union is the plain union, in between it bulges out
+        let blended = SmoothUnion {
+            a: sphere(),
+            b: Box::new(moved),
+            k: 0.5,
+        };
+        assert_close(blended.distance(Vec3::new(-3.0, 0.0, 0.0)), 2.0);
+        assert!(blended.distance(Vec3::new(2.5, 0.0, 0.0)) < 1.5);
+        let repeated = Repeat {
+            sdf: sphere(),
+            period: Vec3::new(4.0, 4.0, 4.0),
+        };
+        assert_close(repeated.distance(Vec3::new(8.0, -4.0, 2.0)), 1.0);
+        // A twisted box turns by a quarter turn at the top
+        let twisted = Twist {
+            sdf: Box::new(RoundedBox {
+                half_size: Vec3::new(2.0, 4.0, 0.5),
+                radius: 0.0,
+            }),
+            rate: std::f64::consts::FRAC_PI_2 / 4.0,
+        };
+        assert!(twisted.distance(Vec3::new(1.5, 0.0, 0.0)) < 0.0);
+        assert!(twisted.distance(Vec3::new(0.0, 3.99, 1.5)) < 0.0);
+        assert!(twisted.distance(Vec3::new(1.5, 3.99, 0.0)) > 0.0);
+    }
+
+    #[test]
+    fn sphere_tracing() {
+        let shape = SdfShape::new(
+            Box::new(SdfSphere { radius: 1.0 }),
+            BoundingBox::new(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0),
+        );
+        let ray = Ray {
+            origin: Vec3::new(0.0, 0.0, 5.0),
+            direction: Vec3::new(0.0, 0.0, -2.0),
+        };
+        let hit = shape.hit(&ray, 0.0, f64::INFINITY).unwrap();
+        assert!((hit.t - 2.0).abs() < 1e-4);
+        assert!(hit.normal.z > 0.999);
+        // Starting on the surface the ray leaves through the other side
+        let inside = Ray {
+            origin: hit.p,
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        let exit = shape.hit(&inside, 1e-3, f64::INFINITY).unwrap();
+        assert!((exit.t - 2.0).abs() < 1e-4);
+        assert!(shape.hit(&ray, 0.0, 1.0).is_none());
+    }
+}
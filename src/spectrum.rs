@@ -0,0 +1,164 @@
+// Helpers for the spectral rendering mode
+// A path carries a few wavelength samples, the values of a spectrum at these wavelengths are stored in a Vec3
+use crate::vec3::Vec3;
+use std::sync::OnceLock;
+
+// Range of wavelengths in nm that gets sampled
+const MIN_WAVELENGTH: f64 = 360.0;
+const MAX_WAVELENGTH: f64 = 830.0;
+
+#[derive(Clone, Copy)]
+pub struct SampledWavelengths {
+    pub lambda: [f64; 3],
+    pub pdf: [f64; 3], // Zero for wavelengths that were terminated
+}
+impl SampledWavelengths {
+    pub fn sample_visible(u: f64) -> SampledWavelengths {
+        // The wavelengths are stratified over the range and sampled where the eye is most sensitive
+        // "Hero wavelength spectral sampling", Wilkie et al. 2014 and the visible wavelength distribution of pbrt-v4
+        let mut lambda = [0.0; 3];
+        let mut pdf = [0.0; 3];
+        for i in 0..3 {
+            let u = (u + i as f64 / 3.0).fract();
+            lambda[i] = 538.0 - 138.888889 * (0.85691062 - 1.82750197 * u).atanh();
+            pdf[i] = visible_wavelength_pdf(lambda[i]);
+        }
+        SampledWavelengths { lambda, pdf }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn terminate_secondary(&mut self) {
+        // Used when light of different wavelengths takes different paths, e.g. with dispersion
+        // Only the hero wavelength is traced further, it then stands in for all the wavelengths
+        if self.pdf[1] == 0.0 {
+            return;
+        }
+        self.pdf = [self.pdf[0] / 3.0, 0.0, 0.0];
+    }
+
+    pub fn upsample_rgb(&self, rgb: Vec3) -> Vec3 {
+        Vec3::new(
+            rgb_to_spectrum(rgb, self.lambda[0]),
+            rgb_to_spectrum(rgb, self.lambda[1]),
+            rgb_to_spectrum(rgb, self.lambda[2]),
+        )
+    }
+
+    pub fn estimate_xyz(&self, values: Vec3) -> Vec3 {
+        // Monte Carlo estimate of the CIE XYZ color of the spectrum, scaled so that a white of 1.0 has a Y of 1.0
+        let values = [values.x, values.y, values.z];
+        (0..3)
+            .filter(|i| self.pdf[*i] > 0.0)
+            .fold(Vec3::z(), |acc, i| {
+                acc + cie_xyz(self.lambda[i]) * (values[i] / self.pdf[i])
+            })
+            / (3.0 * white_xyz().y)
+    }
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Vec3 {
+    // Only done for the mean of all samples of a pixel, clamping single samples would bias the result
+    (xyz_to_rgb(xyz) * white_balance()).max(0.0)
+}
+
+fn visible_wavelength_pdf(lambda: f64) -> f64 {
+    if !(MIN_WAVELENGTH..=MAX_WAVELENGTH).contains(&lambda) {
+        return 0.0;
+    }
+    0.0039398042 / (0.0072 * (lambda - 538.0)).cosh().powi(2)
+}
+
+fn cie_xyz(lambda: f64) -> Vec3 {
+    // Fit of the CIE 1931 color matching functions
+    // "Simple Analytic Approximations to the CIE XYZ Color Matching Functions", Wyman et al. 2013
+    let g = |mu: f64, sigma_low: f64, sigma_high: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma_low } else { sigma_high };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3::new(
+        3.2406 * xyz.x - 1.5372 * xyz.y - 0.4986 * xyz.z,
+        -0.9689 * xyz.x + 1.8758 * xyz.y + 0.0415 * xyz.z,
+        0.0557 * xyz.x - 0.2040 * xyz.y + 1.0570 * xyz.z,
+    )
+}
+
+fn white_xyz() -> Vec3 {
+    // XYZ color of an equal energy white
+    static WHITE_XYZ: OnceLock<Vec3> = OnceLock::new();
+    *WHITE_XYZ.get_or_init(|| {
+        (MIN_WAVELENGTH as usize..=MAX_WAVELENGTH as usize)
+            .fold(Vec3::z(), |acc, lambda| acc + cie_xyz(lambda as f64))
+    })
+}
+
+fn white_balance() -> Vec3 {
+    // The spectra of RGB colors are relative to an equal energy white, scale the channels so that it stays white
+    static WHITE_BALANCE: OnceLock<Vec3> = OnceLock::new();
+    *WHITE_BALANCE.get_or_init(|| {
+        let white = xyz_to_rgb(white_xyz() / white_xyz().y);
+        Vec3::new(1.0 / white.x, 1.0 / white.y, 1.0 / white.z)
+    })
+}
+
+// Spectra of the basis colors from 380nm to 720nm in 10 equal bins
+// "An RGB to Spectrum Conversion for Reflectances", Smits 1999
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+pub fn rgb_to_spectrum(rgb: Vec3, lambda: f64) -> f64 {
+    // Value of a smooth spectrum that has the given color, wavelengths outside of the table use the closest bin
+    let bin = (((lambda - 380.0) / 34.0).max(0.0) as usize).min(9);
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin]
+            + if g <= b {
+                (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+            } else {
+                (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+            }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin]
+            + if r <= b {
+                (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+            } else {
+                (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+            }
+    } else {
+        b * SMITS_WHITE[bin]
+            + if r <= g {
+                (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+            } else {
+                (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+            }
+    }
+}
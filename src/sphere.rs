@@ -1,7 +1,9 @@
 use crate::hittable::BoundingBox;
 use crate::hittable::Hit;
 use crate::hittable::Hittable;
+use crate::hittable::Interval;
 use crate::ray::Ray;
+use crate::util::PI;
 use crate::vec3::Vec3;
 
 pub struct Sphere {
@@ -9,8 +11,15 @@ pub struct Sphere {
     pub radius: f64,
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+impl Sphere {
+    pub fn get_uv(p: &Vec3) -> (f64, f64) {
+        // p is a point on the unit sphere, u goes around the y-axis starting at -x and v goes from bottom to top
+        let theta = (-p.y).acos();
+        let phi = (-p.z).atan2(p.x) + PI;
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn roots(&self, ray: &Ray) -> Option<(f64, f64)> {
         let oc = ray.origin - self.center;
         let a = ray.direction.length_squared();
         let half_b = oc.dot(&ray.direction);
@@ -20,19 +29,50 @@ impl Hittable for Sphere {
             return None;
         }
         let sqrtd = discriminant.sqrt();
+        Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+    }
 
-        let mut root = (-half_b - sqrtd) / a;
+    fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+        let p = ray.at(t);
+        let outward_normal = (p - self.center) / self.radius;
+        let unit_p = (p - self.center) / self.radius.abs();
+        let (u, v) = Sphere::get_uv(&unit_p);
+        let hit = Hit::new(p, outward_normal, t, ray).with_uv(u, v);
+        // The tangent goes around the y-axis, it's undefined at the poles
+        let tangent = Vec3::new(unit_p.z, 0.0, -unit_p.x);
+        if tangent.near_zero() {
+            hit
+        } else {
+            // Turning the sphere inside out flips the outward normal but not the direction in which v increases
+            hit.with_tangent(tangent.unit_vector(), self.radius.signum())
+        }
+    }
+}
+
+impl Hittable for Sphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (near, far) = self.roots(ray)?;
+        let mut root = near;
         if root < t_min || t_max < root {
-            root = (-half_b + sqrtd) / a;
+            root = far;
             if root < t_min || t_max < root {
                 return None;
             }
         }
-        let t = root;
-        let p = ray.at(root);
-        let outward_normal = (p - self.center) / self.radius;
-        Some(Hit::new(p, outward_normal, t, ray))
+        Some(self.hit_at(ray, root))
+    }
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // A sphere with a negative radius is inside out, it can't be used as a solid
+        // Rays that only touch the sphere don't go through it
+        match self.roots(ray) {
+            Some((near, far)) if self.radius > 0.0 && near < far => vec![Interval {
+                enter: self.hit_at(ray, near),
+                exit: self.hit_at(ray, far),
+            }],
+            _ => Vec::new(),
+        }
     }
+
     fn get_bounds(&self) -> BoundingBox {
         let radius = if self.radius < 0.0 {
             -self.radius
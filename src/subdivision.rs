@@ -0,0 +1,578 @@
+// Subdivision surfaces, refines a polygon mesh towards a smooth surface
+// Catmull-Clark is used for meshes with quads or larger polygons and Loop for pure triangle meshes
+// Sharp edges stay sharp, with the crease rules from "Piecewise Smooth Surface Reconstruction", Hoppe et al. 1994
+use crate::util::PI;
+use crate::vec3::Vec3;
+use std::collections::{HashMap, HashSet};
+
+// Polygon mesh with indices starting at 0
+#[derive(Clone)]
+pub struct ControlMesh {
+    pub positions: Vec<Vec3>,
+    pub tex_coords: Vec<(f64, f64)>,
+    pub colors: Option<Vec<Vec3>>, // For every position, interpolated linearly like the texture coordinates
+    pub faces: Vec<Vec<(usize, Option<usize>)>>, // Position and texture coordinate of every corner
+    pub parents: Vec<usize>, // Index of the face of the original mesh that every face comes from
+    pub sharp_edges: HashSet<Edge>,
+}
+
+// Pair of vertices, the lower one first
+type Edge = (usize, usize);
+
+fn edge(a: usize, b: usize) -> Edge {
+    (a.min(b), a.max(b))
+}
+
+fn average(points: impl Iterator<Item = Vec3>) -> Vec3 {
+    let (sum, n) = points.fold((Vec3::z(), 0), |(sum, n), p| (sum + p, n + 1));
+    sum / n as f64
+}
+
+fn midpoint(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)
+}
+
+impl ControlMesh {
+    pub fn new(
+        positions: Vec<Vec3>,
+        tex_coords: Vec<(f64, f64)>,
+        colors: Option<Vec<Vec3>>,
+        faces: Vec<Vec<(usize, Option<usize>)>>,
+    ) -> ControlMesh {
+        ControlMesh {
+            positions,
+            tex_coords,
+            colors,
+            parents: (0..faces.len()).collect(),
+            faces,
+            sharp_edges: HashSet::new(),
+        }
+    }
+
+    pub fn mark_creases(&mut self, crease_angle: f64) {
+        // Edges where the faces meet at a larger angle than crease_angle, in radians, become sharp
+        // Edges shared by more than two faces are always sharp, edges on the border are handled as sharp anyway
+        let normals = self.face_normals();
+        for (e, faces) in self.edge_faces() {
+            let sharp = match faces[..] {
+                [a, b] => {
+                    let (na, nb) = (normals[a], normals[b]);
+                    !na.near_zero()
+                        && !nb.near_zero()
+                        && na.unit_vector().dot(&nb.unit_vector()) < crease_angle.cos()
+                }
+                [_] => false,
+                _ => true,
+            };
+            if sharp {
+                self.sharp_edges.insert(e);
+            }
+        }
+    }
+
+    pub fn mark_sharp_edges(&mut self, edges: &[(usize, usize)]) {
+        // Edges given by the positions at their ends, pairs that aren't an edge of the mesh are ignored
+        let edge_faces = self.edge_faces();
+        self.sharp_edges.extend(
+            edges
+                .iter()
+                .map(|(a, b)| edge(*a, *b))
+                .filter(|e| edge_faces.contains_key(e)),
+        );
+    }
+
+    pub fn vertex_normals(&self) -> (Vec<Vec3>, Vec<Vec<usize>>) {
+        // Returns the normals and the index of the normal at every corner of the faces
+        // The faces around a vertex share a normal, except where a sharp edge is between them
+        let face_normals = self.face_normals();
+        let mut first_corner = Vec::with_capacity(self.faces.len());
+        let mut corners = 0;
+        for face in &self.faces {
+            first_corner.push(corners);
+            corners += face.len();
+        }
+        let corner = |f: usize, v: usize| {
+            first_corner[f] + self.faces[f].iter().position(|(p, _)| *p == v).unwrap()
+        };
+        // Union-find over the corners, corners on both sides of a smooth edge are joined
+        let mut groups: Vec<usize> = (0..corners).collect();
+        fn find(groups: &mut [usize], mut i: usize) -> usize {
+            while groups[i] != i {
+                groups[i] = groups[groups[i]];
+                i = groups[i];
+            }
+            i
+        }
+        let edge_faces = self.edge_faces();
+        for (e, faces) in &edge_faces {
+            if self.is_sharp(*e, &edge_faces) {
+                continue;
+            }
+            for v in [e.0, e.1] {
+                let a = find(&mut groups, corner(faces[0], v));
+                let b = find(&mut groups, corner(faces[1], v));
+                groups[a] = b;
+            }
+        }
+        // The normals of the faces are weighted by their area
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut group_normals: HashMap<usize, usize> = HashMap::new();
+        let mut corner_normals = Vec::with_capacity(self.faces.len());
+        for (f, face) in self.faces.iter().enumerate() {
+            let indices = (0..face.len())
+                .map(|i| {
+                    let group = find(&mut groups, first_corner[f] + i);
+                    let index = *group_normals.entry(group).or_insert_with(|| {
+                        normals.push(Vec3::z());
+                        normals.len() - 1
+                    });
+                    normals[index] += face_normals[f];
+                    index
+                })
+                .collect();
+            corner_normals.push(indices);
+        }
+        let normals = normals
+            .into_iter()
+            .map(|n| if n.near_zero() { n } else { n.unit_vector() })
+            .collect();
+        (normals, corner_normals)
+    }
+
+    fn face_normals(&self) -> Vec<Vec3> {
+        // Newell's method, works for polygons that aren't quite planar, the length is twice the area
+        self.faces
+            .iter()
+            .map(|face| {
+                (0..face.len()).fold(Vec3::z(), |acc, i| {
+                    let a = self.positions[face[i].0];
+                    let b = self.positions[face[(i + 1) % face.len()].0];
+                    acc + Vec3::new(
+                        (a.y - b.y) * (a.z + b.z),
+                        (a.z - b.z) * (a.x + b.x),
+                        (a.x - b.x) * (a.y + b.y),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    pub fn subdivide(self, levels: usize) -> ControlMesh {
+        let triangles = self.faces.iter().all(|face| face.len() == 3);
+        (0..levels).fold(self, |mesh, _| {
+            if triangles {
+                mesh.loop_step()
+            } else {
+                mesh.catmull_clark_step()
+            }
+        })
+    }
+
+    fn edge_faces(&self) -> HashMap<Edge, Vec<usize>> {
+        let mut edges: HashMap<Edge, Vec<usize>> = HashMap::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            for i in 0..face.len() {
+                let e = edge(face[i].0, face[(i + 1) % face.len()].0);
+                edges.entry(e).or_default().push(f);
+            }
+        }
+        edges
+    }
+
+    fn is_sharp(&self, e: Edge, edge_faces: &HashMap<Edge, Vec<usize>>) -> bool {
+        edge_faces[&e].len() != 2 || self.sharp_edges.contains(&e)
+    }
+
+    fn vertex_edges(&self, edge_faces: &HashMap<Edge, Vec<usize>>) -> Vec<Vec<Edge>> {
+        let mut vertex_edges = vec![Vec::new(); self.positions.len()];
+        for e in edge_faces.keys() {
+            vertex_edges[e.0].push(*e);
+            vertex_edges[e.1].push(*e);
+        }
+        vertex_edges
+    }
+
+    fn crease_point(&self, v: usize, sharp: &[Edge]) -> Option<Vec3> {
+        // Vertices on two sharp edges move along the crease, corners with more stay where they are
+        // Returns None for smooth vertices
+        let p = self.positions[v];
+        match sharp.len() {
+            0 | 1 => None,
+            2 => {
+                let other = |e: Edge| self.positions[if e.0 == v { e.1 } else { e.0 }];
+                Some(p * 0.75 + (other(sharp[0]) + other(sharp[1])) * 0.125)
+            }
+            _ => Some(p),
+        }
+    }
+
+    fn split_tex_coords(&self) -> (Vec<(f64, f64)>, HashMap<Edge, usize>) {
+        // Texture coordinates are interpolated linearly, so seams in the texture stay where they are
+        // Every edge of a face gets the midpoint of the texture coordinates at its ends
+        let mut tex_coords = self.tex_coords.clone();
+        let mut edge_tex_coords = HashMap::new();
+        for face in &self.faces {
+            for i in 0..face.len() {
+                if let (Some(a), Some(b)) = (face[i].1, face[(i + 1) % face.len()].1) {
+                    edge_tex_coords.entry(edge(a, b)).or_insert_with(|| {
+                        tex_coords.push(midpoint(self.tex_coords[a], self.tex_coords[b]));
+                        tex_coords.len() - 1
+                    });
+                }
+            }
+        }
+        (tex_coords, edge_tex_coords)
+    }
+
+    fn split_colors(&self, edges: &[Edge]) -> Option<Vec<Vec3>> {
+        // Colors of the vertices followed by the ones in the middle of the edges
+        self.colors.as_ref().map(|colors| {
+            let mut split = colors.clone();
+            split.extend(edges.iter().map(|e| (colors[e.0] + colors[e.1]) * 0.5));
+            split
+        })
+    }
+
+    fn catmull_clark_step(&self) -> ControlMesh {
+        // New positions are the moved vertices, then a point for every edge, then one for every face
+        let edge_faces = self.edge_faces();
+        let vertex_edges = self.vertex_edges(&edge_faces);
+        let face_points: Vec<Vec3> = self
+            .faces
+            .iter()
+            .map(|face| average(face.iter().map(|(v, _)| self.positions[*v])))
+            .collect();
+        let mut edges: Vec<Edge> = edge_faces.keys().copied().collect();
+        edges.sort();
+        let edge_index: HashMap<Edge, usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (*e, self.positions.len() + i))
+            .collect();
+        let edge_points = edges.iter().map(|e| {
+            let mid = (self.positions[e.0] + self.positions[e.1]) * 0.5;
+            if self.is_sharp(*e, &edge_faces) {
+                mid
+            } else {
+                let faces = &edge_faces[e];
+                (mid + (face_points[faces[0]] + face_points[faces[1]]) * 0.5) * 0.5
+            }
+        });
+        let vertex_points = (0..self.positions.len()).map(|v| {
+            let incident = &vertex_edges[v];
+            let sharp: Vec<Edge> = incident
+                .iter()
+                .filter(|e| self.is_sharp(**e, &edge_faces))
+                .copied()
+                .collect();
+            if incident.is_empty() {
+                return self.positions[v];
+            }
+            self.crease_point(v, &sharp).unwrap_or_else(|| {
+                let mut faces: Vec<usize> = incident
+                    .iter()
+                    .flat_map(|e| edge_faces[e].clone())
+                    .collect();
+                faces.sort();
+                faces.dedup();
+                let n = incident.len() as f64;
+                let q = average(faces.iter().map(|f| face_points[*f]));
+                let r = average(
+                    incident
+                        .iter()
+                        .map(|e| (self.positions[e.0] + self.positions[e.1]) * 0.5),
+                );
+                (q + r * 2.0 + self.positions[v] * (n - 3.0)) / n
+            })
+        });
+        let face_start = self.positions.len() + edges.len();
+        let mut positions: Vec<Vec3> = vertex_points.collect();
+        positions.extend(edge_points);
+        positions.extend(face_points.iter().copied());
+        let colors = self.split_colors(&edges).map(|mut colors| {
+            let face_colors: Vec<Vec3> = self
+                .faces
+                .iter()
+                .map(|face| average(face.iter().map(|(v, _)| colors[*v])))
+                .collect();
+            colors.extend(face_colors);
+            colors
+        });
+
+        let (mut tex_coords, edge_tex_coords) = self.split_tex_coords();
+        let mut faces = Vec::new();
+        let mut parents = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            // The face is split into a quad at every corner
+            let has_tex_coords = face.iter().all(|(_, t)| t.is_some());
+            let center_tex_coord = has_tex_coords.then(|| {
+                let n = face.len() as f64;
+                let sum = face.iter().fold((0.0, 0.0), |acc, (_, t)| {
+                    let uv = self.tex_coords[t.unwrap()];
+                    (acc.0 + uv.0, acc.1 + uv.1)
+                });
+                tex_coords.push((sum.0 / n, sum.1 / n));
+                tex_coords.len() - 1
+            });
+            let edge_corner = |a: (usize, Option<usize>), b: (usize, Option<usize>)| {
+                let t = match (a.1, b.1) {
+                    (Some(ta), Some(tb)) if has_tex_coords => Some(edge_tex_coords[&edge(ta, tb)]),
+                    _ => None,
+                };
+                (edge_index[&edge(a.0, b.0)], t)
+            };
+            let n = face.len();
+            for i in 0..n {
+                let prev = face[(i + n - 1) % n];
+                let next = face[(i + 1) % n];
+                faces.push(vec![
+                    (face[i].0, face[i].1.filter(|_| has_tex_coords)),
+                    edge_corner(face[i], next),
+                    (face_start + f, center_tex_coord),
+                    edge_corner(prev, face[i]),
+                ]);
+                parents.push(self.parents[f]);
+            }
+        }
+        let sharp_edges = self
+            .sharp_edges
+            .iter()
+            .flat_map(|e| {
+                let mid = edge_index[e];
+                [edge(e.0, mid), edge(mid, e.1)]
+            })
+            .collect();
+        ControlMesh {
+            positions,
+            tex_coords,
+            colors,
+            faces,
+            parents,
+            sharp_edges,
+        }
+    }
+
+    fn loop_step(&self) -> ControlMesh {
+        // New positions are the moved vertices, then a point for every edge
+        let edge_faces = self.edge_faces();
+        let vertex_edges = self.vertex_edges(&edge_faces);
+        let mut edges: Vec<Edge> = edge_faces.keys().copied().collect();
+        edges.sort();
+        let edge_index: HashMap<Edge, usize> = edges
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (*e, self.positions.len() + i))
+            .collect();
+        let opposite = |face: usize, e: Edge| {
+            let face = &self.faces[face];
+            self.positions[face.iter().find(|(v, _)| *v != e.0 && *v != e.1).unwrap().0]
+        };
+        let edge_points = edges.iter().map(|e| {
+            let (a, b) = (self.positions[e.0], self.positions[e.1]);
+            if self.is_sharp(*e, &edge_faces) {
+                (a + b) * 0.5
+            } else {
+                let faces = &edge_faces[e];
+                (a + b) * 0.375 + (opposite(faces[0], *e) + opposite(faces[1], *e)) * 0.125
+            }
+        });
+        let vertex_points = (0..self.positions.len()).map(|v| {
+            let incident = &vertex_edges[v];
+            let sharp: Vec<Edge> = incident
+                .iter()
+                .filter(|e| self.is_sharp(**e, &edge_faces))
+                .copied()
+                .collect();
+            if incident.is_empty() {
+                return self.positions[v];
+            }
+            self.crease_point(v, &sharp).unwrap_or_else(|| {
+                let n = incident.len() as f64;
+                let beta = (0.625 - (0.375 + 0.25 * (2.0 * PI / n).cos()).powi(2)) / n;
+                let neighbors = incident.iter().fold(Vec3::z(), |acc, e| {
+                    acc + self.positions[if e.0 == v { e.1 } else { e.0 }]
+                });
+                self.positions[v] * (1.0 - n * beta) + neighbors * beta
+            })
+        });
+        let mut positions: Vec<Vec3> = vertex_points.collect();
+        positions.extend(edge_points);
+        let colors = self.split_colors(&edges);
+
+        let (tex_coords, edge_tex_coords) = self.split_tex_coords();
+        let mut faces = Vec::new();
+        let mut parents = Vec::new();
+        for (f, face) in self.faces.iter().enumerate() {
+            // One triangle at every corner and one in the middle
+            let has_tex_coords = face.iter().all(|(_, t)| t.is_some());
+            let corner = |i: usize| (face[i].0, face[i].1.filter(|_| has_tex_coords));
+            let mid = |i: usize| {
+                let (a, b) = (face[i], face[(i + 1) % 3]);
+                let t = match (a.1, b.1) {
+                    (Some(ta), Some(tb)) if has_tex_coords => Some(edge_tex_coords[&edge(ta, tb)]),
+                    _ => None,
+                };
+                (edge_index[&edge(a.0, b.0)], t)
+            };
+            faces.push(vec![corner(0), mid(0), mid(2)]);
+            faces.push(vec![corner(1), mid(1), mid(0)]);
+            faces.push(vec![corner(2), mid(2), mid(1)]);
+            faces.push(vec![mid(0), mid(1), mid(2)]);
+            parents.extend([self.parents[f]; 4]);
+        }
+        let sharp_edges = self
+            .sharp_edges
+            .iter()
+            .flat_map(|e| {
+                let mid = edge_index[e];
+                [edge(e.0, mid), edge(mid, e.1)]
+            })
+            .collect();
+        ControlMesh {
+            positions,
+            tex_coords,
+            colors,
+            faces,
+            parents,
+            sharp_edges,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3, b: Vec3) {
+        assert!(
+            (a - b).length() < 1e-9,
+            "({}, {}, {}) != ({}, {}, {})",
+            a.x,
+            a.y,
+            a.z,
+            b.x,
+            b.y,
+            b.z
+        );
+    }
+
+    fn mesh(positions: Vec<Vec3>, faces: &[&[usize]]) -> ControlMesh {
+        let faces = faces
+            .iter()
+            .map(|face| face.iter().map(|v| (*v, None)).collect())
+            .collect();
+        ControlMesh::new(positions, Vec::new(), None, faces)
+    }
+
+    fn cube() -> ControlMesh {
+        // Corners at ±1, the bits of the index are the signs of x, y and z, the faces point outwards
+        let positions = (0..8)
+            .map(|i| {
+                let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+                Vec3::new(sign(1), sign(2), sign(4))
+            })
+            .collect();
+        let faces: [&[usize]; 6] = [
+            &[0, 4, 6, 2],
+            &[1, 3, 7, 5],
+            &[0, 1, 5, 4],
+            &[2, 6, 7, 3],
+            &[0, 2, 3, 1],
+            &[4, 5, 7, 6],
+        ];
+        mesh(positions, &faces)
+    }
+
+    fn tetrahedron() -> ControlMesh {
+        let positions = vec![
+            Vec3::new(1.0, 1.0, 1.0),
+            Vec3::new(1.0, -1.0, -1.0),
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(-1.0, -1.0, 1.0),
+        ];
+        mesh(positions, &[&[0, 1, 2], &[0, 3, 1], &[0, 2, 3], &[1, 3, 2]])
+    }
+
+    #[test]
+    fn catmull_clark_counts() {
+        // A new vertex for every vertex, edge and face, and a quad for every corner
+        let mut cube = cube();
+        cube.colors = Some(cube.positions.clone());
+        let once = cube.clone().subdivide(1);
+        assert_eq!(once.positions.len(), 8 + 12 + 6);
+        assert_eq!(once.faces.len(), 24);
+        assert!(once.faces.iter().all(|face| face.len() == 4));
+        assert_eq!(once.parents[..4], [0; 4]);
+        assert_eq!(once.parents[20..], [5; 4]);
+        // Colors are interpolated linearly, the last point is in the middle of the last face
+        let colors = once.colors.unwrap();
+        assert_eq!(colors.len(), once.positions.len());
+        assert_close(colors[25], Vec3::new(0.0, 0.0, 1.0));
+        let twice = cube.subdivide(2);
+        assert_eq!(twice.positions.len(), 26 + 48 + 24);
+        assert_eq!(twice.faces.len(), 96);
+    }
+
+    #[test]
+    fn loop_counts() {
+        // A new vertex for every vertex and edge, and four triangles for every triangle
+        let once = tetrahedron().subdivide(1);
+        assert_eq!(once.positions.len(), 4 + 6);
+        assert_eq!(once.faces.len(), 16);
+        assert!(once.faces.iter().all(|face| face.len() == 3));
+        let twice = tetrahedron().subdivide(2);
+        assert_eq!(twice.positions.len(), 10 + 24);
+        assert_eq!(twice.faces.len(), 64);
+    }
+
+    #[test]
+    fn crease_rules() {
+        // Smooth corners of a cube are pulled in, from the average of the face points and the edge midpoints
+        let smooth = cube().subdivide(1);
+        assert_close(smooth.positions[7], Vec3::new(5.0, 5.0, 5.0) / 9.0);
+        // With all edges sharp the corners stay and the edges are split in the middle
+        let mut sharp = cube();
+        sharp.mark_creases(45f64.to_radians());
+        assert_eq!(sharp.sharp_edges.len(), 12);
+        let sharp = sharp.subdivide(1);
+        assert_close(sharp.positions[7], Vec3::new(1.0, 1.0, 1.0));
+        assert_close(sharp.positions[8], Vec3::new(0.0, -1.0, -1.0));
+        assert_eq!(sharp.sharp_edges.len(), 24);
+        // Corners on two sharp edges move along the crease
+        let mut top = cube();
+        top.mark_sharp_edges(&[(2, 6), (6, 7), (7, 3), (3, 2), (0, 7)]);
+        assert_eq!(top.sharp_edges.len(), 4);
+        let top = top.subdivide(1);
+        assert_close(top.positions[7], Vec3::new(0.75, 1.0, 0.75));
+        // The corners of a tetrahedron are sharper than the crease angle, Loop keeps them as well
+        let mut tetrahedron = tetrahedron();
+        tetrahedron.mark_creases(60f64.to_radians());
+        let subdivided = tetrahedron.subdivide(1);
+        assert_close(subdivided.positions[0], Vec3::new(1.0, 1.0, 1.0));
+        assert_close(
+            subdivided.positions[4],
+            (subdivided.positions[0] + subdivided.positions[1]) * 0.5,
+        );
+    }
+
+    #[test]
+    fn vertex_normals() {
+        // Smooth corners share a normal, sharp edges split them
+        let (normals, corners) = cube().vertex_normals();
+        assert_eq!(normals.len(), 8);
+        assert_close(
+            normals[corners[5][2]],
+            Vec3::new(1.0, 1.0, 1.0).unit_vector(),
+        );
+        let mut sharp = cube();
+        sharp.mark_sharp_edges(&[(2, 6), (6, 7), (7, 3), (3, 2)]);
+        let (normals, corners) = sharp.vertex_normals();
+        // The top corners have one normal for the top face and one for the sides
+        assert_eq!(normals.len(), 12);
+        assert_close(normals[corners[3][2]], Vec3::new(0.0, 1.0, 0.0));
+        assert_close(
+            normals[corners[5][2]],
+            Vec3::new(1.0, 0.0, 1.0).unit_vector(),
+        );
+    }
+}
@@ -0,0 +1,10 @@
+use crate::hittable::Hit;
+use crate::vec3::Vec3;
+
+pub trait Texture {
+    fn value(&self, hit: &Hit) -> Vec3;
+    fn scalar(&self, hit: &Hit) -> f64 {
+        // For textures that drive a single parameter, e.g. roughness. Grayscale textures have the same value in each channel
+        self.value(hit).x
+    }
+}
@@ -0,0 +1,437 @@
+use crate::hittable::Hit;
+use crate::perlin::Perlin;
+use crate::texture::Texture;
+use crate::vec3::Vec3;
+use std::fs::{self, File};
+use std::path::Path;
+
+pub struct ConstantTexture {
+    pub color: Vec3,
+}
+impl ConstantTexture {
+    pub fn new(color: Vec3) -> ConstantTexture {
+        ConstantTexture { color }
+    }
+    pub fn gray(value: f64) -> ConstantTexture {
+        ConstantTexture {
+            color: Vec3::new(value, value, value),
+        }
+    }
+}
+impl Texture for ConstantTexture {
+    fn value(&self, _hit: &Hit) -> Vec3 {
+        self.color
+    }
+}
+
+// Colors of the vertices of meshes that have them, e.g. from .ply files
+#[allow(dead_code)]
+pub struct VertexColorTexture {
+    pub fallback: Vec3, // Used for hits without a color
+}
+impl Texture for VertexColorTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        hit.color.unwrap_or(self.fallback)
+    }
+}
+
+#[allow(dead_code)]
+pub struct CheckerTexture {
+    pub even: Box<dyn Texture>,
+    pub odd: Box<dyn Texture>,
+    pub scale: f64, // Number of squares along each texture coordinate
+}
+impl Texture for CheckerTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let (u, v) = hit.uv;
+        if ((u * self.scale).floor() + (v * self.scale).floor()) as i64 % 2 == 0 {
+            self.even.value(hit)
+        } else {
+            self.odd.value(hit)
+        }
+    }
+}
+
+pub struct NoiseTexture {
+    pub perlin: Perlin,
+    pub scale: f64,
+    pub octaves: i32,
+}
+impl NoiseTexture {
+    #[allow(dead_code)]
+    pub fn new(scale: f64, octaves: i32) -> NoiseTexture {
+        NoiseTexture {
+            perlin: Perlin::new(0),
+            scale,
+            octaves,
+        }
+    }
+}
+impl Texture for NoiseTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        // Solid texture, so it uses the position instead of the texture coordinates
+        let v =
+            (0.5 * (1.0 + self.perlin.fbm(&(hit.p * self.scale), self.octaves))).clamp(0.0, 1.0);
+        Vec3::new(v, v, v)
+    }
+}
+
+pub struct MarbleTexture {
+    pub perlin: Perlin,
+    pub color: Vec3,
+    pub scale: f64,
+    pub octaves: i32,
+}
+impl MarbleTexture {
+    #[allow(dead_code)]
+    pub fn new(color: Vec3, scale: f64) -> MarbleTexture {
+        MarbleTexture {
+            perlin: Perlin::new(0),
+            color,
+            scale,
+            octaves: 7,
+        }
+    }
+}
+impl Texture for MarbleTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        // Stripes along the z-axis that get distorted by turbulence
+        let p = hit.p * self.scale;
+        self.color * (0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(&p, self.octaves)).sin()))
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+impl WrapMode {
+    fn wrap(&self, i: i64, size: usize) -> usize {
+        let n = size as i64;
+        (match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let m = i.rem_euclid(2 * n);
+                if m >= n {
+                    2 * n - 1 - m
+                } else {
+                    m
+                }
+            }
+        }) as usize
+    }
+}
+
+// Decoded image file, before it is turned into a texture
+struct ImageData {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3>,
+    alpha: Option<Vec<f64>>,
+}
+
+pub struct ImageTexture {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Vec3>, // Row by row, starting at the top
+    pub wrap: WrapMode,
+    pub gamma: f64, // Set to 1.0 for textures that don't hold colors, e.g. roughness maps
+}
+impl ImageTexture {
+    pub fn from_file(path: &Path) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        Ok(ImageTexture::from_image(ImageTexture::read(path)?))
+    }
+
+    pub fn from_png_data(data: &[u8]) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        // For images that are embedded in other files
+        Ok(ImageTexture::from_image(ImageTexture::read_png(data)?))
+    }
+
+    pub fn alpha_from_file(path: &Path) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        Ok(ImageTexture::alpha_from_image(ImageTexture::read(path)?))
+    }
+
+    pub fn alpha_from_png_data(data: &[u8]) -> Result<ImageTexture, Box<dyn std::error::Error>> {
+        Ok(ImageTexture::alpha_from_image(ImageTexture::read_png(
+            data,
+        )?))
+    }
+
+    fn from_image(image: ImageData) -> ImageTexture {
+        ImageTexture {
+            width: image.width,
+            height: image.height,
+            pixels: image.pixels,
+            wrap: WrapMode::Repeat,
+            // The image gets written with a gamma of 2, so read it back the same way
+            gamma: 2.0,
+        }
+    }
+
+    fn alpha_from_image(image: ImageData) -> ImageTexture {
+        // Grayscale texture of the alpha channel, or of the brightness for images without one
+        let pixels = match image.alpha {
+            Some(alpha) => alpha.iter().map(|a| Vec3::new(*a, *a, *a)).collect(),
+            None => image
+                .pixels
+                .iter()
+                .map(|c| {
+                    let v = (c.x + c.y + c.z) / 3.0;
+                    Vec3::new(v, v, v)
+                })
+                .collect(),
+        };
+        ImageTexture {
+            width: image.width,
+            height: image.height,
+            pixels,
+            wrap: WrapMode::Repeat,
+            gamma: 1.0,
+        }
+    }
+
+    fn read(path: &Path) -> Result<ImageData, Box<dyn std::error::Error>> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => ImageTexture::read_png(File::open(path)?),
+            Some("ppm") => ImageTexture::read_ppm(path),
+            _ => Err(format!("Unsupported image format: {}", path.display()).into()),
+        }
+    }
+
+    fn read_png(data: impl std::io::Read) -> Result<ImageData, Box<dyn std::error::Error>> {
+        let mut decoder = png::Decoder::new(data);
+        // Always get 8 bits per channel without palettes
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let channels = info.color_type.samples();
+        let samples: Vec<Vec<f64>> = buf[..info.buffer_size()]
+            .chunks(channels)
+            .map(|c| c.iter().map(|v| *v as f64 / 255.0).collect())
+            .collect();
+        let pixels = samples
+            .iter()
+            .map(|c| {
+                if channels < 3 {
+                    // Grayscale, possibly with alpha
+                    Vec3::new(c[0], c[0], c[0])
+                } else {
+                    Vec3::new(c[0], c[1], c[2])
+                }
+            })
+            .collect();
+        // The alpha channel is always the last one
+        let alpha = if channels == 2 || channels == 4 {
+            Some(samples.iter().map(|c| c[channels - 1]).collect())
+        } else {
+            None
+        };
+        Ok(ImageData {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+            alpha,
+        })
+    }
+
+    fn read_ppm(path: &Path) -> Result<ImageData, Box<dyn std::error::Error>> {
+        // Supports both the plain (P3) and the binary (P6) format
+        let data = fs::read(path)?;
+        let mut pos = 0;
+        let mut next_token = || -> Result<String, Box<dyn std::error::Error>> {
+            loop {
+                match data.get(pos) {
+                    None => return Err("Unexpected end of PPM file".into()),
+                    Some(b'#') => {
+                        // Comment until the end of the line
+                        while data.get(pos).is_some_and(|c| *c != b'\n') {
+                            pos += 1;
+                        }
+                    }
+                    Some(c) if c.is_ascii_whitespace() => pos += 1,
+                    Some(_) => break,
+                }
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            Ok(String::from_utf8_lossy(&data[start..pos]).to_string())
+        };
+        let magic = next_token()?;
+        let width: usize = next_token()?.parse()?;
+        let height: usize = next_token()?.parse()?;
+        let max_val: u32 = next_token()?.parse()?;
+        if width == 0 || height == 0 {
+            return Err("PPM image without pixels".into());
+        }
+        if max_val == 0 || max_val > 65535 {
+            return Err(format!("Invalid maximum value in PPM file: {}", max_val).into());
+        }
+        let samples = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or("PPM image is too large")?;
+        let max_val = max_val as f64;
+        let values: Vec<f64> = match magic.as_str() {
+            "P3" => (0..samples)
+                .map(|_| Ok(next_token()?.parse::<f64>()? / max_val))
+                .collect::<Result<_, Box<dyn std::error::Error>>>()?,
+            "P6" => {
+                // A single whitespace separates the header from the binary data
+                // Samples take two bytes, most significant first, if the maximum value doesn't fit in one
+                let sample_size = if max_val > 255.0 { 2 } else { 1 };
+                let start = pos + 1;
+                let bytes = samples
+                    .checked_mul(sample_size)
+                    .and_then(|n| data.get(start..start.checked_add(n)?))
+                    .ok_or("Unexpected end of PPM file")?;
+                bytes
+                    .chunks(sample_size)
+                    .map(|b| b.iter().fold(0.0, |acc, v| acc * 256.0 + *v as f64) / max_val)
+                    .collect()
+            }
+            _ => return Err(format!("Unsupported PPM format: {}", magic).into()),
+        };
+        let pixels = values
+            .chunks(3)
+            .map(|c| Vec3::new(c[0], c[1], c[2]))
+            .collect();
+        Ok(ImageData {
+            width,
+            height,
+            pixels,
+            alpha: None,
+        })
+    }
+
+    pub fn grayscale(&self) -> Vec<f64> {
+        // Brightness of the pixels row by row from the top, without the gamma
+        self.pixels
+            .iter()
+            .map(|c| (c.x + c.y + c.z) / 3.0)
+            .collect()
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        self.pixels[self.wrap.wrap(y, self.height) * self.width + self.wrap.wrap(x, self.width)]
+    }
+}
+impl Texture for ImageTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        // Bilinear filtering between the four closest texels, v = 0 is the bottom of the image
+        let (u, v) = hit.uv;
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let c = self.texel(x0, y0) * ((1.0 - fx) * (1.0 - fy))
+            + self.texel(x0 + 1, y0) * (fx * (1.0 - fy))
+            + self.texel(x0, y0 + 1) * ((1.0 - fx) * fy)
+            + self.texel(x0 + 1, y0 + 1) * (fx * fy);
+        Vec3::new(
+            c.x.powf(self.gamma),
+            c.y.powf(self.gamma),
+            c.z.powf(self.gamma),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn lookup(texture: &ImageTexture, u: f64, v: f64) -> Vec3 {
+        let ray = Ray {
+            origin: Vec3::z(),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        texture.value(&Hit::new(Vec3::z(), Vec3::new(0.0, 0.0, 1.0), 1.0, &ray).with_uv(u, v))
+    }
+
+    fn read_ppm(name: &str, contents: &[u8]) -> Result<ImageData, Box<dyn std::error::Error>> {
+        // Every test uses its own file names, since the tests run in parallel
+        let path = std::env::temp_dir().join(format!("ray_tracer_{}", name));
+        fs::write(&path, contents).unwrap();
+        ImageTexture::read_ppm(&path)
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let wrapped = |mode: WrapMode| (-4..8).map(|i| mode.wrap(i, 3)).collect::<Vec<_>>();
+        assert_eq!(
+            wrapped(WrapMode::Repeat),
+            [2, 0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 1]
+        );
+        assert_eq!(
+            wrapped(WrapMode::Clamp),
+            [0, 0, 0, 0, 0, 1, 2, 2, 2, 2, 2, 2]
+        );
+        assert_eq!(
+            wrapped(WrapMode::Mirror),
+            [2, 2, 1, 0, 0, 1, 2, 2, 1, 0, 0, 1]
+        );
+    }
+
+    #[test]
+    fn bilinear_lookup() {
+        // Two texels next to each other, black on the left and white on the right
+        let mut texture = ImageTexture {
+            width: 2,
+            height: 1,
+            pixels: vec![Vec3::z(), Vec3::new(1.0, 1.0, 1.0)],
+            wrap: WrapMode::Clamp,
+            gamma: 1.0,
+        };
+        // The centers of the texels are at a quarter and three quarters
+        assert!(lookup(&texture, 0.25, 0.5).x.abs() < 1e-12);
+        assert!((lookup(&texture, 0.75, 0.5).x - 1.0).abs() < 1e-12);
+        assert!((lookup(&texture, 0.5, 0.5).x - 0.5).abs() < 1e-12);
+        assert!((lookup(&texture, 0.375, 0.9).x - 0.25).abs() < 1e-12);
+        // Clamping keeps the edges, repeating blends them with the opposite edge
+        assert!(lookup(&texture, 0.0, 0.5).x.abs() < 1e-12);
+        texture.wrap = WrapMode::Repeat;
+        assert!((lookup(&texture, 0.0, 0.5).x - 0.5).abs() < 1e-12);
+        // The gamma is applied after filtering
+        texture.gamma = 2.0;
+        assert!((lookup(&texture, 0.5, 0.5).x - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ppm_formats() {
+        let plain = read_ppm("plain.ppm", b"P3\n# Comment\n2 1\n4\n0 1 2\n4 4 0\n").unwrap();
+        assert_eq!((plain.width, plain.height), (2, 1));
+        assert_eq!(plain.pixels[0].y, 0.25);
+        assert_eq!(plain.pixels[1].x, 1.0);
+
+        let binary = read_ppm("binary.ppm", b"P6 1 2 255\n\x00\x33\xff\xff\x00\x00").unwrap();
+        assert_eq!((binary.width, binary.height), (1, 2));
+        assert_eq!(binary.pixels[0].y, 0.2);
+        assert_eq!(binary.pixels[1].x, 1.0);
+
+        // Two bytes per sample, most significant first
+        let wide = read_ppm("wide.ppm", b"P6 1 1 1000\n\x01\xf4\x03\xe8\x00\x00").unwrap();
+        assert_eq!(wide.pixels[0].x, 0.5);
+        assert_eq!(wide.pixels[0].y, 1.0);
+    }
+
+    #[test]
+    fn malformed_ppm() {
+        let error = |name: &str, contents: &[u8]| match read_ppm(name, contents) {
+            Ok(_) => panic!("{} was read", name),
+            Err(e) => e.to_string(),
+        };
+        assert!(error("empty.ppm", b"P3 0 0 255\n").contains("without pixels"));
+        assert!(error("max_val.ppm", b"P3 1 1 0\n0 0 0\n").contains("maximum value"));
+        assert!(error("huge.ppm", b"P6 4294967296 4294967296 255\n").contains("too large"));
+        assert!(error("short.ppm", b"P6 2 2 255\n\x00\x00\x00").contains("end of PPM"));
+        assert!(error("wide_short.ppm", b"P6 1 1 1000\n\x00\x00\x00").contains("end of PPM"));
+        assert!(error("format.ppm", b"P5 1 1 255\n\x00").contains("Unsupported"));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::hittable::{hit_list, Hit, Hittable, ObjectContainer};
+use crate::hittable::{hit_list, Hit, Hittable, ObjectContainer, TraversalStats};
 use crate::object::Object;
 use crate::ray::Ray;
 use crate::util::{EPSILON, INFINITY};
@@ -6,30 +6,40 @@ use crate::vec3::Vec3;
 
 pub struct TDTree<'a> {
     root: Box<TDTreePart<'a>>,
+    unbounded: Vec<&'a Object>, // Objects with infinite bounds, like planes, can't be split up and are always tested
 }
 unsafe impl<'a> Sync for TDTree<'a> {}
 impl ObjectContainer for TDTree<'_> {
-    fn get_object_hit(&self, ray: &Ray) -> Option<(&Object, Hit)> {
+    fn get_object_hit_with_stats(
+        &self,
+        ray: &Ray,
+        stats: &mut TraversalStats,
+    ) -> Option<(&Object, Hit)> {
         fn _obj_hit<'a>(
             node: &'a TDTreePart<'a>,
             ray: &Ray,
             t_min: f64,
             t_max: f64,
+            stats: &mut TraversalStats,
         ) -> Option<(&'a Object, Hit)> {
             if t_min >= t_max {
                 return None;
             }
+            stats.nodes_visited += 1;
             match node {
-                TDTreePart::Leaf { children } => hit_list(children, ray, t_min.max(EPSILON), t_max),
+                TDTreePart::Leaf { children } => {
+                    stats.intersection_tests += children.len();
+                    hit_list(children, ray, t_min.max(EPSILON), t_max)
+                }
                 TDTreePart::Node {
                     axis,
                     h,
                     left,
                     right,
                 } => {
-                    let t = ray.intersect_axis_plane(&axis, *h);
+                    let t = ray.intersect_axis_plane(axis, *h);
                     let (first, second) = {
-                        if ray.direction.get_axis(&axis) >= 0.0 {
+                        if ray.direction.get_axis(axis) >= 0.0 {
                             (left, right)
                         } else {
                             (right, left)
@@ -37,19 +47,25 @@ impl ObjectContainer for TDTree<'_> {
                     };
                     if t > t_max {
                         // Plane intersection comes after the ray interval
-                        _obj_hit(first.as_ref(), ray, t_min, t_max)
+                        _obj_hit(first.as_ref(), ray, t_min, t_max, stats)
                     } else if t < t_min {
                         // Plane intersection comes before the ray interval
-                        _obj_hit(second.as_ref(), ray, t_min, t_max)
+                        _obj_hit(second.as_ref(), ray, t_min, t_max, stats)
                     } else {
                         // Plane intersection is in the ray interval
-                        _obj_hit(first.as_ref(), ray, t_min, t)
-                            .or_else(|| _obj_hit(second.as_ref(), ray, t, t_max))
+                        _obj_hit(first.as_ref(), ray, t_min, t, stats)
+                            .or_else(|| _obj_hit(second.as_ref(), ray, t, t_max, stats))
                     }
                 }
             }
         }
-        _obj_hit(self.root.as_ref(), ray, EPSILON, INFINITY)
+        let tree_hit = _obj_hit(self.root.as_ref(), ray, EPSILON, INFINITY, stats);
+        if self.unbounded.is_empty() {
+            return tree_hit;
+        }
+        stats.intersection_tests += self.unbounded.len();
+        let t_max = tree_hit.as_ref().map_or(INFINITY, |(_, hit)| hit.t);
+        hit_list(&self.unbounded, ray, EPSILON, t_max).or(tree_hit)
     }
 }
 pub enum TDTreePart<'a> {
@@ -69,9 +85,11 @@ pub enum Axis {
     Z,
 }
 
-pub fn build_tdtree<'a>(hittables: &'a Vec<Object>, max_depth: i32) -> TDTree<'a> {
+pub fn build_tdtree(hittables: &[Object], max_depth: i32) -> TDTree<'_> {
+    let (bounded, unbounded) = hittables.iter().partition(|h| h.get_bounds().is_finite());
     TDTree {
-        root: _build_tdtree(hittables.iter().collect(), max_depth),
+        root: _build_tdtree(bounded, max_depth),
+        unbounded,
     }
 }
 
@@ -124,7 +142,7 @@ fn _build_tdtree<'a>(hittables: Vec<&'a Object>, depth_remaining: i32) -> Box<TD
         _build_tdtree(hittables, depth_remaining - 1)
     } else {
         Box::new(TDTreePart::Node {
-            axis: axis,
+            axis,
             h: mid,
             left: _build_tdtree(left, depth_remaining - 1),
             right: _build_tdtree(right, depth_remaining - 1),
@@ -0,0 +1,108 @@
+use crate::hittable::BoundingBox;
+use crate::hittable::Hit;
+use crate::hittable::Hittable;
+use crate::hittable::Interval;
+use crate::microfacet::Frame;
+use crate::polynomial::solve_quartic;
+use crate::ray::Ray;
+use crate::util::PI;
+use crate::vec3::Vec3;
+
+// Ring around the axis through center, major_radius is the distance from the center to the middle of the tube
+pub struct Torus {
+    pub center: Vec3,
+    pub axis: Vec3,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let t = self
+            .roots(ray)
+            .into_iter()
+            .find(|t| *t >= t_min && *t <= t_max)?;
+        Some(self.hit_at(ray, t))
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        // The roots alternate between entering and leaving the tube, a ray that touches the surface gives a double root
+        // An odd number of roots means one got lost to rounding, the ray then grazes the surface and is skipped
+        let roots = self.roots(ray);
+        if !roots.len().is_multiple_of(2) {
+            return Vec::new();
+        }
+        roots
+            .chunks_exact(2)
+            .filter(|pair| pair[0] < pair[1])
+            .map(|pair| Interval {
+                enter: self.hit_at(ray, pair[0]),
+                exit: self.hit_at(ray, pair[1]),
+            })
+            .collect()
+    }
+
+    fn get_bounds(&self) -> BoundingBox {
+        // The ring extends by the major radius in the plane orthogonal to the axis, the tube adds the minor radius everywhere
+        let a = self.axis.unit_vector();
+        let extent = |a: f64| self.major_radius * (1.0 - a * a).max(0.0).sqrt() + self.minor_radius;
+        let (ex, ey, ez) = (extent(a.x), extent(a.y), extent(a.z));
+        BoundingBox::new(
+            self.center.x - ex,
+            self.center.x + ex,
+            self.center.y - ey,
+            self.center.y + ey,
+            self.center.z - ez,
+            self.center.z + ez,
+        )
+    }
+}
+
+impl Torus {
+    #[allow(dead_code)]
+    pub fn new(center: Vec3, axis: Vec3, major_radius: f64, minor_radius: f64) -> Torus {
+        Torus {
+            center,
+            axis,
+            major_radius,
+            minor_radius,
+        }
+    }
+
+    fn roots(&self, ray: &Ray) -> Vec<f64> {
+        // Intersect in the local frame where the axis is z with a unit direction, which keeps the quartic well behaved
+        // The surface is (x² + y² + z² + R² - r²)² = 4R² (x² + y²)
+        let frame = Frame::new(self.axis.unit_vector());
+        let length = ray.direction.length();
+        let o = frame.to_local(ray.origin - self.center);
+        let d = frame.to_local(ray.direction) / length;
+        let r2_major = self.major_radius * self.major_radius;
+        let r2_minor = self.minor_radius * self.minor_radius;
+        let od = o.dot(&d);
+        let e = o.length_squared() - r2_major - r2_minor;
+        solve_quartic(
+            4.0 * od,
+            2.0 * e + 4.0 * od * od + 4.0 * r2_major * d.z * d.z,
+            4.0 * od * e + 8.0 * r2_major * o.z * d.z,
+            e * e - 4.0 * r2_major * (r2_minor - o.z * o.z),
+        )
+        .into_iter()
+        .map(|t| t / length)
+        .collect()
+    }
+
+    fn hit_at(&self, ray: &Ray, t: f64) -> Hit {
+        // u goes around the axis and v around the tube, starting at the outside
+        let frame = Frame::new(self.axis.unit_vector());
+        let p = frame.to_local(ray.at(t) - self.center);
+        let phi = p.y.atan2(p.x);
+        let rho = (p.x * p.x + p.y * p.y).sqrt();
+        let theta = p.z.atan2(rho - self.major_radius);
+        let tube_center = Vec3::new(phi.cos(), phi.sin(), 0.0) * self.major_radius;
+        let normal = frame.to_world((p - tube_center).unit_vector());
+        let wrap = |angle: f64| if angle < 0.0 { angle + 2.0 * PI } else { angle } / (2.0 * PI);
+        let tangent = frame.to_world(Vec3::new(-phi.sin(), phi.cos(), 0.0));
+        Hit::new(ray.at(t), normal, t, ray)
+            .with_uv(wrap(phi), wrap(theta))
+            .with_tangent(tangent, 1.0)
+    }
+}
@@ -2,46 +2,207 @@ use crate::hittable::BoundingBox;
 use crate::hittable::Hit;
 use crate::hittable::Hittable;
 use crate::ray::Ray;
-use crate::util::EPSILON;
 use crate::vec3::Vec3;
 pub struct Triangle {
     pub p1: Vec3,
     pub p2: Vec3,
     pub p3: Vec3,
+    pub uvs: Option<[(f64, f64); 3]>, // Texture coordinates of the vertices
+    pub tangents: Option<[(Vec3, f64); 3]>, // Tangents and their handedness at the vertices, see Hit::tangent
+    pub normals: Option<[Vec3; 3]>,         // Normals at the vertices for smooth shading
+    pub colors: Option<[Vec3; 3]>, // Colors at the vertices, see textures::VertexColorTexture
 }
-impl Hittable for Triangle {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
-        let p12 = self.p2 - self.p1;
-        let p23 = self.p3 - self.p2;
-        let p31 = self.p1 - self.p3;
-        let normal = p12.cross(&p23).unit_vector();
-        if ray.direction.dot(&normal).abs() < EPSILON {
-            // Ray and normal of plane are othogonal => Ray and plane are parallel
-            return Option::None;
-        }
-        // There is an intersection of the plane and the ray
-
-        // Get distance from ray-origin to plane
-        let dist: f64 = normal.dot(&ray.origin) - normal.dot(&self.p1);
-        // Get intersection point of plane and ray
-        let t = -dist / normal.dot(&ray.direction);
-        if t < t_min || t > t_max {
-            return Option::None;
+
+pub fn uv_derivatives(
+    e1: Vec3,
+    e2: Vec3,
+    duv1: (f64, f64),
+    duv2: (f64, f64),
+) -> Option<(Vec3, Vec3)> {
+    // Solves for the directions in which u and v increase, given two edges and the change of texture coordinates along them
+    let r = duv1.0 * duv2.1 - duv2.0 * duv1.1;
+    if r.abs() < 1e-12 {
+        // Degenerate texture coordinates
+        return None;
+    }
+    Some((
+        (e1 * duv2.1 - e2 * duv1.1) / r,
+        (e2 * duv1.0 - e1 * duv2.0) / r,
+    ))
+}
+
+// The part of the watertight intersection that only depends on the ray, so it's done once for all triangles of a mesh
+// "Watertight Ray/Triangle Intersection", Woop et al. 2013
+pub struct WatertightRay {
+    origin: Vec3,
+    axes: [usize; 3], // The ray goes mostly along the last axis, the first two span the plane the triangles are projected onto
+    shear: Vec3,      // Turns the ray direction into (0, 0, 1)
+}
+impl WatertightRay {
+    pub fn new(ray: &Ray) -> WatertightRay {
+        let d = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let kz = (0..3)
+            .max_by(|i, j| d[*i].abs().total_cmp(&d[*j].abs()))
+            .unwrap();
+        let (mut kx, mut ky) = ((kz + 1) % 3, (kz + 2) % 3);
+        if d[kz] < 0.0 {
+            // Keeps the winding of the triangles the same
+            std::mem::swap(&mut kx, &mut ky);
         }
-        let intersection = ray.origin + ray.direction * t;
-        // Check that intersection point is in triangle
-        let pi1 = intersection - self.p1;
-        let pi2 = intersection - self.p2;
-        let pi3 = intersection - self.p3;
-
-        if (p23 - p12 * (p12.dot(&p23) / p12.length_squared())).dot(&pi1) > 0.0
-            && (p31 - p23 * p23.dot(&p31) / p23.length_squared()).dot(&pi2) > 0.0
-            && (p12 - p31 * p31.dot(&p12) / p31.length_squared()).dot(&pi3) > 0.0
-        {
-            Option::from(Hit::new(intersection, normal, t, ray))
+        WatertightRay {
+            origin: ray.origin,
+            axes: [kx, ky, kz],
+            shear: Vec3::new(d[kx] / d[kz], d[ky] / d[kz], 1.0 / d[kz]),
+        }
+    }
+
+    fn transform(&self, p: Vec3) -> Vec3 {
+        // Into the space where the ray starts at the origin and goes along z, t is the z coordinate there
+        let p = p - self.origin;
+        let p = [p.x, p.y, p.z];
+        let [kx, ky, kz] = self.axes;
+        Vec3::new(
+            p[kx] - self.shear.x * p[kz],
+            p[ky] - self.shear.y * p[kz],
+            self.shear.z * p[kz],
+        )
+    }
+}
+
+// Properties of the flat triangle that don't depend on where it's hit, meshes compute them once for every face
+#[derive(Clone, Copy)]
+pub struct FaceGeometry {
+    pub normal: Vec3,
+    pub tangent: Option<(Vec3, f64)>, // Only for triangles without tangents at the vertices
+}
+
+impl Triangle {
+    pub fn face_geometry(&self) -> FaceGeometry {
+        FaceGeometry {
+            normal: (self.p2 - self.p1)
+                .cross(&(self.p3 - self.p2))
+                .unit_vector(),
+            tangent: self
+                .tangents
+                .is_none()
+                .then(|| self.face_tangent())
+                .flatten(),
+        }
+    }
+
+    pub fn face_tangent(&self) -> Option<(Vec3, f64)> {
+        // Tangent of the flat triangle, used when there are no tangents for the vertices
+        let [uv1, uv2, uv3] = self.uvs.unwrap_or([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        let e1 = self.p2 - self.p1;
+        let e2 = self.p3 - self.p1;
+        let (t, b) = uv_derivatives(
+            e1,
+            e2,
+            (uv2.0 - uv1.0, uv2.1 - uv1.1),
+            (uv3.0 - uv1.0, uv3.1 - uv1.1),
+        )?;
+        let handedness = if e1.cross(&e2).cross(&t).dot(&b) < 0.0 {
+            -1.0
         } else {
-            Option::None
+            1.0
+        };
+        Some((t.unit_vector(), handedness))
+    }
+
+    pub fn intersect(
+        points: [Vec3; 3],
+        ray: &WatertightRay,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<(f64, Vec3)> {
+        // Distance along the ray and barycentric weights of the vertices where the ray hits the triangle
+        // Rays that hit an edge or a vertex hit all triangles that share it, so they can't slip through a mesh
+        let [a, b, c] = points.map(|p| ray.transform(p));
+        // Twice the signed areas of the triangles between the ray and each edge, opposite to each vertex
+        let u = c.x * b.y - c.y * b.x;
+        let v = a.x * c.y - a.y * c.x;
+        let w = b.x * a.y - b.y * a.x;
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+        let det = u + v + w;
+        if det == 0.0 {
+            // The ray goes along the plane of the triangle
+            return None;
         }
+        let t = (u * a.z + v * b.z + w * c.z) / det;
+        // Also rejects rays with an invalid direction, whose t is NaN
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+        Some((t, Vec3::new(u / det, v / det, w / det)))
+    }
+
+    pub fn hit_at(&self, ray: &Ray, t: f64, barycentric: Vec3) -> Hit {
+        self.hit_with_geometry(ray, t, barycentric, &self.face_geometry())
+    }
+
+    pub fn hit_with_geometry(
+        &self,
+        ray: &Ray,
+        t: f64,
+        barycentric: Vec3,
+        geometry: &FaceGeometry,
+    ) -> Hit {
+        // Interpolates the attributes of the vertices at a point found by Triangle::intersect
+        let normal = geometry.normal;
+        let intersection = ray.origin + ray.direction * t;
+        let (w1, w2, w3) = (barycentric.x, barycentric.y, barycentric.z);
+        // Without texture coordinates the triangle is mapped onto the lower half of the texture
+        let (u, v) = match self.uvs {
+            Some([uv1, uv2, uv3]) => (
+                w1 * uv1.0 + w2 * uv2.0 + w3 * uv3.0,
+                w1 * uv1.1 + w2 * uv2.1 + w3 * uv3.1,
+            ),
+            None => (w2, w3),
+        };
+        // Shading normals are interpolated, but stay on the same side as the face
+        let shading_normal = match self.normals {
+            Some([n1, n2, n3]) => {
+                let n = (n1 * w1 + n2 * w2 + n3 * w3).unit_vector();
+                if n.dot(&normal) < 0.0 {
+                    -n
+                } else {
+                    n
+                }
+            }
+            None => normal,
+        };
+        let hit = Hit::new(intersection, shading_normal, t, ray)
+            .with_barycentric(barycentric)
+            .with_uv(u, v);
+        let hit = match self.colors {
+            Some([c1, c2, c3]) => hit.with_color(c1 * w1 + c2 * w2 + c3 * w3),
+            None => hit,
+        };
+
+        let tangent = match self.tangents {
+            // The handedness is the same for all vertices unless the texture is mirrored inside the triangle
+            Some([(t1, s1), (t2, _), (t3, _)]) => {
+                Some(((t1 * w1 + t2 * w2 + t3 * w3).unit_vector(), s1))
+            }
+            None => geometry.tangent,
+        };
+        match tangent {
+            Some((tangent, handedness)) => hit.with_tangent(tangent, handedness),
+            None => hit,
+        }
+    }
+}
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<Hit> {
+        let (t, barycentric) = Triangle::intersect(
+            [self.p1, self.p2, self.p3],
+            &WatertightRay::new(ray),
+            t_min,
+            t_max,
+        )?;
+        Some(self.hit_at(ray, t, barycentric))
     }
 
     fn get_bounds(&self) -> BoundingBox {
@@ -49,9 +210,194 @@ impl Hittable for Triangle {
             self.p1.x.min(self.p2.x).min(self.p3.x),
             self.p1.x.max(self.p2.x).max(self.p3.x),
             self.p1.y.min(self.p2.y).min(self.p3.y),
-            self.p1.y.max(self.p2.x).max(self.p3.y),
+            self.p1.y.max(self.p2.y).max(self.p3.y),
             self.p1.z.min(self.p2.z).min(self.p3.z),
             self.p1.z.max(self.p2.z).max(self.p3.z),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn intersect(points: [Vec3; 3], origin: Vec3, direction: Vec3) -> Option<(f64, Vec3)> {
+        let ray = Ray { origin, direction };
+        Triangle::intersect(points, &WatertightRay::new(&ray), 0.0, f64::INFINITY)
+    }
+
+    fn hits_any(triangles: &[[Vec3; 3]], origin: Vec3, direction: Vec3) -> bool {
+        triangles
+            .iter()
+            .any(|t| intersect(*t, origin, direction).is_some())
+    }
+
+    #[test]
+    fn barycentric_weights() {
+        let points = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let (t, w) =
+            intersect(points, Vec3::new(0.2, 0.3, 2.0), Vec3::new(0.0, 0.0, -0.5)).unwrap();
+        assert!((t - 4.0).abs() < 1e-12);
+        assert!((w.x - 0.5).abs() < 1e-12);
+        assert!((w.y - 0.2).abs() < 1e-12);
+        assert!((w.z - 0.3).abs() < 1e-12);
+        // The same from behind
+        let (_, w) =
+            intersect(points, Vec3::new(0.2, 0.3, -1.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+        assert!((w.x - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn misses() {
+        let points = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        // Outside, parallel to the plane and behind the origin of the ray
+        assert!(intersect(points, Vec3::new(0.6, 0.6, 1.0), Vec3::new(0.0, 0.0, -1.0)).is_none());
+        assert!(intersect(points, Vec3::new(-1.0, 0.2, 0.0), Vec3::new(1.0, 0.0, 0.0)).is_none());
+        assert!(intersect(points, Vec3::new(0.2, 0.2, 1.0), Vec3::new(0.0, 0.0, 1.0)).is_none());
+        let ray = Ray {
+            origin: Vec3::new(0.2, 0.2, 1.0),
+            direction: Vec3::new(0.0, 0.0, -1.0),
+        };
+        assert!(Triangle::intersect(points, &WatertightRay::new(&ray), 0.0, 0.5).is_none());
+    }
+
+    #[test]
+    fn no_leaks_along_shared_edge() {
+        // A skewed quad split along its diagonal, rays through points on the diagonal from many directions
+        let (a, b, c, d) = (
+            Vec3::new(-0.3, -0.7, 0.1),
+            Vec3::new(0.9, -0.4, 0.3),
+            Vec3::new(0.7, 0.8, -0.2),
+            Vec3::new(-0.6, 0.5, 0.0),
+        );
+        let triangles = [[a, b, c], [a, c, d]];
+        // The ends of the diagonal are on the outline of the quad, so rays through them may miss
+        for i in 1..100 {
+            let target = a + (c - a) * (i as f64 / 100.0);
+            for j in 0..50 {
+                let angle = j as f64 * 0.37;
+                let direction =
+                    Vec3::new(angle.cos() * 0.3, angle.sin() * 0.3, -1.0) * (1.0 + j as f64 * 0.1);
+                let origin = target - direction * 3.7;
+                assert!(
+                    hits_any(&triangles, origin, direction),
+                    "Ray through {:?} leaked",
+                    (target.x, target.y, target.z)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_leaks_through_shared_vertex() {
+        // A fan of triangles around a vertex that isn't at a nice position
+        let center = Vec3::new(0.123, -0.456, 0.789);
+        let corners: Vec<Vec3> = (0..7)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::TAU / 7.0;
+                center + Vec3::new(angle.cos(), angle.sin(), 0.1 * angle.sin())
+            })
+            .collect();
+        let triangles: Vec<[Vec3; 3]> = (0..7)
+            .map(|i| [center, corners[i], corners[(i + 1) % 7]])
+            .collect();
+        for j in 0..200 {
+            let angle = j as f64 * 0.71;
+            let direction = Vec3::new(angle.cos() * 0.5, angle.sin() * 0.5, -1.0 - j as f64 * 0.01);
+            let origin = center - direction * 1.9;
+            assert!(hits_any(&triangles, origin, direction));
+        }
+    }
+
+    #[test]
+    fn no_leaks_out_of_closed_mesh() {
+        // Rays from inside an octahedron, aimed exactly at its vertices and edges among others, always hit it
+        let v = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(-1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(0.0, 0.0, -1.0),
+        ];
+        let faces = [
+            [0, 2, 4],
+            [2, 1, 4],
+            [1, 3, 4],
+            [3, 0, 4],
+            [2, 0, 5],
+            [1, 2, 5],
+            [3, 1, 5],
+            [0, 3, 5],
+        ];
+        let triangles: Vec<[Vec3; 3]> = faces.iter().map(|f| f.map(|i| v[i])).collect();
+        let mut directions: Vec<Vec3> = v.to_vec();
+        for (i, a) in v.iter().enumerate() {
+            for b in &v[i + 1..] {
+                if (*a + *b).near_zero() {
+                    continue;
+                }
+                // Through the middle and through other points of the edge
+                for k in 1..10 {
+                    let s = k as f64 / 10.0;
+                    directions.push(*a * s + *b * (1.0 - s));
+                }
+            }
+        }
+        for origin in [
+            Vec3::z(),
+            Vec3::new(0.1, -0.2, 0.05),
+            Vec3::new(-0.3, 0.3, 0.2),
+        ] {
+            for direction in &directions {
+                // Aim at the same points of the surface from each origin
+                let direction = *direction - origin;
+                assert!(
+                    hits_any(&triangles, origin, direction),
+                    "Ray from {:?} along {:?} leaked",
+                    (origin.x, origin.y, origin.z),
+                    (direction.x, direction.y, direction.z)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_direction() {
+        // Rays that went wrong earlier miss instead of panicking
+        let points = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let origin = Vec3::new(0.2, 0.2, 1.0);
+        assert!(intersect(points, origin, Vec3::new(f64::NAN, 0.0, -1.0)).is_none());
+        assert!(intersect(points, origin, Vec3::new(f64::NAN, f64::NAN, f64::NAN)).is_none());
+    }
+
+    #[test]
+    fn bounds() {
+        // The highest y is on the second vertex, whose x is lower than the y of the others
+        let triangle = Triangle {
+            p1: Vec3::new(1.0, 0.0, -1.0),
+            p2: Vec3::new(-2.0, 3.0, 0.0),
+            p3: Vec3::new(0.0, -1.0, 2.0),
+            uvs: None,
+            tangents: None,
+            normals: None,
+            colors: None,
+        };
+        let bounds = triangle.get_bounds();
+        assert_eq!((bounds.x_low, bounds.x_high), (-2.0, 1.0));
+        assert_eq!((bounds.y_low, bounds.y_high), (-1.0, 3.0));
+        assert_eq!((bounds.z_low, bounds.z_high), (-1.0, 2.0));
+    }
+}
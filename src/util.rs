@@ -1,9 +1,10 @@
 use crate::vec3::Vec3;
 
-pub const PI: f64 = 3.1415926535897932385;
+pub const PI: f64 = std::f64::consts::PI;
 pub const EPSILON: f64 = 0.001;
 pub const INFINITY: f64 = 999999.0;
 
+#[allow(dead_code)]
 pub fn normal_to_color(normal: &Vec3) -> Vec3 {
     Vec3::new(1.0 + normal.x, 1.0 + normal.y, 1.0 + normal.z) * 0.5
 }
@@ -11,3 +12,29 @@ pub fn normal_to_color(normal: &Vec3) -> Vec3 {
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     degrees * PI / 180.0
 }
+
+#[allow(dead_code)]
+pub fn heatmap_color(value: f64) -> Vec3 {
+    // Maps a value in [0, 1] onto a blue -> cyan -> green -> yellow -> red ramp
+    let v = value.clamp(0.0, 1.0) * 4.0;
+    match v as i32 {
+        0 => Vec3::new(0.0, v, 1.0),
+        1 => Vec3::new(0.0, 1.0, 2.0 - v),
+        2 => Vec3::new(v - 2.0, 1.0, 0.0),
+        _ => Vec3::new(1.0, (4.0 - v).max(0.0), 0.0),
+    }
+}
+
+#[allow(dead_code)]
+pub fn id_to_color(id: usize) -> Vec3 {
+    // Scrambles the bits of the id so neighbouring ids get very different colors
+    let mut h = id as u64;
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^= h >> 31;
+    Vec3::new(
+        (h & 0xff) as f64 / 255.0,
+        ((h >> 8) & 0xff) as f64 / 255.0,
+        ((h >> 16) & 0xff) as f64 / 255.0,
+    )
+}
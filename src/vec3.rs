@@ -79,7 +79,7 @@ impl Vec3 {
         };
         let r_out_perp = (self + normal * cos_theta) * etai_over_etat;
         let r_out_parallel = normal * -(1.0 - r_out_perp.length_squared()).abs().sqrt();
-        return r_out_perp + r_out_parallel;
+        r_out_perp + r_out_parallel
     }
 
     pub fn clamp(self, clamp: Vec3) -> Vec3 {
@@ -90,10 +90,26 @@ impl Vec3 {
         )
     }
 
+    pub fn max(self, other: f64) -> Vec3 {
+        Vec3::new(self.x.max(other), self.y.max(other), self.z.max(other))
+    }
+
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
     pub fn ln_1p(self) -> Vec3 {
         Vec3::new(self.x.ln_1p(), self.y.ln_1p(), self.z.ln_1p())
     }
 
+    pub fn ln(self) -> Vec3 {
+        Vec3::new(self.x.ln(), self.y.ln(), self.z.ln())
+    }
+
+    pub fn exp(self) -> Vec3 {
+        Vec3::new(self.x.exp(), self.y.exp(), self.z.exp())
+    }
+
     pub fn cross(self, other: &Vec3) -> Vec3 {
         Vec3::new(
             self.y * other.z - self.z * other.y,